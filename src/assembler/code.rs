@@ -1,3 +1,59 @@
+/// Mnemonics of the `dest` field and their 3 bits.
+pub const DEST: [(&str, u16); 8] = [
+    ("null", 0b000),
+    ("M", 0b001),
+    ("D", 0b010),
+    ("MD", 0b011),
+    ("A", 0b100),
+    ("AM", 0b101),
+    ("AD", 0b110),
+    ("AMD", 0b111),
+];
+
+/// Mnemonics of the `comp` field and their 7 bits, `a` followed by `c1`..`c6`.
+pub const COMP: [(&str, u16); 28] = [
+    ("0", 0b0101010),
+    ("1", 0b0111111),
+    ("-1", 0b0111010),
+    ("D", 0b0001100),
+    ("A", 0b0110000),
+    ("!D", 0b0001101),
+    ("!A", 0b0110001),
+    ("-D", 0b0001111),
+    ("-A", 0b0110011),
+    ("D+1", 0b0011111),
+    ("A+1", 0b0110111),
+    ("D-1", 0b0001110),
+    ("A-1", 0b0110010),
+    ("D+A", 0b0000010),
+    ("D-A", 0b0010011),
+    ("A-D", 0b0000111),
+    ("D&A", 0b0000000),
+    ("D|A", 0b0010101),
+    ("M", 0b1110000),
+    ("!M", 0b1110001),
+    ("-M", 0b1110011),
+    ("M+1", 0b1110111),
+    ("M-1", 0b1110010),
+    ("D+M", 0b1000010),
+    ("D-M", 0b1010011),
+    ("M-D", 0b1000111),
+    ("D&M", 0b1000000),
+    ("D|M", 0b1010101),
+];
+
+/// Mnemonics of the `jump` field and their 3 bits.
+pub const JUMP: [(&str, u16); 8] = [
+    ("null", 0b000),
+    ("JGT", 0b001),
+    ("JEQ", 0b010),
+    ("JGE", 0b011),
+    ("JLT", 0b100),
+    ("JNE", 0b101),
+    ("JLE", 0b110),
+    ("JMP", 0b111),
+];
+
 pub struct Code {
     code: String,
 }
@@ -5,72 +61,118 @@ pub struct Code {
 impl Code {
     pub fn new(code: Option<String>) -> Self {
         match code {
-            Some(c) => Self { code: c },
+            Some(c) => Self {
+                code: c.chars().filter(|c| !c.is_whitespace()).collect(),
+            },
             None => Self {
                 code: "null".to_string(),
             },
         }
     }
 
-    pub fn dest(&self) -> Result<String, String> {
-        match self.code.as_str() {
-            "null" => Ok("000".to_string()),
-            "M" => Ok("001".to_string()),
-            "D" => Ok("010".to_string()),
-            "MD" => Ok("011".to_string()),
-            "A" => Ok("100".to_string()),
-            "AM" => Ok("101".to_string()),
-            "AD" => Ok("110".to_string()),
-            "AMD" => Ok("111".to_string()),
-            _ => Err(format!("mnemonic {} is not allowed in `dest`.", self.code)),
-        }
+    /// Registers may be listed in any order, like `DM` for `MD`.
+    pub fn dest(&self) -> Result<u16, String> {
+        let registers: String = "AMD".chars().filter(|&r| self.code.contains(r)).collect();
+        let is_permutation = registers.len() == self.code.len();
+        let dest = if is_permutation {
+            &registers
+        } else {
+            &self.code
+        };
+        lookup(&DEST, dest)
+            .ok_or_else(|| format!("mnemonic {} is not allowed in `dest`.", self.code))
+    }
+
+    /// The operands of `+`, `&` and `|` may be swapped, like `A+D` for `D+A`.
+    pub fn comp(&self) -> Result<u16, String> {
+        lookup(&COMP, &self.code)
+            .or_else(|| {
+                let index = self.code.get(1..)?.find(|c| "+&|".contains(c))? + 1;
+                let (x, y) = (&self.code[..index], &self.code[index + 1..]);
+                lookup(&COMP, &format!("{}{}{}", y, &self.code[index..=index], x))
+            })
+            .ok_or_else(|| format!("mnemonic {} is not allowed in `comp`.", self.code))
+    }
+
+    pub fn jump(&self) -> Result<u16, String> {
+        lookup(&JUMP, &self.code)
+            .ok_or_else(|| format!("mnemonic {} is not allowed in `jump`.", self.code))
+    }
+}
+
+fn lookup(table: &[(&str, u16)], mnemonic: &str) -> Option<u16> {
+    table
+        .iter()
+        .find(|(m, _)| *m == mnemonic)
+        .map(|&(_, bits)| bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(mnemonic: &str) -> Code {
+        Code::new(Some(mnemonic.to_string()))
     }
 
-    pub fn comp(&self) -> Result<String, String> {
-        match self.code.as_str() {
-            "0" => Ok("0101010".to_string()),
-            "1" => Ok("0111111".to_string()),
-            "-1" => Ok("0111010".to_string()),
-            "D" => Ok("0001100".to_string()),
-            "A" => Ok("0110000".to_string()),
-            "!D" => Ok("0001101".to_string()),
-            "!A" => Ok("0110001".to_string()),
-            "-D" => Ok("0001111".to_string()),
-            "-A" => Ok("0110011".to_string()),
-            "D+1" => Ok("0011111".to_string()),
-            "A+1" => Ok("0110111".to_string()),
-            "D-1" => Ok("0001110".to_string()),
-            "A-1" => Ok("0110010".to_string()),
-            "D+A" => Ok("0000010".to_string()),
-            "D-A" => Ok("0010011".to_string()),
-            "A-D" => Ok("0000111".to_string()),
-            "D&A" => Ok("0000000".to_string()),
-            "D|A" => Ok("0010101".to_string()),
-            "M" => Ok("1110000".to_string()),
-            "!M" => Ok("1110001".to_string()),
-            "-M" => Ok("1110011".to_string()),
-            "M+1" => Ok("1110111".to_string()),
-            "M-1" => Ok("1110010".to_string()),
-            "D+M" => Ok("1000010".to_string()),
-            "D-M" => Ok("1010011".to_string()),
-            "M-D" => Ok("1000111".to_string()),
-            "D&M" => Ok("1000000".to_string()),
-            "D|M" => Ok("1010101".to_string()),
-            _ => Err(format!("mnemonic {} is not allowed in `comp`.", self.code)),
+    #[test]
+    fn commuted_comps() {
+        let spellings = [
+            ("A+D", "D+A"),
+            ("M+D", "D+M"),
+            ("A&D", "D&A"),
+            ("M&D", "D&M"),
+            ("A|D", "D|A"),
+            ("M|D", "D|M"),
+            ("1+D", "D+1"),
+            ("1+M", "M+1"),
+            ("D & M", "D&M"),
+            (" M + D ", "D+M"),
+            ("D - 1", "D-1"),
+            ("- A", "-A"),
+            ("! M", "!M"),
+        ];
+        for &(spelling, canonical) in &spellings {
+            assert_eq!(
+                code(spelling).comp(),
+                code(canonical).comp(),
+                "{}",
+                spelling
+            );
         }
+        // Subtraction does not commute.
+        assert_eq!(code("A-D").comp(), Ok(0b0000111));
+        assert_eq!(
+            code("1-D").comp(),
+            Err("mnemonic 1-D is not allowed in `comp`.".to_string())
+        );
+        assert!(code("A+M").comp().is_err());
+        assert!(code("D+").comp().is_err());
     }
 
-    pub fn jump(&self) -> Result<String, String> {
-        match self.code.as_str() {
-            "null" => Ok("000".to_string()),
-            "JGT" => Ok("001".to_string()),
-            "JEQ" => Ok("010".to_string()),
-            "JGE" => Ok("011".to_string()),
-            "JLT" => Ok("100".to_string()),
-            "JNE" => Ok("101".to_string()),
-            "JLE" => Ok("110".to_string()),
-            "JMP" => Ok("111".to_string()),
-            _ => Err(format!("mnemonic {} is not allowed in `jump`.", self.code)),
+    #[test]
+    fn permuted_dests() {
+        let spellings = [
+            ("DM", "MD"),
+            ("MA", "AM"),
+            ("DA", "AD"),
+            ("DMA", "AMD"),
+            ("MDA", "AMD"),
+            ("A D", "AD"),
+        ];
+        for &(spelling, canonical) in &spellings {
+            assert_eq!(
+                code(spelling).dest(),
+                code(canonical).dest(),
+                "{}",
+                spelling
+            );
         }
+        assert_eq!(Code::new(None).dest(), Ok(0));
+        assert_eq!(
+            code("MM").dest(),
+            Err("mnemonic MM is not allowed in `dest`.".to_string())
+        );
+        assert!(code("X").dest().is_err());
     }
 }
@@ -0,0 +1,198 @@
+use std::fmt;
+use std::io;
+
+use crate::diagnostic::write_diagnostic;
+pub use crate::diagnostic::Location;
+
+#[derive(Debug)]
+pub enum AsmError {
+    UnknownMnemonic {
+        field: &'static str,
+        mnemonic: String,
+        location: Location,
+    },
+    MalformedLabel {
+        label: String,
+        location: Location,
+    },
+    MalformedSymbol {
+        symbol: String,
+        location: Location,
+    },
+    NumericOverflow {
+        value: String,
+        location: Location,
+    },
+    NegativeConstant {
+        value: String,
+        location: Location,
+    },
+    DuplicateLabel {
+        label: String,
+        location: Location,
+        /// Line of the first definition.
+        first_line: usize,
+    },
+    /// A label named like a predefined symbol, such as `R3` or `SCREEN`.
+    ReservedLabel {
+        label: String,
+        location: Location,
+    },
+    /// An instruction past the end of the 32K ROM, the first of them.
+    RomOverflow {
+        /// Words the whole program assembles to.
+        words: usize,
+        location: Location,
+    },
+    /// An `@symbol` to a label or variable whose address does not fit in 15 bits.
+    SymbolOverflow {
+        symbol: String,
+        address: usize,
+        location: Location,
+    },
+    /// A misused directive, macro or pseudo-instruction of the extended syntax.
+    Directive {
+        message: String,
+        text: String,
+        location: Location,
+    },
+    Io {
+        file: String,
+        error: io::Error,
+    },
+}
+
+impl AsmError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            AsmError::UnknownMnemonic { location, .. }
+            | AsmError::MalformedLabel { location, .. }
+            | AsmError::MalformedSymbol { location, .. }
+            | AsmError::NumericOverflow { location, .. }
+            | AsmError::NegativeConstant { location, .. }
+            | AsmError::DuplicateLabel { location, .. }
+            | AsmError::ReservedLabel { location, .. }
+            | AsmError::RomOverflow { location, .. }
+            | AsmError::SymbolOverflow { location, .. }
+            | AsmError::Directive { location, .. } => Some(location),
+            AsmError::Io { .. } => None,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AsmError::UnknownMnemonic {
+                field, mnemonic, ..
+            } => format!("mnemonic `{}` is not allowed in `{}`", mnemonic, field),
+            AsmError::MalformedLabel { label, .. } => format!("malformed label `{}`", label),
+            AsmError::MalformedSymbol { symbol, .. } => format!("malformed symbol `{}`", symbol),
+            AsmError::NumericOverflow { value, .. } => {
+                format!("constant `{}` does not fit in 15 bits (max 32767)", value)
+            }
+            AsmError::NegativeConstant { value, .. } => format!(
+                "negative constant `{}`; load `{}` and negate it with `-A`",
+                value,
+                &value[1..]
+            ),
+            AsmError::DuplicateLabel {
+                label, first_line, ..
+            } => format!(
+                "label `{}` is already defined on line {}",
+                label, first_line
+            ),
+            AsmError::ReservedLabel { label, .. } => {
+                format!("label `{}` shadows the predefined symbol", label)
+            }
+            AsmError::RomOverflow { words, .. } => format!(
+                "the program is {} words long and does not fit in the ROM (max 32768)",
+                words
+            ),
+            AsmError::SymbolOverflow {
+                symbol, address, ..
+            } => format!(
+                "address {} of `{}` does not fit in 15 bits (max 32767)",
+                address, symbol
+            ),
+            AsmError::Directive { message, .. } => message.clone(),
+            AsmError::Io { file, error } => format!("{}: {}", file, error),
+        }
+    }
+
+    /// Length of the text the error points at, used for the `^^^` marker.
+    fn width(&self) -> usize {
+        let len = match self {
+            AsmError::UnknownMnemonic { mnemonic, .. } => mnemonic.len(),
+            AsmError::MalformedLabel { label, .. } => label.len() + 2,
+            AsmError::MalformedSymbol { symbol, .. } => symbol.len() + 1,
+            AsmError::NumericOverflow { value, .. } | AsmError::NegativeConstant { value, .. } => {
+                value.len() + 1
+            }
+            AsmError::DuplicateLabel { label, .. } | AsmError::ReservedLabel { label, .. } => {
+                label.len() + 2
+            }
+            AsmError::RomOverflow { location, .. } => {
+                let code = &location.source[location.column - 1..];
+                code.split("//").next().unwrap().trim_end().len()
+            }
+            AsmError::SymbolOverflow { symbol, .. } => symbol.len() + 1,
+            AsmError::Directive { text, .. } => text.len(),
+            AsmError::Io { .. } => 1,
+        };
+        len.max(1)
+    }
+}
+
+/// Formats the error like a compiler diagnostic:
+///
+/// ```text
+/// error: mnemonic `D+X` is not allowed in `comp`
+///  --> Add.asm:3:3
+///   |
+/// 3 | D=D+X
+///   |   ^^^
+/// ```
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_diagnostic(f, "error", &self.message(), self.location(), self.width())
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Something legal but probably unintended in an `.asm` file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmWarning {
+    /// A variable stored to but never loaded from, at its first store.
+    UnreadVariable {
+        variable: String,
+        location: Location,
+    },
+    /// A label no A-instruction refers to.
+    UnusedLabel { label: String, location: Location },
+}
+
+impl AsmWarning {
+    pub fn location(&self) -> &Location {
+        match self {
+            AsmWarning::UnreadVariable { location, .. }
+            | AsmWarning::UnusedLabel { location, .. } => location,
+        }
+    }
+}
+
+/// Formats the warning like `AsmError`, with `warning:` in place of `error:`.
+impl fmt::Display for AsmWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (message, width) = match self {
+            AsmWarning::UnreadVariable { variable, .. } => (
+                format!("variable `{}` is written but never read", variable),
+                variable.len() + 1,
+            ),
+            AsmWarning::UnusedLabel { label, .. } => (
+                format!("label `{}` is never referred to", label),
+                label.len() + 2,
+            ),
+        };
+        write_diagnostic(f, "warning", &message, Some(self.location()), width)
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::io::prelude::*;
+
+/// Words per Intel HEX data record: 16 bytes, as most tools write them.
+const HEX_RECORD_WORDS: usize = 8;
+
+/// File formats the assembled machine words can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// The `.hack` text of the book: one 16-character binary word per line.
+    Hack,
+    /// Raw 16-bit words, big-endian.
+    Binary,
+    /// Intel HEX, at byte address `2 * ROM address`, big-endian, with extended linear address
+    /// records past 64 KiB.
+    IntelHex,
+    /// One binary word per line, for Verilog's `$readmemb`.
+    ReadMemB,
+    /// One 4-digit hexadecimal word per line, for Verilog's `$readmemh`.
+    ReadMemH,
+}
+
+impl Format {
+    /// Names accepted on the command line.
+    pub const NAMES: [&'static str; 5] = ["hack", "bin", "ihex", "memb", "memh"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hack" => Some(Format::Hack),
+            "bin" => Some(Format::Binary),
+            "ihex" => Some(Format::IntelHex),
+            "memb" => Some(Format::ReadMemB),
+            "memh" => Some(Format::ReadMemH),
+            _ => None,
+        }
+    }
+
+    /// Extension of the output file, when none is given.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Hack => "hack",
+            Format::Binary => "bin",
+            Format::IntelHex => "hex",
+            Format::ReadMemB => "memb",
+            Format::ReadMemH => "memh",
+        }
+    }
+
+    /// Writes `words` in this format.
+    pub fn write<W: Write>(&self, words: &[u16], mut writer: W) -> io::Result<()> {
+        match self {
+            Format::Hack | Format::ReadMemB => {
+                for word in words {
+                    writeln!(writer, "{:016b}", word)?;
+                }
+            }
+            Format::ReadMemH => {
+                for word in words {
+                    writeln!(writer, "{:04x}", word)?;
+                }
+            }
+            Format::Binary => {
+                for word in words {
+                    writer.write_all(&word.to_be_bytes())?;
+                }
+            }
+            Format::IntelHex => {
+                // Records carry the low 16 bits of the byte address; an extended linear address
+                // record sets the high ones whenever they change. Records are 16 bytes long,
+                // so none straddles a 64 KiB boundary.
+                let mut upper = 0;
+                for (i, chunk) in words.chunks(HEX_RECORD_WORDS).enumerate() {
+                    let address = i * HEX_RECORD_WORDS * 2;
+                    if address >> 16 != upper {
+                        upper = address >> 16;
+                        let data = (upper as u16).to_be_bytes();
+                        writeln!(writer, "{}", hex_record(0, 0x04, &data))?;
+                    }
+                    let data: Vec<u8> = chunk.iter().flat_map(|word| word.to_be_bytes()).collect();
+                    writeln!(writer, "{}", hex_record(address as u16, 0x00, &data))?;
+                }
+                writeln!(writer, "{}", hex_record(0, 0x01, &[]))?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// An Intel HEX record: `:`, byte count, address, record type, data and checksum.
+fn hex_record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend(data);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+    let digits: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}", digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `@2`, `D=A`, `@3`.
+    const WORDS: [u16; 3] = [0x0002, 0xec10, 0x0003];
+
+    fn written(format: Format, words: &[u16]) -> Vec<u8> {
+        let mut bytes = vec![];
+        format.write(words, &mut bytes).unwrap();
+        bytes
+    }
+
+    fn text(format: Format, words: &[u16]) -> String {
+        String::from_utf8(written(format, words)).unwrap()
+    }
+
+    #[test]
+    fn hack_and_memb() {
+        let hack = "0000000000000010\n1110110000010000\n0000000000000011\n";
+        assert_eq!(text(Format::Hack, &WORDS), hack);
+        assert_eq!(text(Format::ReadMemB, &WORDS), hack);
+    }
+
+    #[test]
+    fn memh() {
+        assert_eq!(text(Format::ReadMemH, &WORDS), "0002\nec10\n0003\n");
+    }
+
+    #[test]
+    fn binary() {
+        assert_eq!(
+            written(Format::Binary, &WORDS),
+            [0x00, 0x02, 0xec, 0x10, 0x00, 0x03]
+        );
+    }
+
+    #[test]
+    fn intel_hex() {
+        assert_eq!(
+            text(Format::IntelHex, &WORDS),
+            ":060000000002EC100003F9\n:00000001FF\n"
+        );
+        assert_eq!(text(Format::IntelHex, &[]), ":00000001FF\n");
+    }
+
+    #[test]
+    fn intel_hex_past_64_kib() {
+        let mut words = vec![0; 0x8000];
+        words.extend(&WORDS);
+        let hex = text(Format::IntelHex, &words);
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(lines.len(), 0x8000 / HEX_RECORD_WORDS + 3);
+        assert_eq!(lines[0], ":1000000000000000000000000000000000000000F0");
+        assert_eq!(
+            lines[lines.len() - 4..],
+            [
+                ":10FFF0000000000000000000000000000000000001",
+                ":020000040001F9",
+                ":060000000002EC100003F9",
+                ":00000001FF"
+            ]
+        );
+    }
+}
@@ -0,0 +1,29 @@
+/// A command of an `.asm` file, parsed once and then resolved and encoded in separate passes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// `@value`
+    ANumeric(u16),
+    /// `@symbol`, a label or a variable.
+    ASymbol(String),
+    /// `dest=comp;jump`, with its fields as written.
+    C {
+        dest: Option<String>,
+        comp: String,
+        jump: Option<String>,
+    },
+    /// `(label)`, which produces no word.
+    Label(String),
+}
+
+impl Instruction {
+    /// Offsets of `dest`, `comp` and `jump` in the text of a C-instruction.
+    pub fn c_offsets(&self) -> Option<(usize, usize, usize)> {
+        match self {
+            Instruction::C { dest, comp, .. } => {
+                let comp_offset = dest.as_ref().map_or(0, |dest| dest.len() + 1);
+                Some((0, comp_offset, comp_offset + comp.len() + 1))
+            }
+            _ => None,
+        }
+    }
+}
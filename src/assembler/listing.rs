@@ -0,0 +1,181 @@
+use std::fmt::Write;
+
+use crate::assembler::error::AsmError;
+use crate::assembler::parser::{Command, Parser};
+use crate::assembler::writer::{Assembly, Writer};
+
+/// Assembles the text `asm` and lays it out as a listing: every source line with the ROM
+/// address and the word it assembled to, in binary and hex, and the value of the symbol it
+/// refers to, followed by the labels and variables of the program.
+///
+/// `asm_name` is the file name used in diagnostics.
+pub fn listing(asm_name: &str, asm: &str) -> Result<String, Vec<AsmError>> {
+    let assembly = Writer::assemble_program(Parser::from_str(asm_name, asm))?;
+    Ok(render(asm, &assembly))
+}
+
+/// Same as `listing` for the extended syntax of `Preprocessor`. The listing is of the expanded
+/// commands, each with the `file:line` it was written on, as a macro body or an included file
+/// has no source line of its own to be listed next to.
+pub fn listing_extended(asm_name: &str, asm: &str) -> Result<String, Vec<AsmError>> {
+    let parser = Parser::from_str_extended(asm_name, asm)?;
+    let mut commands = vec![];
+    let mut expanded = parser.clone();
+    while expanded.has_more_commands() {
+        expanded.advance();
+        let location = expanded.location(0);
+        let origin = format!("{}:{}", location.file, location.line);
+        let label = matches!(expanded.command_type(), Command::LCommand);
+        commands.push((origin, expanded.code().to_string(), label));
+    }
+    let assembly = Writer::assemble_program(parser)?;
+    Ok(render_expanded(&commands, &assembly))
+}
+
+fn render(asm: &str, assembly: &Assembly) -> String {
+    let mut lst = String::new();
+    writeln!(lst, "  ROM  binary            hex     line  source").unwrap();
+    let mut rom = 0;
+    for (i, source) in asm.lines().enumerate() {
+        let line = i + 1;
+        if assembly.lines.get(rom) != Some(&line) {
+            writeln!(lst, "{:31}{:>6}  {}", "", line, source).unwrap();
+            continue;
+        }
+        // A line holds one command, but keep any other words it produced.
+        let mut first = true;
+        while assembly.lines.get(rom) == Some(&line) {
+            let word = assembly.words[rom];
+            write!(lst, "{:5}  {:016b}  {:04X}  ", rom, word, word).unwrap();
+            if first {
+                write!(lst, "{:>6}  {}", line, source).unwrap();
+                if let Some(symbol) = referenced_symbol(source) {
+                    write!(lst, "    [{} = {}]", symbol, word).unwrap();
+                }
+            }
+            writeln!(lst).unwrap();
+            first = false;
+            rom += 1;
+        }
+    }
+
+    render_symbols(&mut lst, assembly);
+    lst
+}
+
+/// Lays out `commands`, as origin, code and whether it is a label, one per row.
+fn render_expanded(commands: &[(String, String, bool)], assembly: &Assembly) -> String {
+    let width = commands
+        .iter()
+        .map(|(origin, _, _)| origin.len())
+        .max()
+        .unwrap_or(0);
+    let mut lst = String::new();
+    writeln!(
+        lst,
+        "  ROM  binary            hex   {:>w$}  command",
+        "origin",
+        w = width
+    )
+    .unwrap();
+    let mut rom = 0;
+    for (origin, code, label) in commands {
+        if *label {
+            writeln!(lst, "{:31}{:>w$}  {}", "", origin, code, w = width).unwrap();
+            continue;
+        }
+        let word = assembly.words[rom];
+        write!(
+            lst,
+            "{:5}  {:016b}  {:04X}  {:>w$}  {}",
+            rom,
+            word,
+            word,
+            origin,
+            code,
+            w = width
+        )
+        .unwrap();
+        if let Some(symbol) = referenced_symbol(code) {
+            write!(lst, "    [{} = {}]", symbol, word).unwrap();
+        }
+        writeln!(lst).unwrap();
+        rom += 1;
+    }
+    render_symbols(&mut lst, assembly);
+    lst
+}
+
+/// Appends the labels and variables of the program.
+fn render_symbols(lst: &mut String, assembly: &Assembly) {
+    writeln!(lst, "\nLabels").unwrap();
+    for (label, address) in sorted(&assembly.labels) {
+        writeln!(lst, "  {:5}  {}", address, label).unwrap();
+    }
+    writeln!(lst, "\nVariables").unwrap();
+    for (variable, address) in sorted(&assembly.variables) {
+        writeln!(lst, "  {:5}  {}", address, variable).unwrap();
+    }
+}
+
+/// The symbol of an A-instruction like `@LOOP`, if the line holds one.
+fn referenced_symbol(source: &str) -> Option<&str> {
+    let code = match source.find("//") {
+        Some(index) => &source[..index],
+        None => source,
+    };
+    let symbol = code.trim().strip_prefix('@')?;
+    if symbol.is_empty() || symbol.chars().all(|c| c.is_ascii_digit()) {
+        None
+    } else {
+        Some(symbol)
+    }
+}
+
+fn sorted(symbols: &[(String, usize)]) -> Vec<(&str, usize)> {
+    let mut symbols: Vec<_> = symbols
+        .iter()
+        .map(|(symbol, address)| (symbol.as_str(), *address))
+        .collect();
+    symbols.sort_by_key(|&(symbol, address)| (address, symbol));
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden() {
+        let asm = "// Counts n down from 10.
+@10
+D=A
+@n
+M=D  // n = 10
+(LOOP)
+@n
+MD=M-1
+@LOOP
+D;JGT
+";
+        let lst = "  ROM  binary            hex     line  source
+                                    1  // Counts n down from 10.
+    0  0000000000001010  000A       2  @10
+    1  1110110000010000  EC10       3  D=A
+    2  0000000000010000  0010       4  @n    [n = 16]
+    3  1110001100001000  E308       5  M=D  // n = 10
+                                    6  (LOOP)
+    4  0000000000010000  0010       7  @n    [n = 16]
+    5  1111110010011000  FC98       8  MD=M-1
+    6  0000000000000100  0004       9  @LOOP    [LOOP = 4]
+    7  1110001100000001  E301      10  D;JGT
+
+Labels
+      4  LOOP
+
+Variables
+     16  n
+";
+        assert_eq!(listing("Count.asm", asm).unwrap(), lst);
+    }
+}
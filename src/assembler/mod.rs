@@ -2,3 +2,17 @@ pub mod parser;
 pub mod code;
 pub mod writer;
 pub mod symbol_table;
+pub mod error;
+pub mod format;
+pub mod instruction;
+pub mod listing;
+pub mod preprocessor;
+
+use crate::assembler::error::AsmError;
+use crate::assembler::parser::Parser;
+use crate::assembler::writer::Writer;
+
+/// Assembles the text of an `.asm` file into machine words.
+pub fn assemble(asm: &str) -> Result<Vec<u16>, Vec<AsmError>> {
+    Writer::assemble(Parser::from_str("<input>", asm))
+}
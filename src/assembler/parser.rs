@@ -1,44 +1,83 @@
+use std::fs;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 
+use crate::assembler::error::{AsmError, Location};
+use crate::assembler::instruction::Instruction;
+use crate::assembler::preprocessor::Preprocessor;
+
 pub enum Command {
     ACommand,
     CCommand,
     LCommand,
 }
 
+#[derive(Clone)]
 pub struct Parser {
-    asm: Vec<String>,
+    // Name and lines of each file the commands come from; more than one with `.include`.
+    files: Vec<(String, Vec<String>)>,
+    // (index in `files`, index of the line in the file, column where the code starts, code)
+    asm: Vec<(usize, usize, usize, String)>,
     current: usize,
     code: String,
+    // `dest`, `comp` and `jump` of the current command, split once in `advance`.
+    fields: (Option<String>, Option<String>, Option<String>),
 }
 
 impl Parser {
     pub fn new(asm_path: &str) -> io::Result<Parser> {
         let f = File::open(asm_path)?;
-        let reader = io::BufReader::new(f);
-        let asm: Vec<String> = reader
-            .lines()
-            .filter_map(|l| {
-                let mut line = l.unwrap();
-                line = line.trim().to_string();
-                line = match line.find("//") {
-                    Some(index) => (&line[..index]).trim().to_string(),
-                    None => line,
-                };
-                if line.len() > 0 {
-                    Some(line)
-                } else {
-                    None
-                }
-            })
+        Self::from_reader(asm_path, f)
+    }
+
+    /// Reads assembly from `reader`; `asm_name` is the file name used in diagnostics.
+    pub fn from_reader<R: Read>(asm_name: &str, mut reader: R) -> io::Result<Parser> {
+        let mut asm = String::new();
+        reader.read_to_string(&mut asm)?;
+        Ok(Self::from_str(asm_name, &asm))
+    }
+
+    /// Parses the text `asm`; `asm_name` is the file name used in diagnostics.
+    pub fn from_str(asm_name: &str, asm: &str) -> Parser {
+        let source: Vec<String> = asm.lines().map(|l| l.to_string()).collect();
+        let asm = code_lines(&source)
+            .into_iter()
+            .map(|(line, column, code)| (0, line, column, code))
             .collect();
-        Ok(Parser {
-            asm: asm,
+        Self::from_lines(vec![(asm_name.to_string(), source)], asm)
+    }
+
+    /// Reads `asm_path` in the extended syntax of `Preprocessor`, expanding it to plain
+    /// Hack assembly.
+    pub fn new_extended(asm_path: &str) -> Result<Parser, Vec<AsmError>> {
+        let asm = fs::read_to_string(asm_path).map_err(|error| {
+            vec![AsmError::Io {
+                file: asm_path.to_string(),
+                error,
+            }]
+        })?;
+        Self::from_str_extended(asm_path, &asm)
+    }
+
+    /// Same as `new_extended` for the text `asm`. `.include` paths are relative to the
+    /// directory of `asm_name`.
+    pub fn from_str_extended(asm_name: &str, asm: &str) -> Result<Parser, Vec<AsmError>> {
+        let (files, asm) = Preprocessor::expand(asm_name, asm)?;
+        Ok(Self::from_lines(files, asm))
+    }
+
+    fn from_lines(
+        files: Vec<(String, Vec<String>)>,
+        asm: Vec<(usize, usize, usize, String)>,
+    ) -> Parser {
+        Parser {
+            files,
+            asm,
             current: 0,
             code: "".to_string(),
-        })
+            fields: (None, None, None),
+        }
     }
 
     pub fn has_more_commands(&self) -> bool {
@@ -47,14 +86,31 @@ impl Parser {
 
     pub fn advance(&mut self) {
         assert!(self.has_more_commands());
-        self.code = self.asm[self.current].clone();
+        self.code = self.asm[self.current].3.clone();
         self.current += 1;
+        self.fields = self.decompose_c_command();
+    }
+
+    /// Returns the location of the `offset`-th byte of the current command.
+    pub fn location(&self, offset: usize) -> Location {
+        let (file, line, column, _) = self.asm[self.current - 1];
+        let (name, source) = &self.files[file];
+        Location {
+            file: name.clone(),
+            line: line + 1,
+            column: column + offset + 1,
+            source: source[line].clone(),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
     }
 
     pub fn command_type(&self) -> Command {
-        if self.code.starts_with("@") {
+        if self.code.starts_with('@') {
             Command::ACommand
-        } else if self.code.starts_with("(") && self.code.ends_with(")") {
+        } else if self.code.starts_with('(') && self.code.ends_with(')') {
             Command::LCommand
         } else {
             Command::CCommand
@@ -70,15 +126,82 @@ impl Parser {
     }
 
     pub fn dest(&self) -> Option<String> {
-        self.decompose_c_command().0
+        self.fields.0.clone()
     }
 
     pub fn comp(&self) -> Option<String> {
-        self.decompose_c_command().1
+        self.fields.1.clone()
     }
 
     pub fn jump(&self) -> Option<String> {
-        self.decompose_c_command().2
+        self.fields.2.clone()
+    }
+
+    /// The current command as an `Instruction`, or what makes it malformed.
+    pub fn instruction(&self) -> Result<Instruction, AsmError> {
+        match self.command_type() {
+            Command::ACommand => {
+                let symbol = self.symbol().unwrap();
+                if symbol.chars().all(|c| c.is_ascii_digit()) && !symbol.is_empty() {
+                    match symbol.parse::<u16>() {
+                        Ok(value) if value < 1 << 15 => Ok(Instruction::ANumeric(value)),
+                        _ => Err(AsmError::NumericOverflow {
+                            value: symbol,
+                            location: self.location(0),
+                        }),
+                    }
+                } else if symbol.starts_with('-')
+                    && symbol.len() > 1
+                    && symbol[1..].chars().all(|c| c.is_ascii_digit())
+                {
+                    Err(AsmError::NegativeConstant {
+                        value: symbol,
+                        location: self.location(0),
+                    })
+                } else if is_valid_symbol(&symbol) {
+                    Ok(Instruction::ASymbol(symbol))
+                } else {
+                    Err(AsmError::MalformedSymbol {
+                        symbol,
+                        location: self.location(0),
+                    })
+                }
+            }
+            Command::LCommand => {
+                let label = self.symbol().unwrap();
+                if is_valid_symbol(&label) {
+                    Ok(Instruction::Label(label))
+                } else {
+                    Err(AsmError::MalformedLabel {
+                        label,
+                        location: self.location(0),
+                    })
+                }
+            }
+            Command::CCommand if self.code.starts_with('(') => Err(AsmError::MalformedLabel {
+                label: self.code[1..].to_string(),
+                location: self.location(0),
+            }),
+            Command::CCommand => Ok(Instruction::C {
+                dest: self.dest(),
+                comp: self.comp().unwrap_or_default(),
+                jump: self.jump(),
+            }),
+        }
+    }
+
+    /// Parses the remaining commands, collecting the malformed ones as errors.
+    pub fn instructions(&mut self) -> (Vec<(Instruction, Location)>, Vec<AsmError>) {
+        let mut instructions = vec![];
+        let mut errors = vec![];
+        while self.has_more_commands() {
+            self.advance();
+            match self.instruction() {
+                Ok(instruction) => instructions.push((instruction, self.location(0))),
+                Err(error) => errors.push(error),
+            }
+        }
+        (instructions, errors)
     }
 
     fn decompose_c_command(&self) -> (Option<String>, Option<String>, Option<String>) {
@@ -86,14 +209,14 @@ impl Parser {
         let comp: Option<String>;
         let jump: Option<String>;
         let mut tmp_code = self.code.clone();
-        match tmp_code.find("=") {
+        match tmp_code.find('=') {
             Some(equal_index) => {
                 dest = Some(self.code[0..equal_index].to_string());
                 tmp_code = tmp_code[equal_index + 1..].to_string();
             }
             None => dest = None,
         }
-        match tmp_code.find(";") {
+        match tmp_code.find(';') {
             Some(semicolon_index) => {
                 comp = Some(tmp_code[0..semicolon_index].to_string());
                 tmp_code = tmp_code[semicolon_index + 1..].to_string();
@@ -107,3 +230,35 @@ impl Parser {
         (dest, comp, jump)
     }
 }
+
+/// The commands of `source` with comments and blank lines dropped:
+/// (index of the line, column where the code starts, code).
+pub fn code_lines(source: &[String]) -> Vec<(usize, usize, String)> {
+    source
+        .iter()
+        .enumerate()
+        .filter_map(|(i, l)| {
+            let line = match l.find("//") {
+                Some(index) => &l[..index],
+                None => l,
+            };
+            let column = line.len() - line.trim_start().len();
+            let line = line.trim();
+            if !line.is_empty() {
+                Some((i, column, line.to_string()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Symbols consist of letters, digits, `_`, `.`, `$` and `:`, and do not begin with a digit.
+pub fn is_valid_symbol(symbol: &str) -> bool {
+    match symbol.chars().next() {
+        Some(c) if !c.is_ascii_digit() => symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c)),
+        _ => false,
+    }
+}
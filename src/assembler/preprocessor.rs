@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::error::{AsmError, Location};
+use crate::assembler::parser::{code_lines, is_valid_symbol};
+
+/// How deep macros may call macros, and files include files.
+const MAX_DEPTH: usize = 64;
+
+/// Expands the extended assembly syntax into plain Hack assembly:
+///
+/// ```text
+/// .define SIZE 512            // `@SIZE` becomes `@512`
+/// .include "stack.asm"        // relative to the including file
+/// .macro INC addr             // parameters are written `\addr` in the body,
+///     @\addr                  // and `\@` is a number unique to each expansion,
+///     M=M+1                   // for labels like `(LOOP\@)`
+/// .endm
+/// INC counter, ...            // arguments are separated by commas
+/// PUSH D                      // @SP, M=M+1, A=M-1, M=D
+/// POP D                       // @SP, AM=M-1, D=M
+/// JMP label                   // @label, 0;JMP, and `JEQ label` is @label, D;JEQ and so on
+/// ```
+///
+/// Expanded commands keep the location of the line they were written on, or, for macro
+/// bodies, of the line invoking the macro.
+pub struct Preprocessor {
+    files: Vec<(String, Vec<String>)>,
+    asm: Vec<(usize, usize, usize, String)>,
+    defines: HashMap<String, String>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    errors: Vec<AsmError>,
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// Where a command was written: index in `files`, line index and column.
+type Origin = (usize, usize, usize);
+
+/// The files read, as name and lines, and the expanded commands, as `Parser` keeps them.
+type Expansion = (
+    Vec<(String, Vec<String>)>,
+    Vec<(usize, usize, usize, String)>,
+);
+
+impl Preprocessor {
+    /// Expands the text `asm` of `asm_name`.
+    pub fn expand(asm_name: &str, asm: &str) -> Result<Expansion, Vec<AsmError>> {
+        let mut preprocessor = Preprocessor {
+            files: vec![],
+            asm: vec![],
+            defines: HashMap::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            errors: vec![],
+        };
+        preprocessor.file(Path::new(asm_name), asm, &[]);
+        if !preprocessor.errors.is_empty() {
+            return Err(preprocessor.errors);
+        }
+        Ok((preprocessor.files, preprocessor.asm))
+    }
+
+    /// Expands a file; `including` are the files including it, innermost last.
+    fn file(&mut self, path: &Path, asm: &str, including: &[PathBuf]) {
+        let file = self.files.len();
+        let source: Vec<String> = asm.lines().map(|l| l.to_string()).collect();
+        self.files
+            .push((path.to_string_lossy().to_string(), source.clone()));
+        let mut including = including.to_vec();
+        including.push(path.to_path_buf());
+
+        let mut definition: Option<(String, Macro, Origin)> = None;
+        for (line, column, code) in code_lines(&source) {
+            let origin = (file, line, column);
+            let (directive, rest) = match code.find(char::is_whitespace) {
+                Some(index) => (&code[..index], code[index..].trim()),
+                None => (code.as_str(), ""),
+            };
+            if let Some((_, body, _)) = &mut definition {
+                match directive {
+                    ".endm" => {
+                        let (name, body, _) = definition.take().unwrap();
+                        self.macros.insert(name, body);
+                    }
+                    _ if directive.starts_with('.') => {
+                        self.error(origin, &code, "directives are not allowed in macros");
+                    }
+                    _ => body.body.push(code.clone()),
+                }
+                continue;
+            }
+            match directive {
+                ".define" => match rest.find(char::is_whitespace) {
+                    Some(index) if is_valid_symbol(&rest[..index]) => {
+                        let value = rest[index..].trim().to_string();
+                        self.defines.insert(rest[..index].to_string(), value);
+                    }
+                    _ => self.error(origin, &code, "expected `.define NAME value`"),
+                },
+                ".macro" => {
+                    let mut words = rest
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|w| !w.is_empty());
+                    let name = words.next().unwrap_or_default().to_string();
+                    let params: Vec<String> = words.map(|w| w.to_string()).collect();
+                    if !is_valid_symbol(&name) || !params.iter().all(|p| is_valid_symbol(p)) {
+                        self.error(origin, &code, "expected `.macro NAME param, ...`");
+                    }
+                    let body = Macro {
+                        params,
+                        body: vec![],
+                    };
+                    definition = Some((name, body, origin));
+                }
+                ".endm" => self.error(origin, &code, "`.endm` without `.macro`"),
+                ".include" => self.include(origin, &code, rest, &including),
+                _ if directive.starts_with('.') => {
+                    self.error(origin, &code, &format!("unknown directive `{}`", directive))
+                }
+                _ => self.statement(origin, &code, 0),
+            }
+        }
+        if let Some((name, _, origin)) = definition {
+            let code = format!(".macro {}", name);
+            self.error(origin, &code, "`.macro` without `.endm`");
+        }
+    }
+
+    fn include(&mut self, origin: Origin, code: &str, rest: &str, including: &[PathBuf]) {
+        let name = match rest.strip_prefix('"').and_then(|r| r.strip_suffix('"')) {
+            Some(name) => name,
+            None => return self.error(origin, code, "expected `.include \"file.asm\"`"),
+        };
+        let dir = including.last().and_then(|path| path.parent()).unwrap();
+        let path = dir.join(name);
+        if including.contains(&path) {
+            return self.error(origin, code, &format!("`{}` includes itself", name));
+        }
+        if including.len() >= MAX_DEPTH {
+            return self.error(origin, code, "files are included too deeply");
+        }
+        match fs::read_to_string(&path) {
+            Ok(asm) => self.file(&path, &asm, including),
+            Err(e) => self.error(origin, code, &format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Expands a command: a macro invocation, a pseudo-instruction or a Hack command.
+    fn statement(&mut self, origin: Origin, code: &str, depth: usize) {
+        if depth > MAX_DEPTH {
+            return self.error(origin, code, "macros are nested too deeply");
+        }
+        let (name, rest) = match code.find(char::is_whitespace) {
+            Some(index) => (&code[..index], code[index..].trim()),
+            None => (code, ""),
+        };
+        let expansion = if let Some(m) = self.macros.get(name).cloned() {
+            let args: Vec<&str> = match rest {
+                "" => vec![],
+                _ => rest.split(',').map(|a| a.trim()).collect(),
+            };
+            if args.len() != m.params.len() {
+                let message = format!(
+                    "macro `{}` takes {} argument(s) but {} were given",
+                    name,
+                    m.params.len(),
+                    args.len()
+                );
+                return self.error(origin, code, &message);
+            }
+            self.expansions += 1;
+            let mut lines = vec![];
+            for line in &m.body {
+                match substitute(line, &m.params, &args, self.expansions) {
+                    Ok(line) => lines.push(line),
+                    Err(message) => return self.error(origin, code, &message),
+                }
+            }
+            lines
+        } else {
+            match (name, rest) {
+                ("PUSH", "D") => vec!["@SP", "M=M+1", "A=M-1", "M=D"]
+                    .into_iter()
+                    .map(|l| l.to_string())
+                    .collect(),
+                ("POP", "D") => vec!["@SP", "AM=M-1", "D=M"]
+                    .into_iter()
+                    .map(|l| l.to_string())
+                    .collect(),
+                ("PUSH", _) | ("POP", _) => {
+                    return self.error(origin, code, &format!("`{}` only takes `D`", name))
+                }
+                ("JMP", label) if !label.is_empty() => vec![format!("@{}", label), "0;JMP".into()],
+                ("JGT", label)
+                | ("JEQ", label)
+                | ("JGE", label)
+                | ("JLT", label)
+                | ("JNE", label)
+                | ("JLE", label)
+                    if !label.is_empty() =>
+                {
+                    vec![format!("@{}", label), format!("D;{}", name)]
+                }
+                _ => {
+                    let code = match code.strip_prefix('@').and_then(|s| self.defines.get(s)) {
+                        Some(value) => format!("@{}", value),
+                        None => code.to_string(),
+                    };
+                    let (file, line, column) = origin;
+                    self.asm.push((file, line, column, code));
+                    return;
+                }
+            }
+        };
+        for line in expansion {
+            self.statement(origin, &line, depth + 1);
+        }
+    }
+
+    fn error(&mut self, origin: Origin, code: &str, message: &str) {
+        let (file, line, column) = origin;
+        self.errors.push(AsmError::Directive {
+            message: message.to_string(),
+            text: code.to_string(),
+            location: Location {
+                file: self.files[file].0.clone(),
+                line: line + 1,
+                column: column + 1,
+                source: self.files[file].1[line].clone(),
+            },
+        });
+    }
+}
+
+/// Replaces `\param` in `line` with its argument and `\@` with `expansion`.
+fn substitute(
+    line: &str,
+    params: &[String],
+    args: &[&str],
+    expansion: usize,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(index) = rest.find('\\') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        if let Some(r) = rest.strip_prefix('@') {
+            result.push_str(&expansion.to_string());
+            rest = r;
+            continue;
+        }
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_.$:".contains(c)))
+            .unwrap_or(rest.len());
+        match params.iter().position(|p| *p == rest[..end]) {
+            Some(i) => result.push_str(args[i]),
+            None => return Err(format!("unknown macro parameter `\\{}`", &rest[..end])),
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The commands `asm` expands to, one per line.
+    fn expand(asm_name: &str, asm: &str) -> String {
+        let (_, commands) = Preprocessor::expand(asm_name, asm).unwrap();
+        let lines: Vec<String> = commands.into_iter().map(|(.., code)| code + "\n").collect();
+        lines.concat()
+    }
+
+    /// The message and line of each error expanding `asm`.
+    fn errors(asm: &str) -> Vec<(String, usize)> {
+        match Preprocessor::expand("Test.asm", asm) {
+            Ok(_) => vec![],
+            Err(errors) => errors
+                .into_iter()
+                .map(|error| match error {
+                    AsmError::Directive {
+                        message, location, ..
+                    } => (message, location.line),
+                    error => panic!("{:?}", error),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn macros_with_parameters() {
+        let asm = ".macro ADD_TO dest, src
+    @\\src
+    D=M
+    @\\dest
+    M=D+M
+.endm
+.macro COUNT_DOWN counter
+(LOOP\\@)
+    @\\counter
+    MD=M-1
+    @LOOP\\@
+    D;JGT
+.endm
+ADD_TO sum, i
+COUNT_DOWN i
+COUNT_DOWN j
+";
+        let hack = "@i
+D=M
+@sum
+M=D+M
+(LOOP2)
+@i
+MD=M-1
+@LOOP2
+D;JGT
+(LOOP3)
+@j
+MD=M-1
+@LOOP3
+D;JGT
+";
+        assert_eq!(expand("Test.asm", asm), hack);
+    }
+
+    #[test]
+    fn defines() {
+        let asm = ".define SIZE 512
+.define BASE SCREEN
+@SIZE
+D=A
+@BASE
+M=D
+@SIZES
+";
+        assert_eq!(expand("Test.asm", asm), "@512\nD=A\n@SCREEN\nM=D\n@SIZES\n");
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let asm = "PUSH D
+POP D
+JMP END
+JEQ END
+JLE END
+(END)
+";
+        let hack = "@SP
+M=M+1
+A=M-1
+M=D
+@SP
+AM=M-1
+D=M
+@END
+0;JMP
+@END
+D;JEQ
+@END
+D;JLE
+(END)
+";
+        assert_eq!(expand("Test.asm", asm), hack);
+        assert_eq!(
+            errors("PUSH A\nPOP\n"),
+            vec![
+                ("`PUSH` only takes `D`".to_string(), 1),
+                ("`POP` only takes `D`".to_string(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("nand2tetris-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stack = ".macro PUSH_CONSTANT value
+    @\\value
+    D=A
+    PUSH D
+.endm
+";
+        fs::write(dir.join("stack.asm"), stack).unwrap();
+        let asm = ".include \"stack.asm\"\nPUSH_CONSTANT 7\n";
+        let asm_name = dir.join("Main.asm").to_string_lossy().to_string();
+        let hack = "@7\nD=A\n@SP\nM=M+1\nA=M-1\nM=D\n";
+        assert_eq!(expand(&asm_name, asm), hack);
+        assert_eq!(
+            errors(".include stack.asm\n"),
+            vec![("expected `.include \"file.asm\"`".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn misused_macros() {
+        let asm = ".macro INC addr
+    @\\addr
+    M=M+1
+.endm
+INC
+INC a, b
+.endm
+.macro OPEN
+";
+        let expected = vec![
+            (
+                "macro `INC` takes 1 argument(s) but 0 were given".to_string(),
+                5,
+            ),
+            (
+                "macro `INC` takes 1 argument(s) but 2 were given".to_string(),
+                6,
+            ),
+            ("`.endm` without `.macro`".to_string(), 7),
+            ("`.macro` without `.endm`".to_string(), 8),
+        ];
+        assert_eq!(errors(asm), expected);
+    }
+}
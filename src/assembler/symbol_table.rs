@@ -1,11 +1,17 @@
 use std::collections::HashMap;
 
-use crate::assembler::parser::{Command, Parser};
+use crate::assembler::instruction::Instruction;
 
 pub struct SymbolTable {
     table: HashMap<String, usize>,
 }
 
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         let mut table = HashMap::new();
@@ -32,7 +38,7 @@ impl SymbolTable {
         table.insert("R15".to_string(), 15);
         table.insert("SCREEN".to_string(), 16384);
         table.insert("KBD".to_string(), 24576);
-        Self { table: table }
+        Self { table }
     }
 
     pub fn add_entry(&mut self, symbol: String, address: usize) {
@@ -48,25 +54,15 @@ impl SymbolTable {
     }
 }
 
-pub fn make_symbol_table(parser: &mut Parser) -> SymbolTable {
+/// Creates the symbol table of the predefined symbols and the labels of `instructions`.
+pub fn make_symbol_table(instructions: &[Instruction]) -> SymbolTable {
     let mut symbol_table = SymbolTable::new();
     let mut i = 0;
-    loop {
-        if !parser.has_more_commands() {
-            break;
+    for instruction in instructions {
+        match instruction {
+            Instruction::Label(label) => symbol_table.add_entry(label.clone(), i),
+            _ => i += 1,
         }
-        parser.advance();
-        match parser.command_type() {
-            Command::LCommand => {
-                let symbol = parser.symbol().unwrap();
-                if !symbol.chars().nth(0).unwrap().is_digit(10) {
-                    symbol_table.add_entry(symbol, i);
-                }
-            }
-            _ => {
-                i += 1;
-            }
-        };
     }
     symbol_table
 }
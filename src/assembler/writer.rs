@@ -1,57 +1,423 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 
 use crate::assembler::code::Code;
-use crate::assembler::parser::{Command, Parser};
-use crate::assembler::symbol_table::make_symbol_table;
+use crate::assembler::error::{AsmError, AsmWarning, Location};
+use crate::assembler::format::Format;
+use crate::assembler::instruction::Instruction;
+use crate::assembler::parser::Parser;
+use crate::assembler::symbol_table::{make_symbol_table, SymbolTable};
+
+/// Words the ROM of the Hack computer holds.
+const ROM_SIZE: usize = 32768;
+
+/// Largest address an A-instruction can load.
+const MAX_ADDRESS: usize = 32767;
 
 pub struct Writer {}
 
+/// An assembled program and what it was assembled from.
+pub struct Assembly {
+    pub words: Vec<u16>,
+    /// 1-based `.asm` line of each word.
+    pub lines: Vec<usize>,
+    /// Labels and their ROM addresses, in order of definition.
+    pub labels: Vec<(String, usize)>,
+    /// Variables and their RAM addresses, in order of allocation.
+    pub variables: Vec<(String, usize)>,
+    pub warnings: Vec<AsmWarning>,
+}
+
 impl Writer {
-    pub fn write(asm_path: &str, hack_path: &str) {
-        let mut parser = Parser::new(asm_path).unwrap();
-        let mut symbol_table = make_symbol_table(&mut parser);
-
-        let mut parser = Parser::new(asm_path).unwrap();
-        let f = File::create(hack_path).unwrap();
-        let mut writer = io::BufWriter::new(f);
-        let mut n_var = 0;
-        loop {
-            if !parser.has_more_commands() {
-                break;
-            }
-            parser.advance();
-            let byte = match parser.command_type() {
-                Command::CCommand => Some(format!(
-                    "111{}{}{}\n",
-                    Code::new(parser.comp()).comp().unwrap(),
-                    Code::new(parser.dest()).dest().unwrap(),
-                    Code::new(parser.jump()).jump().unwrap()
-                )),
-                Command::ACommand => {
-                    let symbol = parser.symbol().unwrap();
-                    if symbol.chars().nth(0).unwrap().is_digit(10) {
-                        Some(format!("{:0>16b}\n", symbol.parse::<i64>().unwrap()))
-                    } else {
-                        let address = if symbol_table.contains(&symbol) {
-                            symbol_table.get_address(&symbol)
-                        } else {
-                            symbol_table.add_entry(symbol.clone(), n_var + 16);
-                            n_var += 1;
-                            symbol_table.get_address(&symbol)
-                        };
-                        Some(format!("{:0>16b}\n", address))
+    /// Assembles `asm_path` into `hack_path`, returning the warnings about it.
+    ///
+    /// Every problem found in the file is returned at once; the output file is only
+    /// written when there are none.
+    pub fn write(asm_path: &str, hack_path: &str) -> Result<Vec<AsmWarning>, Vec<AsmError>> {
+        Self::write_as(asm_path, hack_path, Format::Hack)
+    }
+
+    /// Same as `write`, writing the words to `out_path` in `format`.
+    pub fn write_as(
+        asm_path: &str,
+        out_path: &str,
+        format: Format,
+    ) -> Result<Vec<AsmWarning>, Vec<AsmError>> {
+        let parser = Parser::new(asm_path).map_err(|error| {
+            vec![AsmError::Io {
+                file: asm_path.to_string(),
+                error,
+            }]
+        })?;
+        let assembly = Self::assemble_program(parser)?;
+        let f = File::create(out_path).map_err(|error| {
+            vec![AsmError::Io {
+                file: out_path.to_string(),
+                error,
+            }]
+        })?;
+        format
+            .write(&assembly.words, io::BufWriter::new(f))
+            .map_err(|error| {
+                vec![AsmError::Io {
+                    file: out_path.to_string(),
+                    error,
+                }]
+            })?;
+        Ok(assembly.warnings)
+    }
+
+    /// Assembles the text read from `reader`; `asm_name` is the file name used in diagnostics.
+    pub fn assemble_reader<R: Read>(asm_name: &str, reader: R) -> Result<Vec<u16>, Vec<AsmError>> {
+        let parser = Parser::from_reader(asm_name, reader).map_err(|error| {
+            vec![AsmError::Io {
+                file: asm_name.to_string(),
+                error,
+            }]
+        })?;
+        Self::assemble(parser)
+    }
+
+    /// Writes `words` in the textual `.hack` format, one 16-character binary word per line.
+    pub fn write_hack<W: Write>(words: &[u16], writer: W) -> io::Result<()> {
+        Format::Hack.write(words, writer)
+    }
+
+    /// Assembles all commands of `parser` into machine words.
+    pub fn assemble(parser: Parser) -> Result<Vec<u16>, Vec<AsmError>> {
+        Self::assemble_with_lines(parser).map(|(words, _)| words)
+    }
+
+    /// Same as `assemble`, also returning the 1-based `.asm` line each word came from.
+    pub fn assemble_with_lines(parser: Parser) -> Result<(Vec<u16>, Vec<usize>), Vec<AsmError>> {
+        Self::assemble_program(parser).map(|assembly| (assembly.words, assembly.lines))
+    }
+
+    /// Same as `assemble`, also returning the lines and the symbols of the program.
+    ///
+    /// The commands are parsed once into `Instruction`s; labels, then variables, are resolved
+    /// in a pass over them, and words are encoded in another.
+    pub fn assemble_program(mut parser: Parser) -> Result<Assembly, Vec<AsmError>> {
+        let (instructions, mut errors) = parser.instructions();
+        let (instructions, locations): (Vec<_>, Vec<_>) = instructions.into_iter().unzip();
+
+        errors.extend(Self::check_labels(&instructions, &locations));
+
+        let mut symbol_table = make_symbol_table(&instructions);
+        let labels = instructions
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::Label(label) => Some((label.clone(), symbol_table.get_address(label))),
+                _ => None,
+            })
+            .collect();
+        let mut variables = vec![];
+        for instruction in &instructions {
+            if let Instruction::ASymbol(symbol) = instruction {
+                if !symbol_table.contains(symbol) {
+                    let address = 16 + variables.len();
+                    symbol_table.add_entry(symbol.clone(), address);
+                    variables.push((symbol.clone(), address));
+                }
+            }
+        }
+
+        let size = instructions
+            .iter()
+            .filter(|instruction| !matches!(instruction, Instruction::Label(_)))
+            .count();
+        let mut words = vec![];
+        let mut lines = vec![];
+        for (instruction, location) in instructions.iter().zip(&locations) {
+            if let Instruction::ASymbol(symbol) = instruction {
+                let address = symbol_table.get_address(symbol);
+                if address > MAX_ADDRESS {
+                    errors.push(AsmError::SymbolOverflow {
+                        symbol: symbol.clone(),
+                        address,
+                        location: location.clone(),
+                    });
+                }
+            }
+            match Self::encode(instruction, &symbol_table) {
+                Ok(Some(word)) => {
+                    if words.len() == ROM_SIZE {
+                        errors.push(AsmError::RomOverflow {
+                            words: size,
+                            location: location.clone(),
+                        });
                     }
+                    words.push(word);
+                    lines.push(location.line);
+                }
+                Ok(None) => {}
+                Err(fields) => {
+                    let offsets = instruction.c_offsets().unwrap();
+                    errors.extend(fields.into_iter().map(|(field, mnemonic)| {
+                        let offset = match field {
+                            "dest" => offsets.0,
+                            "comp" => offsets.1,
+                            _ => offsets.2,
+                        };
+                        AsmError::UnknownMnemonic {
+                            field,
+                            mnemonic,
+                            location: Location {
+                                column: location.column + offset,
+                                ..location.clone()
+                            },
+                        }
+                    }));
                 }
-                Command::LCommand => None,
+            }
+        }
+        if !errors.is_empty() {
+            errors.sort_by_key(|error| error.location().map(|location| location.line));
+            return Err(errors);
+        }
+        let warnings = Self::lint(&instructions, &locations, &variables);
+        Ok(Assembly {
+            words,
+            lines,
+            labels,
+            variables,
+            warnings,
+        })
+    }
+
+    /// Finds labels defined twice or named like predefined symbols.
+    fn check_labels(instructions: &[Instruction], locations: &[Location]) -> Vec<AsmError> {
+        let predefined = SymbolTable::new();
+        let mut first_lines = HashMap::new();
+        let mut errors = vec![];
+        for (instruction, location) in instructions.iter().zip(locations) {
+            if let Instruction::Label(label) = instruction {
+                if predefined.contains(label) {
+                    errors.push(AsmError::ReservedLabel {
+                        label: label.clone(),
+                        location: location.clone(),
+                    });
+                } else if let Some(&first_line) = first_lines.get(label) {
+                    errors.push(AsmError::DuplicateLabel {
+                        label: label.clone(),
+                        location: location.clone(),
+                        first_line,
+                    });
+                } else {
+                    first_lines.insert(label, location.line);
+                }
+            }
+        }
+        errors
+    }
+
+    /// Warns about variables that are stored to but never loaded from and labels nothing
+    /// refers to.
+    ///
+    /// A variable is read when the instruction after one of its `@variable` uses `M` or `A` in
+    /// `comp`, the latter because its address may be taken, and written when it has `M` in
+    /// `dest`.
+    fn lint(
+        instructions: &[Instruction],
+        locations: &[Location],
+        variables: &[(String, usize)],
+    ) -> Vec<AsmWarning> {
+        let mut referred = HashSet::new();
+        let mut read = HashSet::new();
+        let mut first_writes = HashMap::new();
+        for (i, instruction) in instructions.iter().enumerate() {
+            let symbol = match instruction {
+                Instruction::ASymbol(symbol) => symbol.as_str(),
+                _ => continue,
             };
-            match byte {
-                Some(byte) => {
-                    writer.write(byte.as_bytes()).unwrap();
+            referred.insert(symbol);
+            if let Some(Instruction::C { dest, comp, .. }) = instructions.get(i + 1) {
+                if comp.contains('M') || comp.contains('A') {
+                    read.insert(symbol);
+                }
+                if dest.as_ref().is_some_and(|dest| dest.contains('M')) {
+                    first_writes.entry(symbol).or_insert(i);
+                }
+            }
+        }
+
+        let mut warnings = vec![];
+        for (variable, _) in variables {
+            match first_writes.get(variable.as_str()) {
+                Some(&i) if !read.contains(variable.as_str()) => {
+                    warnings.push(AsmWarning::UnreadVariable {
+                        variable: variable.clone(),
+                        location: locations[i].clone(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        for (instruction, location) in instructions.iter().zip(locations) {
+            match instruction {
+                Instruction::Label(label) if !referred.contains(label.as_str()) => {
+                    warnings.push(AsmWarning::UnusedLabel {
+                        label: label.clone(),
+                        location: location.clone(),
+                    })
                 }
-                None => {}
+                _ => {}
             }
         }
+        warnings.sort_by_key(|warning| warning.location().line);
+        warnings
+    }
+
+    /// Encodes `instruction`, which produces no word if it is a label. A C-instruction with
+    /// unknown mnemonics gives the fields they are in, in `dest`, `comp`, `jump` order.
+    fn encode(
+        instruction: &Instruction,
+        symbol_table: &SymbolTable,
+    ) -> Result<Option<u16>, Vec<(&'static str, String)>> {
+        match instruction {
+            Instruction::ANumeric(value) => Ok(Some(*value)),
+            Instruction::ASymbol(symbol) => Ok(Some(symbol_table.get_address(symbol) as u16)),
+            Instruction::Label(_) => Ok(None),
+            Instruction::C { dest, comp, jump } => {
+                let dest_bits = Code::new(dest.clone()).dest();
+                let comp_bits = Code::new(Some(comp.clone())).comp();
+                let jump_bits = Code::new(jump.clone()).jump();
+                match (dest_bits, comp_bits, jump_bits) {
+                    (Ok(dest), Ok(comp), Ok(jump)) => {
+                        Ok(Some(0b111 << 13 | comp << 6 | dest << 3 | jump))
+                    }
+                    (dest_bits, comp_bits, jump_bits) => {
+                        let mut unknown = vec![];
+                        if dest_bits.is_err() {
+                            unknown.push(("dest", dest.clone().unwrap_or_default()));
+                        }
+                        if comp_bits.is_err() {
+                            unknown.push(("comp", comp.clone()));
+                        }
+                        if jump_bits.is_err() {
+                            unknown.push(("jump", jump.clone().unwrap_or_default()));
+                        }
+                        Err(unknown)
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assembly_errors(asm: &str) -> Vec<AsmError> {
+        match Writer::assemble_program(Parser::from_str("Test.asm", asm)) {
+            Ok(_) => vec![],
+            Err(errors) => errors,
+        }
+    }
+
+    fn warnings(asm: &str) -> Vec<AsmWarning> {
+        match Writer::assemble_program(Parser::from_str("Test.asm", asm)) {
+            Ok(assembly) => assembly.warnings,
+            Err(errors) => panic!("{:?}", errors),
+        }
+    }
+
+    fn location(line: usize, column: usize, source: &str) -> Location {
+        Location {
+            file: "Test.asm".to_string(),
+            line,
+            column,
+            source: source.to_string(),
+        }
+    }
+
+    #[test]
+    fn constants_out_of_range() {
+        let errors = assembly_errors("@32767\n@32768\n@40000\n@-1\n");
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(
+            &errors[0],
+            AsmError::NumericOverflow { value, location } if value == "32768" && location.line == 2
+        ));
+        assert!(matches!(
+            &errors[1],
+            AsmError::NumericOverflow { value, location } if value == "40000" && location.line == 3
+        ));
+        assert!(matches!(
+            &errors[2],
+            AsmError::NegativeConstant { value, location } if value == "-1" && location.line == 4
+        ));
+    }
+
+    #[test]
+    fn bad_labels() {
+        let errors = assembly_errors("(LOOP)\n@LOOP\n0;JMP\n(LOOP)\n(R3)\n(SCREEN)\n");
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(
+            &errors[0],
+            AsmError::DuplicateLabel { label, location, first_line: 1 }
+                if label == "LOOP" && location.line == 4
+        ));
+        assert!(matches!(
+            &errors[1],
+            AsmError::ReservedLabel { label, location } if label == "R3" && location.line == 5
+        ));
+        assert!(matches!(
+            &errors[2],
+            AsmError::ReservedLabel { label, location } if label == "SCREEN" && location.line == 6
+        ));
+    }
+
+    #[test]
+    fn program_past_the_rom() {
+        // The label lands at 32768, past what an A-instruction can load.
+        let asm = "D=0\n".repeat(ROM_SIZE) + "(END)\n@END\n0;JMP\n";
+        let errors = assembly_errors(&asm);
+        assert_eq!(errors.len(), 2);
+        let line = ROM_SIZE + 2;
+        assert!(matches!(
+            &errors[0],
+            AsmError::SymbolOverflow { symbol, address: ROM_SIZE, location }
+                if symbol == "END" && location.line == line
+        ));
+        assert!(matches!(
+            &errors[1],
+            AsmError::RomOverflow { words, location }
+                if *words == ROM_SIZE + 2 && location.line == line
+        ));
+        assert!(assembly_errors("D=0\n".repeat(ROM_SIZE).as_str()).is_empty());
+    }
+
+    #[test]
+    fn unread_variables_and_unused_labels() {
+        let asm = "@count
+M=1
+@count
+M=M+1
+@sum
+M=0
+@sum
+M=D
+@address
+D=A
+(UNUSED)
+(LOOP)
+@LOOP
+0;JMP
+";
+        let expected = vec![
+            AsmWarning::UnreadVariable {
+                variable: "sum".to_string(),
+                location: location(5, 1, "@sum"),
+            },
+            AsmWarning::UnusedLabel {
+                label: "UNUSED".to_string(),
+                location: location(11, 1, "(UNUSED)"),
+            },
+        ];
+        assert_eq!(warnings(asm), expected);
     }
 }
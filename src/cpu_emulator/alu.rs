@@ -0,0 +1,77 @@
+/// Output of the ALU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AluOutput {
+    pub out: u16,
+    /// `out == 0`
+    pub zr: bool,
+    /// `out < 0`
+    pub ng: bool,
+}
+
+/// Computes the ALU function selected by `control`, which holds the six control bits
+/// `zx nx zy ny f no` from the most significant (bit 5) to the least (bit 0),
+/// in the same order as bits 11..6 of a C-instruction.
+///
+/// This follows `projects/02/ALU.hdl` step by step.
+pub fn compute(x: u16, y: u16, control: u16) -> AluOutput {
+    let bit = |i: u16| control & (1 << i) != 0;
+    let (zx, nx, zy, ny, f, no) = (bit(5), bit(4), bit(3), bit(2), bit(1), bit(0));
+
+    let x = if zx { 0 } else { x };
+    let x = if nx { !x } else { x };
+    let y = if zy { 0 } else { y };
+    let y = if ny { !y } else { y };
+    let out = if f { x.wrapping_add(y) } else { x & y };
+    let out = if no { !out } else { out };
+    AluOutput {
+        out,
+        zr: out == 0,
+        ng: out & 0x8000 != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn functions() {
+        // zx nx zy ny f no, and out for x = 17, y = 3, as in `ALU.cmp`.
+        let rows = [
+            (0b101010, 0),
+            (0b111111, 1),
+            (0b111010, -1),
+            (0b001100, 17),
+            (0b110000, 3),
+            (0b001101, !17),
+            (0b110001, !3),
+            (0b001111, -17),
+            (0b110011, -3),
+            (0b011111, 18),
+            (0b110111, 4),
+            (0b001110, 16),
+            (0b110010, 2),
+            (0b000010, 20),
+            (0b010011, 14),
+            (0b000111, -14),
+            (0b000000, 1),
+            (0b010101, 19),
+        ];
+        for &(control, out) in &rows {
+            let expected = AluOutput {
+                out: out as u16,
+                zr: out == 0,
+                ng: out < 0,
+            };
+            assert_eq!(compute(17, 3, control), expected, "{:06b}", control);
+        }
+    }
+
+    #[test]
+    fn wraps_around() {
+        let output = compute(0x7fff, 1, 0b000010);
+        assert_eq!((output.out, output.zr, output.ng), (0x8000, false, true));
+        let output = compute(0xffff, 1, 0b000010);
+        assert_eq!((output.out, output.zr, output.ng), (0, true, false));
+    }
+}
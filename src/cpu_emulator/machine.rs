@@ -0,0 +1,301 @@
+use std::fs;
+
+use crate::cpu_emulator::alu;
+
+pub const ROM_SIZE: usize = 32768;
+pub const RAM_SIZE: usize = 32768;
+
+/// The Hack computer: 32K words of ROM and RAM, and the A, D and PC registers.
+pub struct Machine {
+    rom: Vec<u16>,
+    ram: Vec<u16>,
+    a: u16,
+    d: u16,
+    pc: u16,
+    cycles: u64,
+}
+
+impl Machine {
+    /// Creates a machine whose ROM holds `program` from address 0.
+    pub fn new(program: Vec<u16>) -> Result<Self, String> {
+        if program.len() > ROM_SIZE {
+            return Err(format!(
+                "program has {} instructions, but ROM holds only {}.",
+                program.len(),
+                ROM_SIZE
+            ));
+        }
+        let mut rom = program;
+        rom.resize(ROM_SIZE, 0);
+        Ok(Self {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+        })
+    }
+
+    /// Creates a machine from the text of a `.hack` file.
+    pub fn from_hack(hack: &str) -> Result<Self, String> {
+        Self::new(parse_hack(hack)?)
+    }
+
+    /// Creates a machine from a `.hack` file.
+    pub fn load(hack_path: &str) -> Result<Self, String> {
+        let hack = fs::read_to_string(hack_path).map_err(|e| format!("{}: {}", hack_path, e))?;
+        Self::from_hack(&hack).map_err(|e| format!("{}: {}", hack_path, e))
+    }
+
+    /// Executes the instruction at PC.
+    pub fn step(&mut self) -> Result<(), String> {
+        let instruction = self.rom[self.pc as usize];
+        self.cycles += 1;
+        if instruction & 0x8000 == 0 {
+            // A-instruction
+            self.a = instruction;
+            self.pc = self.next_pc();
+            return Ok(());
+        }
+
+        // C-instruction: 111a cccc ccdd djjj
+        let y = if instruction & 0x1000 != 0 {
+            self.read_m()?
+        } else {
+            self.a
+        };
+        let alu::AluOutput { out, zr, ng } = alu::compute(self.d, y, (instruction >> 6) & 0x3f);
+        let address = self.a;
+        if instruction & 0x0008 != 0 {
+            self.write_m(out)?;
+        }
+        if instruction & 0x0010 != 0 {
+            self.d = out;
+        }
+        if instruction & 0x0020 != 0 {
+            self.a = out;
+        }
+        let jump = (instruction & 0x0004 != 0 && ng)
+            || (instruction & 0x0002 != 0 && zr)
+            || (instruction & 0x0001 != 0 && !ng && !zr);
+        self.pc = if jump {
+            address & 0x7fff
+        } else {
+            self.next_pc()
+        };
+        Ok(())
+    }
+
+    /// Executes `n` instructions, stopping at the first error.
+    pub fn run(&mut self, n: usize) -> Result<(), String> {
+        for _ in 0..n {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Sets PC to 0 without touching memory, as the `reset` input of `Computer.hdl` does.
+    pub fn reset(&mut self) {
+        self.pc = 0;
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn set_a(&mut self, a: u16) {
+        self.a = a;
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn set_d(&mut self, d: u16) {
+        self.d = d;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc & 0x7fff;
+    }
+
+    /// Number of instructions executed so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn next_pc(&self) -> u16 {
+        (self.pc + 1) & 0x7fff
+    }
+
+    fn read_m(&self) -> Result<u16, String> {
+        match self.ram.get(self.a as usize) {
+            Some(m) => Ok(*m),
+            None => Err(format!(
+                "RAM address {} is out of range at PC {}.",
+                self.a, self.pc
+            )),
+        }
+    }
+
+    fn write_m(&mut self, value: u16) -> Result<(), String> {
+        let (a, pc) = (self.a, self.pc);
+        match self.ram.get_mut(a as usize) {
+            Some(m) => {
+                *m = value;
+                Ok(())
+            }
+            None => Err(format!("RAM address {} is out of range at PC {}.", a, pc)),
+        }
+    }
+}
+
+/// Parses the text of a `.hack` file: one 16-character binary word per line.
+pub fn parse_hack(hack: &str) -> Result<Vec<u16>, String> {
+    hack.lines()
+        .enumerate()
+        .map(|(i, l)| (i, l.trim()))
+        .filter(|(_, l)| !l.is_empty())
+        .map(|(i, l)| {
+            if l.len() != 16 || !l.chars().all(|c| c == '0' || c == '1') {
+                return Err(format!("line {}: `{}` is not a 16-bit word.", i + 1, l));
+            }
+            Ok(u16::from_str_radix(l, 2).unwrap())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::Parser;
+    use crate::assembler::writer::Writer;
+
+    /// Multiplies R0 and R1 into R2, as in project 04.
+    const MULT: &str = "@i
+M=0
+@R2
+M=0
+(LOOP)
+@i
+D=M
+@R1
+D=D-M
+@END
+D;JGE
+@R0
+D=M
+@R2
+M=D+M
+@i
+M=M+1
+@LOOP
+0;JMP
+(END)
+@END
+0;JMP
+";
+
+    fn machine(asm: &str) -> Machine {
+        Machine::new(Writer::assemble(Parser::from_str("Test.asm", asm)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn m_is_written_at_the_old_a() {
+        let mut machine = machine("@5\nD=A\n@100\nAM=D\n@200\nM=1\nAM=M+1\n");
+        machine.run(7).unwrap();
+        assert_eq!((machine.ram()[100], machine.ram()[5]), (5, 0));
+        // M is read and written at 200, then A takes the sum.
+        assert_eq!((machine.ram()[200], machine.a()), (2, 2));
+    }
+
+    #[test]
+    fn jump_conditions() {
+        // Whether each jump is taken with D negative, zero and positive.
+        let rows = [
+            ("JGT", [false, false, true]),
+            ("JEQ", [false, true, false]),
+            ("JGE", [false, true, true]),
+            ("JLT", [true, false, false]),
+            ("JNE", [true, false, true]),
+            ("JLE", [true, true, false]),
+            ("JMP", [true, true, true]),
+        ];
+        for &(jump, taken) in &rows {
+            for (&d, &taken) in [0xffff, 0, 1].iter().zip(&taken) {
+                let mut machine = machine(&format!("@42\nD;{}\n", jump));
+                machine.set_d(d);
+                machine.run(2).unwrap();
+                let pc = if taken { 42 } else { 2 };
+                assert_eq!(machine.pc(), pc, "D={} {}", d as i16, jump);
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_to_the_old_a() {
+        let mut machine = machine("@42\nD=A\n@7\nA=D;JMP\n");
+        machine.run(4).unwrap();
+        assert_eq!((machine.pc(), machine.a()), (7, 42));
+    }
+
+    #[test]
+    fn load_hack() {
+        let hack = "0000000000000010\n\n  1110110000010000  \n0000000000000000\n1110001100001000\n";
+        let mut machine = Machine::from_hack(hack).unwrap();
+        assert_eq!(machine.rom()[..4], [2, 0xec10, 0, 0xe308]);
+        machine.run(4).unwrap();
+        assert_eq!((machine.ram()[0], machine.d(), machine.pc()), (2, 2, 4));
+        assert_eq!(
+            Machine::from_hack("0000000000000010\n0101\n").err(),
+            Some("line 2: `0101` is not a 16-bit word.".to_string())
+        );
+    }
+
+    #[test]
+    fn mult() {
+        for &(r0, r1) in &[(0, 0), (1, 0), (0, 2), (3, 1), (2, 4), (6, 7)] {
+            let mut machine = machine(MULT);
+            machine.ram_mut()[0] = r0;
+            machine.ram_mut()[1] = r1;
+            machine.run(200).unwrap();
+            assert_eq!(machine.ram()[2], r0 * r1, "{} * {}", r0, r1);
+        }
+    }
+
+    #[test]
+    fn max() {
+        for &(r0, r1) in &[
+            (0, 0),
+            (1, 0),
+            (0, 2),
+            (3, 1),
+            (12345, 23456),
+            (23456, 12345),
+        ] {
+            let mut machine =
+                Machine::from_hack(include_str!("../../projects/06/max/Max.hack")).unwrap();
+            machine.ram_mut()[0] = r0;
+            machine.ram_mut()[1] = r1;
+            machine.run(50).unwrap();
+            assert_eq!(machine.ram()[2], r0.max(r1), "max({}, {})", r0, r1);
+        }
+    }
+}
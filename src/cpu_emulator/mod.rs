@@ -0,0 +1,3 @@
+pub mod alu;
+pub mod machine;
+pub mod screen;
@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cpu_emulator::machine::Machine;
+
+/// RAM address of the screen memory map.
+pub const SCREEN: usize = 16384;
+/// RAM address of the keyboard register.
+pub const KBD: usize = 24576;
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+/// Instructions per frame when recording: the Hack platform has no clock rate, so this is
+/// about what a Jack program like Pong executes between two moves of the ball.
+pub const CYCLES_PER_FRAME: u64 = 1 << 16;
+
+/// Image file formats for screen snapshots.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    /// Binary portable bitmap (`P4`).
+    Pbm,
+    /// 1-bit grayscale PNG.
+    Png,
+}
+
+impl ImageFormat {
+    /// The format of `path`, from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "pbm" => Some(ImageFormat::Pbm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    /// Renders the screen memory map of `ram`.
+    pub fn render(&self, ram: &[u16]) -> Vec<u8> {
+        match self {
+            ImageFormat::Pbm => to_pbm(ram),
+            ImageFormat::Png => to_png(ram),
+        }
+    }
+}
+
+/// Rows of the screen, one bit per pixel, the leftmost pixel in the most significant bit
+/// of the first byte and 1 for black.
+///
+/// In the Hack memory map, the leftmost pixel of a word is its least significant bit.
+fn rows(ram: &[u16]) -> Vec<Vec<u8>> {
+    ram[SCREEN..SCREEN + WIDTH * HEIGHT / 16]
+        .chunks(WIDTH / 16)
+        .map(|row| {
+            row.iter()
+                .flat_map(|word| word.reverse_bits().to_be_bytes())
+                .collect()
+        })
+        .collect()
+}
+
+/// Renders the screen as a binary PBM image.
+pub fn to_pbm(ram: &[u16]) -> Vec<u8> {
+    let mut pbm = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for row in rows(ram) {
+        pbm.extend(row);
+    }
+    pbm
+}
+
+/// Renders the screen as a PNG image, compressed with stored (uncompressed) deflate blocks,
+/// which every decoder reads and which need no compression library.
+pub fn to_png(ram: &[u16]) -> Vec<u8> {
+    // In grayscale PNG, 0 is black.
+    let mut raw = vec![];
+    for row in rows(ram) {
+        raw.push(0); // filter type: none
+        raw.extend(row.iter().map(|byte| !byte));
+    }
+
+    let mut ihdr = vec![];
+    ihdr.extend(&(WIDTH as u32).to_be_bytes());
+    ihdr.extend(&(HEIGHT as u32).to_be_bytes());
+    // Bit depth 1, grayscale, deflate, no filtering, no interlace.
+    ihdr.extend(&[1, 0, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut png, b"IHDR", &ihdr);
+    png_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn png_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(chunk_type);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(&crc.to_be_bytes());
+}
+
+/// A zlib stream holding `data` in stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, no preset dictionary, fastest compression.
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xffff).collect();
+    for (i, block) in blocks.iter().enumerate() {
+        let is_final = i == blocks.len() - 1;
+        zlib.push(is_final as u8);
+        let len = block.len() as u16;
+        zlib.extend(&len.to_le_bytes());
+        zlib.extend(&(!len).to_le_bytes());
+        zlib.extend(*block);
+    }
+    if blocks.is_empty() {
+        zlib.extend(&[1, 0, 0, 0xff, 0xff]);
+    }
+    zlib.extend(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+/// Writes the screen of `ram` to `path`, as PNG or PBM depending on its extension.
+pub fn write_image(ram: &[u16], path: &Path) -> Result<(), String> {
+    let format = ImageFormat::from_path(path)
+        .ok_or_else(|| format!("{}: expected a .png or .pbm file.", path.display()))?;
+    fs::write(path, format.render(ram)).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Runs a machine, writing a snapshot of the screen every few frames.
+pub struct Recorder {
+    path: PathBuf,
+    every: u64,
+    cycles_per_frame: u64,
+}
+
+impl Recorder {
+    /// Records every `every` frames into files named after `path`: `screen.png` gives
+    /// `screen-0001.png`, `screen-0002.png` and so on.
+    pub fn new(path: &Path, every: u64) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            every: every.max(1),
+            cycles_per_frame: CYCLES_PER_FRAME,
+        }
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u64) {
+        self.cycles_per_frame = cycles_per_frame.max(1);
+    }
+
+    /// Runs `machine` for `cycles` instructions, returning the snapshots written.
+    pub fn run(&self, machine: &mut Machine, cycles: u64) -> Result<Vec<PathBuf>, String> {
+        let interval = self.every * self.cycles_per_frame;
+        let mut paths = vec![];
+        let mut remaining = cycles;
+        while remaining > 0 {
+            let n = remaining.min(interval);
+            machine.run(n as usize)?;
+            remaining -= n;
+            if n == interval {
+                let path = self.frame_path(paths.len() + 1);
+                write_image(machine.ram(), &path)?;
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    fn frame_path(&self, n: usize) -> PathBuf {
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match self.path.extension() {
+            Some(extension) => format!("{}-{:04}.{}", stem, n, extension.to_string_lossy()),
+            None => format!("{}-{:04}", stem, n),
+        };
+        self.path.with_file_name(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_emulator::machine::RAM_SIZE;
+
+    #[test]
+    fn pbm() {
+        let mut ram = vec![0; RAM_SIZE];
+        ram[SCREEN] = 1;
+        ram[SCREEN + 1] = 0x8000;
+        ram[KBD - 1] = 1;
+        let pbm = to_pbm(&ram);
+        let header = b"P4\n512 256\n";
+        assert_eq!(&pbm[..header.len()], header);
+        let bits = &pbm[header.len()..];
+        assert_eq!(bits.len(), WIDTH * HEIGHT / 8);
+        // The leftmost pixel of a word is its least significant bit, and 1 is black.
+        assert_eq!(&bits[..4], &[0x80, 0x00, 0x00, 0x01]);
+        assert_eq!(&bits[bits.len() - 2..], &[0x80, 0x00]);
+        assert_eq!(bits.iter().map(|byte| byte.count_ones()).sum::<u32>(), 3);
+    }
+
+    #[test]
+    fn recorder() {
+        let dir = std::env::temp_dir().join(format!("nand2tetris-screen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // (LOOP) @LOOP 0;JMP
+        let mut machine = Machine::new(vec![0, 0xea87]).unwrap();
+        let mut recorder = Recorder::new(&dir.join("screen.png"), 2);
+        recorder.set_cycles_per_frame(10);
+        let paths = recorder.run(&mut machine, 45).unwrap();
+        assert_eq!(
+            paths,
+            vec![dir.join("screen-0001.png"), dir.join("screen-0002.png")]
+        );
+        assert_eq!(machine.cycles(), 45);
+        for path in &paths {
+            assert_eq!(&fs::read(path).unwrap()[..8], b"\x89PNG\r\n\x1a\n");
+        }
+        assert!(!dir.join("screen-0003.png").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
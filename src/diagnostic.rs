@@ -0,0 +1,69 @@
+use std::fmt;
+
+/// Position of a problem in a source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number.
+    pub column: usize,
+    /// Raw text of the offending line.
+    pub source: String,
+}
+
+impl Location {
+    /// Location of `line`:`column` in `source`, the text of `file`.
+    pub fn new(file: &str, source: &str, line: usize, column: usize) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            column,
+            source: source.lines().nth(line - 1).unwrap_or("").to_string(),
+        }
+    }
+}
+
+/// Writes `message` like a compiler diagnostic, pointing `width` carets at `location`:
+///
+/// ```text
+/// error: mnemonic `D+X` is not allowed in `comp`
+///  --> Add.asm:3:3
+///   |
+/// 3 | D=D+X
+///   |   ^^^
+/// ```
+pub fn write_diagnostic(
+    f: &mut fmt::Formatter,
+    severity: &str,
+    message: &str,
+    location: Option<&Location>,
+    width: usize,
+) -> fmt::Result {
+    write!(f, "{}: {}", severity, message)?;
+    if let Some(location) = location {
+        let gutter = location.line.to_string().len();
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>w$}--> {}:{}:{}",
+            "",
+            location.file,
+            location.line,
+            location.column,
+            w = gutter
+        )?;
+        writeln!(f, "{:>w$} |", "", w = gutter)?;
+        writeln!(f, "{} | {}", location.line, location.source)?;
+        write!(
+            f,
+            "{:>w$} | {:>c$}{}",
+            "",
+            "",
+            "^".repeat(width),
+            w = gutter,
+            c = location.column - 1
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::assembler::code::{COMP, DEST, JUMP};
+
+/// A machine word decoded back into assembly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    A(u16),
+    C {
+        dest: &'static str,
+        comp: &'static str,
+        jump: &'static str,
+    },
+}
+
+impl Instruction {
+    /// Decodes `word`, or says why it is not a valid instruction.
+    pub fn decode(word: u16) -> Result<Self, String> {
+        if word & 0x8000 == 0 {
+            return Ok(Instruction::A(word));
+        }
+        if word & 0x6000 != 0x6000 {
+            return Err("bits 13 and 14 of a C-instruction must be set.".to_string());
+        }
+        let comp = match mnemonic(&COMP, (word >> 6) & 0x7f) {
+            Some(comp) => comp,
+            None => {
+                return Err(format!(
+                    "comp bits {:07b} do not name a computation.",
+                    (word >> 6) & 0x7f
+                ))
+            }
+        };
+        Ok(Instruction::C {
+            dest: mnemonic(&DEST, (word >> 3) & 0x7).unwrap(),
+            comp,
+            jump: mnemonic(&JUMP, word & 0x7).unwrap(),
+        })
+    }
+
+    /// Decodes `word` as the CPU executes it: bits 13 and 14 of a C-instruction are ignored.
+    /// `None` if the comp bits do not name a computation.
+    pub fn decode_as_executed(word: u16) -> Option<Self> {
+        match word & 0x8000 {
+            0 => Self::decode(word).ok(),
+            _ => Self::decode(word | 0x6000).ok(),
+        }
+    }
+
+    /// Whether this is a C-instruction that may jump.
+    pub fn is_jump(&self) -> bool {
+        matches!(self, Instruction::C { jump, .. } if *jump != "null")
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::A(value) => write!(f, "@{}", value),
+            Instruction::C { dest, comp, jump } => {
+                if *dest != "null" {
+                    write!(f, "{}=", dest)?;
+                }
+                write!(f, "{}", comp)?;
+                if *jump != "null" {
+                    write!(f, ";{}", jump)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A word that does not decode, at ROM address `rom`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidWord {
+    pub rom: usize,
+    pub word: u16,
+    pub message: String,
+}
+
+impl fmt::Display for InvalidWord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ROM[{}]: {:016b}: {}", self.rom, self.word, self.message)
+    }
+}
+
+/// Renders `words` as assembly, one instruction per line.
+///
+/// With `labels`, every `@value` directly followed by a jump gets a label `(L<value>)` in place
+/// of its value, placed before the instruction at that address, so that the control flow reads
+/// like source. Other A-instructions keep their numbers: they may as well be data.
+///
+/// Invalid words are listed in the second value. Each still takes its ROM slot, so that the
+/// output reassembles to the same addresses: it is written as the CPU executes it, ignoring
+/// bits 13 and 14, or as `0`, doing nothing, if its comp bits name no computation, followed by
+/// a comment holding its bits.
+pub fn disassemble(words: &[u16], labels: bool) -> (String, Vec<InvalidWord>) {
+    let decoded: Vec<_> = words
+        .iter()
+        .map(|&word| Instruction::decode_as_executed(word))
+        .collect();
+    let is_target = |i: usize| match (&decoded[i], decoded.get(i + 1)) {
+        (Some(Instruction::A(value)), Some(Some(next))) => {
+            labels && next.is_jump() && *value as usize <= words.len()
+        }
+        _ => false,
+    };
+    let targets: BTreeSet<usize> = (0..words.len())
+        .filter(|&i| is_target(i))
+        .map(|i| words[i] as usize)
+        .collect();
+
+    let mut asm = String::new();
+    let mut invalid = vec![];
+    for (rom, (&word, instruction)) in words.iter().zip(&decoded).enumerate() {
+        if targets.contains(&rom) {
+            asm.push_str(&format!("(L{})\n", rom));
+        }
+        match instruction {
+            Some(Instruction::A(value)) if is_target(rom) => {
+                asm.push_str(&format!("@L{}", value));
+            }
+            Some(instruction) => asm.push_str(&instruction.to_string()),
+            None => asm.push('0'),
+        }
+        if let Err(message) = Instruction::decode(word) {
+            let invalid_word = InvalidWord { rom, word, message };
+            asm.push_str(&format!(" // invalid: {}", invalid_word));
+            invalid.push(invalid_word);
+        }
+        asm.push('\n');
+    }
+    if targets.contains(&words.len()) {
+        asm.push_str(&format!("(L{})\n", words.len()));
+    }
+    (asm, invalid)
+}
+
+fn mnemonic(table: &[(&'static str, u16)], bits: u16) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|&&(_, b)| b == bits)
+        .map(|&(mnemonic, _)| mnemonic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::Parser;
+    use crate::assembler::writer::Writer;
+
+    fn reassemble(words: &[u16], labels: bool) -> Vec<u16> {
+        let (asm, _) = disassemble(words, labels);
+        Writer::assemble(Parser::from_str("<disassembled>", &asm)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let words: Vec<u16> = include_str!("../projects/06/pong/Pong.hack")
+            .lines()
+            .map(|line| u16::from_str_radix(line, 2).unwrap())
+            .collect();
+        for &labels in &[false, true] {
+            assert_eq!(reassemble(&words, labels), words, "labels: {}", labels);
+        }
+    }
+
+    #[test]
+    fn invalid_words_keep_their_slot() {
+        // @5, 0;JMP, a C-instruction without bits 13 and 14, one with unknown comp bits, @0,
+        // 0;JMP
+        let words = [5, 0xea87, 0xa000, 0xffc0, 0, 0xea87];
+        let (asm, invalid) = disassemble(&words, true);
+        assert_eq!(
+            invalid.iter().map(|word| word.rom).collect::<Vec<_>>(),
+            [2, 3]
+        );
+        assert!(asm.contains("\nD&A // invalid: ROM[2]: 1010000000000000: "));
+        assert!(asm.contains("\n0 // invalid: ROM[3]: 1111111111000000: "));
+        assert!(asm.ends_with("@L0\n(L5)\n0;JMP\n"));
+        for &labels in &[false, true] {
+            assert_eq!(
+                reassemble(&words, labels),
+                [5, 0xea87, 0xe000, 0xea80, 0, 0xea87]
+            );
+        }
+    }
+}
@@ -0,0 +1,556 @@
+use crate::cpu_emulator::alu;
+
+/// A chip of the standard library, simulated natively instead of from its parts.
+///
+/// Pin values are passed in the order of `Spec::inputs` and `Spec::outputs`.
+pub trait Builtin {
+    /// Sets `outputs` from `inputs` and the state shown by the chip.
+    fn eval(&self, inputs: &[u16], outputs: &mut [u16]);
+
+    /// The rising edge of the clock: takes in the inputs, without showing them yet.
+    fn tick(&mut self, _inputs: &[u16]) {}
+
+    /// The falling edge of the clock: shows what was taken in at `tick`.
+    fn tock(&mut self) {}
+
+    /// The register of the chip with no `index`, or a word of its memory, as `.tst` scripts
+    /// read them with `PC[]` or `RAM16K[3]`.
+    fn get(&self, _index: Option<usize>) -> Option<u16> {
+        None
+    }
+
+    /// Sets what `get` reads; false if there is no such register or word.
+    fn set(&mut self, _index: Option<usize>, _value: u16) -> bool {
+        false
+    }
+
+    /// The word of its memory the last `tock` wrote, as an index and a value.
+    fn written(&self) -> Option<(usize, u16)> {
+        None
+    }
+}
+
+/// Interface and implementation of a builtin chip.
+#[derive(Clone, Copy)]
+pub struct Spec {
+    pub name: &'static str,
+    pub inputs: &'static [(&'static str, usize)],
+    pub outputs: &'static [(&'static str, usize)],
+    /// Inputs only taken in at `tick`: the outputs do not follow them until `tock`.
+    pub clocked: &'static [&'static str],
+    implementation: Implementation,
+}
+
+#[derive(Clone, Copy)]
+enum Implementation {
+    /// Outputs computed from the inputs alone.
+    Logic(fn(&[u16], &mut [u16])),
+    /// A chip with state, made anew for every part.
+    Stateful(fn() -> Box<dyn Builtin>),
+}
+
+impl Spec {
+    pub fn instantiate(&self) -> Box<dyn Builtin> {
+        match self.implementation {
+            Implementation::Logic(function) => Box::new(Logic(function)),
+            Implementation::Stateful(new) => new(),
+        }
+    }
+}
+
+/// The builtin chip `name`, if there is one.
+pub fn spec(name: &str) -> Option<Spec> {
+    SPECS.iter().find(|spec| spec.name == name).copied()
+}
+
+/// Names of the builtin chips.
+pub fn names() -> Vec<&'static str> {
+    SPECS.iter().map(|spec| spec.name).collect()
+}
+
+struct Logic(fn(&[u16], &mut [u16]));
+
+impl Builtin for Logic {
+    fn eval(&self, inputs: &[u16], outputs: &mut [u16]) {
+        (self.0)(inputs, outputs)
+    }
+}
+
+/// Bit, Register, ARegister and DRegister: `out` is `in` as of the last clock cycle with
+/// `load` set.
+#[derive(Default)]
+struct Register {
+    state: u16,
+    next: Option<u16>,
+}
+
+impl Builtin for Register {
+    fn eval(&self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.state;
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.next = if inputs[1] == 1 {
+            Some(inputs[0])
+        } else {
+            None
+        };
+    }
+
+    fn tock(&mut self) {
+        if let Some(next) = self.next.take() {
+            self.state = next;
+        }
+    }
+
+    fn get(&self, index: Option<usize>) -> Option<u16> {
+        index.map_or(Some(self.state), |_| None)
+    }
+
+    fn set(&mut self, index: Option<usize>, value: u16) -> bool {
+        if index.is_none() {
+            self.state = value;
+        }
+        index.is_none()
+    }
+}
+
+/// The program counter: `reset` takes precedence over `load`, and `load` over `inc`.
+#[derive(Default)]
+struct Counter {
+    state: u16,
+    next: Option<u16>,
+}
+
+impl Builtin for Counter {
+    fn eval(&self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.state;
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        let (input, load, inc, reset) = (inputs[0], inputs[1], inputs[2], inputs[3]);
+        self.next = if reset == 1 {
+            Some(0)
+        } else if load == 1 {
+            Some(input)
+        } else if inc == 1 {
+            Some(self.state.wrapping_add(1))
+        } else {
+            None
+        };
+    }
+
+    fn tock(&mut self) {
+        if let Some(next) = self.next.take() {
+            self.state = next;
+        }
+    }
+
+    fn get(&self, index: Option<usize>) -> Option<u16> {
+        index.map_or(Some(self.state), |_| None)
+    }
+
+    fn set(&mut self, index: Option<usize>, value: u16) -> bool {
+        if index.is_none() {
+            self.state = value;
+        }
+        index.is_none()
+    }
+}
+
+/// The RAM chips and Screen: `out` is the word at `address`, written with `in` at the end
+/// of a clock cycle with `load` set.
+struct Memory {
+    words: Vec<u16>,
+    write: Option<(usize, u16)>,
+    written: Option<(usize, u16)>,
+}
+
+impl Memory {
+    fn boxed(size: usize) -> Box<dyn Builtin> {
+        Box::new(Memory {
+            words: vec![0; size],
+            write: None,
+            written: None,
+        })
+    }
+}
+
+impl Builtin for Memory {
+    fn eval(&self, inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.words[inputs[2] as usize];
+    }
+
+    fn tick(&mut self, inputs: &[u16]) {
+        self.write = if inputs[1] == 1 {
+            Some((inputs[2] as usize, inputs[0]))
+        } else {
+            None
+        };
+    }
+
+    fn tock(&mut self) {
+        self.written = self.write.take();
+        if let Some((address, value)) = self.written {
+            self.words[address] = value;
+        }
+    }
+
+    fn get(&self, index: Option<usize>) -> Option<u16> {
+        self.words.get(index?).copied()
+    }
+
+    fn set(&mut self, index: Option<usize>, value: u16) -> bool {
+        match index.and_then(|index| self.words.get_mut(index)) {
+            Some(word) => {
+                *word = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn written(&self) -> Option<(usize, u16)> {
+        self.written
+    }
+}
+
+/// ROM32K: `out` is the word at `address`, set from outside the chip only.
+struct Rom {
+    words: Vec<u16>,
+}
+
+impl Builtin for Rom {
+    fn eval(&self, inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.words[inputs[0] as usize];
+    }
+
+    fn get(&self, index: Option<usize>) -> Option<u16> {
+        self.words.get(index?).copied()
+    }
+
+    fn set(&mut self, index: Option<usize>, value: u16) -> bool {
+        match index.and_then(|index| self.words.get_mut(index)) {
+            Some(word) => {
+                *word = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keyboard: `out` is the code of the key held down, set from outside the chip.
+#[derive(Default)]
+struct Keyboard {
+    key: u16,
+}
+
+impl Builtin for Keyboard {
+    fn eval(&self, _inputs: &[u16], outputs: &mut [u16]) {
+        outputs[0] = self.key;
+    }
+
+    fn get(&self, index: Option<usize>) -> Option<u16> {
+        index.map_or(Some(self.key), |_| None)
+    }
+
+    fn set(&mut self, index: Option<usize>, value: u16) -> bool {
+        if index.is_none() {
+            self.key = value;
+        }
+        index.is_none()
+    }
+}
+
+const REGISTER_INPUTS: &[(&str, usize)] = &[("in", 16), ("load", 1)];
+const WORD: &[(&str, usize)] = &[("out", 16)];
+const BIT: &[(&str, usize)] = &[("out", 1)];
+const IN_LOAD: &[&str] = &["in", "load"];
+
+const SPECS: &[Spec] = &[
+    Spec {
+        name: "Not",
+        inputs: &[("in", 1)],
+        outputs: BIT,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = !i[0]),
+    },
+    Spec {
+        name: "And",
+        inputs: &[("a", 1), ("b", 1)],
+        outputs: BIT,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0] & i[1]),
+    },
+    Spec {
+        name: "Or",
+        inputs: &[("a", 1), ("b", 1)],
+        outputs: BIT,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0] | i[1]),
+    },
+    Spec {
+        name: "Xor",
+        inputs: &[("a", 1), ("b", 1)],
+        outputs: BIT,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0] ^ i[1]),
+    },
+    Spec {
+        name: "Mux",
+        inputs: &[("a", 1), ("b", 1), ("sel", 1)],
+        outputs: BIT,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[i[2] as usize]),
+    },
+    Spec {
+        name: "DMux",
+        inputs: &[("in", 1), ("sel", 1)],
+        outputs: &[("a", 1), ("b", 1)],
+        clocked: &[],
+        implementation: Implementation::Logic(demux),
+    },
+    Spec {
+        name: "Not16",
+        inputs: &[("in", 16)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = !i[0]),
+    },
+    Spec {
+        name: "And16",
+        inputs: &[("a", 16), ("b", 16)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0] & i[1]),
+    },
+    Spec {
+        name: "Or16",
+        inputs: &[("a", 16), ("b", 16)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0] | i[1]),
+    },
+    Spec {
+        name: "Mux16",
+        inputs: &[("a", 16), ("b", 16), ("sel", 1)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[i[2] as usize]),
+    },
+    Spec {
+        name: "Or8Way",
+        inputs: &[("in", 8)],
+        outputs: BIT,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = (i[0] != 0) as u16),
+    },
+    Spec {
+        name: "Mux4Way16",
+        inputs: &[("a", 16), ("b", 16), ("c", 16), ("d", 16), ("sel", 2)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[i[4] as usize]),
+    },
+    Spec {
+        name: "Mux8Way16",
+        inputs: &[
+            ("a", 16),
+            ("b", 16),
+            ("c", 16),
+            ("d", 16),
+            ("e", 16),
+            ("f", 16),
+            ("g", 16),
+            ("h", 16),
+            ("sel", 3),
+        ],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[i[8] as usize]),
+    },
+    Spec {
+        name: "DMux4Way",
+        inputs: &[("in", 1), ("sel", 2)],
+        outputs: &[("a", 1), ("b", 1), ("c", 1), ("d", 1)],
+        clocked: &[],
+        implementation: Implementation::Logic(demux),
+    },
+    Spec {
+        name: "DMux8Way",
+        inputs: &[("in", 1), ("sel", 3)],
+        outputs: &[
+            ("a", 1),
+            ("b", 1),
+            ("c", 1),
+            ("d", 1),
+            ("e", 1),
+            ("f", 1),
+            ("g", 1),
+            ("h", 1),
+        ],
+        clocked: &[],
+        implementation: Implementation::Logic(demux),
+    },
+    Spec {
+        name: "HalfAdder",
+        inputs: &[("a", 1), ("b", 1)],
+        outputs: &[("sum", 1), ("carry", 1)],
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| {
+            o[0] = i[0] ^ i[1];
+            o[1] = i[0] & i[1];
+        }),
+    },
+    Spec {
+        name: "FullAdder",
+        inputs: &[("a", 1), ("b", 1), ("c", 1)],
+        outputs: &[("sum", 1), ("carry", 1)],
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| {
+            let sum = i[0] + i[1] + i[2];
+            o[0] = sum & 1;
+            o[1] = sum >> 1;
+        }),
+    },
+    Spec {
+        name: "Add16",
+        inputs: &[("a", 16), ("b", 16)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0].wrapping_add(i[1])),
+    },
+    Spec {
+        name: "Inc16",
+        inputs: &[("in", 16)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| o[0] = i[0].wrapping_add(1)),
+    },
+    Spec {
+        name: "ALU",
+        inputs: &[
+            ("x", 16),
+            ("y", 16),
+            ("zx", 1),
+            ("nx", 1),
+            ("zy", 1),
+            ("ny", 1),
+            ("f", 1),
+            ("no", 1),
+        ],
+        outputs: &[("out", 16), ("zr", 1), ("ng", 1)],
+        clocked: &[],
+        implementation: Implementation::Logic(|i, o| {
+            let control = i[2..].iter().fold(0, |control, bit| control << 1 | bit);
+            let output = alu::compute(i[0], i[1], control);
+            o[0] = output.out;
+            o[1] = output.zr as u16;
+            o[2] = output.ng as u16;
+        }),
+    },
+    Spec {
+        name: "Bit",
+        inputs: &[("in", 1), ("load", 1)],
+        outputs: BIT,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Box::new(Register::default())),
+    },
+    Spec {
+        name: "Register",
+        inputs: REGISTER_INPUTS,
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Box::new(Register::default())),
+    },
+    Spec {
+        name: "ARegister",
+        inputs: REGISTER_INPUTS,
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Box::new(Register::default())),
+    },
+    Spec {
+        name: "DRegister",
+        inputs: REGISTER_INPUTS,
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Box::new(Register::default())),
+    },
+    Spec {
+        name: "PC",
+        inputs: &[("in", 16), ("load", 1), ("inc", 1), ("reset", 1)],
+        outputs: WORD,
+        clocked: &["in", "load", "inc", "reset"],
+        implementation: Implementation::Stateful(|| Box::new(Counter::default())),
+    },
+    Spec {
+        name: "RAM8",
+        inputs: &[("in", 16), ("load", 1), ("address", 3)],
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Memory::boxed(8)),
+    },
+    Spec {
+        name: "RAM64",
+        inputs: &[("in", 16), ("load", 1), ("address", 6)],
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Memory::boxed(64)),
+    },
+    Spec {
+        name: "RAM512",
+        inputs: &[("in", 16), ("load", 1), ("address", 9)],
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Memory::boxed(512)),
+    },
+    Spec {
+        name: "RAM4K",
+        inputs: &[("in", 16), ("load", 1), ("address", 12)],
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Memory::boxed(4096)),
+    },
+    Spec {
+        name: "RAM16K",
+        inputs: &[("in", 16), ("load", 1), ("address", 14)],
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Memory::boxed(16384)),
+    },
+    Spec {
+        name: "Screen",
+        inputs: &[("in", 16), ("load", 1), ("address", 13)],
+        outputs: WORD,
+        clocked: IN_LOAD,
+        implementation: Implementation::Stateful(|| Memory::boxed(8192)),
+    },
+    Spec {
+        name: "ROM32K",
+        inputs: &[("address", 15)],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Stateful(|| {
+            Box::new(Rom {
+                words: vec![0; 32768],
+            })
+        }),
+    },
+    Spec {
+        name: "Keyboard",
+        inputs: &[],
+        outputs: WORD,
+        clocked: &[],
+        implementation: Implementation::Stateful(|| Box::new(Keyboard::default())),
+    },
+];
+
+/// DMux, DMux4Way and DMux8Way: `in` goes to the output selected by `sel`, the others are 0.
+fn demux(inputs: &[u16], outputs: &mut [u16]) {
+    let (input, sel) = (inputs[0], inputs[1] as usize);
+    for (index, output) in outputs.iter_mut().enumerate() {
+        *output = if index == sel { input } else { 0 };
+    }
+}
@@ -0,0 +1,1090 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::hdl::builtin::{self, Builtin, Spec};
+use crate::hdl::error::{HdlError, Location};
+use crate::hdl::parser::{parse_file, Body, Bus, ChipDef, Part, PinDecl, Wire};
+
+/// A one-bit wire of a flattened chip.
+type Net = usize;
+
+/// Nets holding the constants `false` and `true`.
+const FALSE: Net = 0;
+const TRUE: Net = 1;
+
+/// Most pins a builtin chip has on either side.
+const MAX_PINS: usize = 16;
+
+/// How chips are found and built.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Directories searched, in order, for the parts missing from the directory of the chip
+    /// using them.
+    pub dirs: Vec<PathBuf>,
+    /// Simulate the parts of the standard library, like `Mux16` or `RAM4K`, natively rather
+    /// than from their `.hdl` files. Without this, they are still simulated natively when no
+    /// `.hdl` file is found.
+    pub builtins: bool,
+    /// Parts built from their `.hdl` files even with `builtins`, to test them in isolation.
+    pub hdl_chips: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            dirs: vec![],
+            builtins: true,
+            hdl_chips: vec![],
+        }
+    }
+}
+
+/// Chips the simulator implements itself rather than from an `.hdl` file: the two chips
+/// every other is built from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Primitive {
+    Nand,
+    /// Data flip-flop: `out` is what `in` was at the previous clock cycle.
+    Dff,
+}
+
+impl Primitive {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Nand" => Some(Primitive::Nand),
+            "DFF" => Some(Primitive::Dff),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Primitive::Nand => "Nand",
+            Primitive::Dff => "DFF",
+        }
+    }
+
+    fn inputs(&self) -> &'static [(&'static str, usize)] {
+        match self {
+            Primitive::Nand => &[("a", 1), ("b", 1)],
+            Primitive::Dff => &[("in", 1)],
+        }
+    }
+
+    fn outputs(&self) -> &'static [(&'static str, usize)] {
+        match self {
+            Primitive::Nand | Primitive::Dff => &[("out", 1)],
+        }
+    }
+
+    fn gate(&self, inputs: &[Vec<Net>], outputs: &[Vec<Net>]) -> Gate {
+        match self {
+            Primitive::Nand => Gate::Nand {
+                a: inputs[0][0],
+                b: inputs[1][0],
+                out: outputs[0][0],
+            },
+            Primitive::Dff => Gate::Dff {
+                input: inputs[0][0],
+                output: outputs[0][0],
+                state: false,
+            },
+        }
+    }
+}
+
+/// What a chip is flattened into.
+enum Gate {
+    Nand {
+        a: Net,
+        b: Net,
+        out: Net,
+    },
+    /// Takes `input` into `state` on the rising edge of the clock, `tick`, and shows it on
+    /// `output` on the falling edge, `tock`.
+    Dff {
+        input: Net,
+        output: Net,
+        state: bool,
+    },
+    /// A builtin chip, with the nets of each of its pins.
+    Native {
+        name: &'static str,
+        chip: Box<dyn Builtin>,
+        inputs: Vec<Vec<Net>>,
+        outputs: Vec<Vec<Net>>,
+        /// Whether the outputs follow each input without waiting for the clock.
+        combinational: Vec<bool>,
+    },
+}
+
+impl Gate {
+    /// Nets the outputs of the gate follow without waiting for the clock.
+    fn inputs(&self) -> Vec<Net> {
+        match self {
+            Gate::Nand { a, b, .. } => vec![*a, *b],
+            Gate::Dff { .. } => vec![],
+            Gate::Native {
+                inputs,
+                combinational,
+                ..
+            } => inputs
+                .iter()
+                .zip(combinational)
+                .filter(|(_, &combinational)| combinational)
+                .flat_map(|(nets, _)| nets.clone())
+                .collect(),
+        }
+    }
+
+    fn outputs(&self) -> Vec<Net> {
+        match self {
+            Gate::Nand { out, .. } => vec![*out],
+            Gate::Dff { output, .. } => vec![*output],
+            Gate::Native { outputs, .. } => outputs.concat(),
+        }
+    }
+
+    fn eval(&self, values: &mut [bool]) {
+        match self {
+            Gate::Nand { a, b, out } => values[*out] = !(values[*a] && values[*b]),
+            Gate::Dff { .. } => {}
+            Gate::Native {
+                chip,
+                inputs,
+                outputs,
+                ..
+            } => {
+                let mut words = [0; MAX_PINS];
+                let mut results = [0; MAX_PINS];
+                read_words(values, inputs, &mut words);
+                chip.eval(&words[..inputs.len()], &mut results[..outputs.len()]);
+                for (nets, word) in outputs.iter().zip(&results) {
+                    for (bit, &net) in nets.iter().enumerate() {
+                        values[net] = word >> bit & 1 == 1;
+                    }
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, values: &[bool]) {
+        match self {
+            Gate::Dff { input, state, .. } => *state = values[*input],
+            Gate::Native { chip, inputs, .. } => {
+                let mut words = [0; MAX_PINS];
+                read_words(values, inputs, &mut words);
+                chip.tick(&words[..inputs.len()]);
+            }
+            Gate::Nand { .. } => {}
+        }
+    }
+
+    /// Shows the state taken in at `tick`; the outputs of builtin chips follow at the next
+    /// `eval`.
+    fn tock(&mut self, values: &mut [bool]) {
+        match self {
+            Gate::Dff { output, state, .. } => values[*output] = *state,
+            Gate::Native { chip, .. } => chip.tock(),
+            Gate::Nand { .. } => {}
+        }
+    }
+
+    fn map_nets(&mut self, map: impl Fn(Net) -> Net) {
+        match self {
+            Gate::Nand { a, b, out } => {
+                *a = map(*a);
+                *b = map(*b);
+                *out = map(*out);
+            }
+            Gate::Dff { input, output, .. } => {
+                *input = map(*input);
+                *output = map(*output);
+            }
+            Gate::Native {
+                inputs, outputs, ..
+            } => {
+                for net in inputs.iter_mut().chain(outputs.iter_mut()).flatten() {
+                    *net = map(*net);
+                }
+            }
+        }
+    }
+}
+
+/// Reads the value of each bus of `pins` into `words`.
+fn read_words(values: &[bool], pins: &[Vec<Net>], words: &mut [u16]) {
+    for (nets, word) in pins.iter().zip(words) {
+        *word = nets
+            .iter()
+            .enumerate()
+            .fold(0, |word, (bit, &net)| word | (values[net] as u16) << bit);
+    }
+}
+
+/// A chip a part refers to.
+#[derive(Clone)]
+enum Kind {
+    Primitive(Primitive),
+    Native(Spec),
+    Composite(Rc<Layout>),
+}
+
+impl Kind {
+    fn name(&self) -> &str {
+        match self {
+            Kind::Primitive(primitive) => primitive.name(),
+            Kind::Native(spec) => spec.name,
+            Kind::Composite(layout) => &layout.name,
+        }
+    }
+
+    fn inputs(&self) -> Vec<(&str, usize)> {
+        match self {
+            Kind::Primitive(primitive) => primitive.inputs().to_vec(),
+            Kind::Native(spec) => spec.inputs.to_vec(),
+            Kind::Composite(layout) => pin_list(&layout.inputs),
+        }
+    }
+
+    fn outputs(&self) -> Vec<(&str, usize)> {
+        match self {
+            Kind::Primitive(primitive) => primitive.outputs().to_vec(),
+            Kind::Native(spec) => spec.outputs.to_vec(),
+            Kind::Composite(layout) => pin_list(&layout.outputs),
+        }
+    }
+}
+
+fn pin_list(pins: &[(String, usize)]) -> Vec<(&str, usize)> {
+    pins.iter()
+        .map(|(name, width)| (name.as_str(), *width))
+        .collect()
+}
+
+/// A chip built from parts, with its connections checked and resolved to bits.
+///
+/// The pins of the chip are numbered inputs first, then outputs, then internal pins.
+struct Layout {
+    name: String,
+    inputs: Vec<(String, usize)>,
+    outputs: Vec<(String, usize)>,
+    internals: Vec<(String, usize)>,
+    parts: Vec<PartLayout>,
+}
+
+/// Where a bit of a part's input pin comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Constant(bool),
+    /// Pin number and bit in the chip using the part.
+    Pin(usize, usize),
+}
+
+struct PartLayout {
+    kind: Kind,
+    /// Name of the part in diagnostics: its chip, numbered if the chip is used more than once.
+    label: String,
+    /// For each bit of each input pin, what feeds it.
+    inputs: Vec<Vec<Source>>,
+    /// For each bit of each output pin, the (pin number, bit) it feeds in the chip using the
+    /// part.
+    outputs: Vec<Vec<Vec<(usize, usize)>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Input,
+    Output,
+    Internal,
+}
+
+/// Finds, parses and checks the chips parts refer to: a builtin chip, as `Options` say, or
+/// `Name.hdl` in the directory of the chip using it, or else in the search directories.
+/// `Nand` and `DFF` are always built in.
+struct Library {
+    options: Options,
+    chips: HashMap<PathBuf, Kind>,
+    /// Files being loaded, innermost last, to catch chips using themselves.
+    loading: Vec<PathBuf>,
+}
+
+impl Library {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            options: options.clone(),
+            chips: HashMap::new(),
+            loading: vec![],
+        }
+    }
+
+    /// The chip `name`, used by a chip in `dir`.
+    fn resolve(&mut self, name: &str, dir: &Path, location: &Location) -> Result<Kind, HdlError> {
+        if let Some(primitive) = Primitive::from_name(name) {
+            return Ok(Kind::Primitive(primitive));
+        }
+        let native = builtin::spec(name);
+        let use_hdl = self.options.hdl_chips.iter().any(|chip| chip == name);
+        match native {
+            Some(spec) if self.options.builtins && !use_hdl => return Ok(Kind::Native(spec)),
+            _ => {}
+        }
+        let file_name = format!("{}.hdl", name);
+        let path = std::iter::once(dir)
+            .chain(self.options.dirs.iter().map(|dir| dir.as_path()))
+            .map(|dir| dir.join(&file_name))
+            .find(|path| path.is_file());
+        let path = match (path, native) {
+            (Some(path), _) => path,
+            (None, Some(spec)) if !use_hdl => return Ok(Kind::Native(spec)),
+            (None, _) => {
+                let message = format!("chip `{}` not found", name);
+                return Err(HdlError::at(&message, location, name.len()));
+            }
+        };
+        if self.loading.contains(&path) {
+            let message = format!("chip `{}` is made of itself", name);
+            return Err(HdlError::at(&message, location, name.len()));
+        }
+        self.load(&path)
+    }
+
+    /// The chip of the `.hdl` file at `hdl_path`.
+    fn load(&mut self, hdl_path: &Path) -> Result<Kind, HdlError> {
+        if let Some(kind) = self.chips.get(hdl_path) {
+            return Ok(kind.clone());
+        }
+        let def = parse_file(&hdl_path.to_string_lossy())?;
+        let kind = match &def.body {
+            Body::Builtin { name, .. } => match (Primitive::from_name(name), builtin::spec(name)) {
+                (Some(primitive), _) => Kind::Primitive(primitive),
+                (None, Some(spec)) => Kind::Native(spec),
+                (None, None) => {
+                    let message = format!("no builtin implementation of chip `{}`", name);
+                    return Err(HdlError::new(&message));
+                }
+            },
+            Body::Parts(_) => {
+                self.loading.push(hdl_path.to_path_buf());
+                let dir = hdl_path.parent().unwrap_or_else(|| Path::new("."));
+                let layout = self.layout(&def, dir);
+                self.loading.pop();
+                Kind::Composite(Rc::new(layout?))
+            }
+        };
+        self.chips.insert(hdl_path.to_path_buf(), kind.clone());
+        Ok(kind)
+    }
+
+    /// Checks and resolves the connections of the parts of `def`, a chip in `dir`.
+    fn layout(&mut self, def: &ChipDef, dir: &Path) -> Result<Layout, HdlError> {
+        let parts = match &def.body {
+            Body::Parts(parts) => parts,
+            Body::Builtin { .. } => unreachable!(),
+        };
+        let mut pins: HashMap<&str, (usize, usize, Role)> = HashMap::new();
+        let mut widths = vec![];
+        let declared = def
+            .inputs
+            .iter()
+            .map(|pin| (pin, Role::Input))
+            .chain(def.outputs.iter().map(|pin| (pin, Role::Output)));
+        for (pin, role) in declared {
+            if pins.contains_key(pin.name.as_str()) {
+                let message = format!("pin `{}` is declared twice", pin.name);
+                return Err(HdlError::at(&message, &pin.location, pin.name.len()));
+            }
+            pins.insert(&pin.name, (widths.len(), pin.width, role));
+            widths.push(pin.width);
+        }
+        let kinds = parts
+            .iter()
+            .map(|part| self.resolve(&part.chip, dir, &part.location))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Internal pins are declared by the part outputs feeding them, as wide as those.
+        let mut internals = vec![];
+        for (part, kind) in parts.iter().zip(&kinds) {
+            let outputs = kind.outputs();
+            for connection in &part.connections {
+                let width = match outputs
+                    .iter()
+                    .find(|(name, _)| *name == connection.pin.name)
+                {
+                    Some(&(_, width)) => width,
+                    None => continue,
+                };
+                let wire = match &connection.wire {
+                    Wire::Bus(wire) if !pins.contains_key(wire.name.as_str()) => wire,
+                    _ => continue,
+                };
+                if wire.range.is_some() {
+                    let message = format!("internal pin `{}` cannot be fed by sub-bus", wire.name);
+                    return Err(HdlError::at(&message, &wire.location, wire.name.len()));
+                }
+                let (first, last) = connection.pin.bits(width)?;
+                pins.insert(&wire.name, (widths.len(), last - first + 1, Role::Internal));
+                widths.push(last - first + 1);
+                internals.push((wire.name.clone(), last - first + 1));
+            }
+        }
+
+        let mut fed: Vec<Vec<bool>> = widths.iter().map(|&width| vec![false; width]).collect();
+        let mut part_layouts = vec![];
+        for (index, (part, kind)) in parts.iter().zip(kinds).enumerate() {
+            let same_chip = |other: &&Part| other.chip == part.chip;
+            let label = match parts.iter().filter(same_chip).count() {
+                1 => part.chip.clone(),
+                _ => format!(
+                    "{}#{}",
+                    part.chip,
+                    parts[..index].iter().filter(same_chip).count()
+                ),
+            };
+            let (part_inputs, part_outputs) = (kind.inputs(), kind.outputs());
+            let mut inputs: Vec<Vec<Option<Source>>> = part_inputs
+                .iter()
+                .map(|&(_, width)| vec![None; width])
+                .collect();
+            let mut outputs: Vec<Vec<Vec<(usize, usize)>>> = part_outputs
+                .iter()
+                .map(|&(_, width)| vec![vec![]; width])
+                .collect();
+            for connection in &part.connections {
+                let pin = &connection.pin;
+                let (is_input, index, width) = match (
+                    part_inputs.iter().position(|(name, _)| *name == pin.name),
+                    part_outputs.iter().position(|(name, _)| *name == pin.name),
+                ) {
+                    (Some(index), _) => (true, index, part_inputs[index].1),
+                    (None, Some(index)) => (false, index, part_outputs[index].1),
+                    (None, None) => {
+                        let message = format!("chip `{}` has no pin `{}`", part.chip, pin.name);
+                        return Err(HdlError::at(&message, &pin.location, pin.name.len()));
+                    }
+                };
+                let (first, last) = pin.bits(width)?;
+                // (bit of the part's pin, what it is connected to)
+                let bits: Vec<(usize, Source)> = match &connection.wire {
+                    Wire::Constant(value, location) => {
+                        if !is_input {
+                            let message = format!("output pin `{}` cannot be set", pin.name);
+                            return Err(HdlError::at(&message, location, 5));
+                        }
+                        (first..=last)
+                            .map(|bit| (bit, Source::Constant(*value)))
+                            .collect()
+                    }
+                    Wire::Bus(wire) => {
+                        let (number, wire_width, role) = match pins.get(wire.name.as_str()) {
+                            Some(&pin) => pin,
+                            None => {
+                                let message = format!("pin `{}` is not defined", wire.name);
+                                let width = wire.name.len();
+                                return Err(HdlError::at(&message, &wire.location, width));
+                            }
+                        };
+                        let (wire_first, wire_last) = wire.bits(wire_width)?;
+                        if wire_last - wire_first != last - first {
+                            let message = format!(
+                                "pin `{}` of `{}` is {} bit(s) wide but `{}` is {}",
+                                bus_text(pin),
+                                part.chip,
+                                last - first + 1,
+                                bus_text(wire),
+                                wire_last - wire_first + 1
+                            );
+                            let width = wire.name.len();
+                            return Err(HdlError::at(&message, &wire.location, width));
+                        }
+                        match (is_input, role) {
+                            (true, Role::Output) => {
+                                let message =
+                                    format!("output pin `{}` cannot feed a part", wire.name);
+                                let width = wire.name.len();
+                                return Err(HdlError::at(&message, &wire.location, width));
+                            }
+                            (false, Role::Input) => {
+                                let message =
+                                    format!("input pin `{}` cannot be fed by a part", wire.name);
+                                let width = wire.name.len();
+                                return Err(HdlError::at(&message, &wire.location, width));
+                            }
+                            _ => {}
+                        }
+                        (first..=last)
+                            .zip(wire_first..=wire_last)
+                            .map(|(bit, wire_bit)| (bit, Source::Pin(number, wire_bit)))
+                            .collect()
+                    }
+                };
+                for (bit, source) in bits {
+                    if is_input {
+                        if inputs[index][bit].is_some() {
+                            let message = format!("pin `{}` is connected twice", bus_text(pin));
+                            return Err(HdlError::at(&message, &pin.location, pin.name.len()));
+                        }
+                        inputs[index][bit] = Some(source);
+                    } else if let Source::Pin(number, wire_bit) = source {
+                        if fed[number][wire_bit] {
+                            let wire = match &connection.wire {
+                                Wire::Bus(wire) => wire,
+                                Wire::Constant(..) => unreachable!(),
+                            };
+                            let message = format!("`{}` is fed by more than one part", wire.name);
+                            let width = wire.name.len();
+                            return Err(HdlError::at(&message, &wire.location, width));
+                        }
+                        fed[number][wire_bit] = true;
+                        outputs[index][bit].push((number, wire_bit));
+                    }
+                }
+            }
+            part_layouts.push(PartLayout {
+                kind,
+                label,
+                inputs: inputs
+                    .into_iter()
+                    .map(|bits| {
+                        bits.into_iter()
+                            .map(|source| source.unwrap_or(Source::Constant(false)))
+                            .collect()
+                    })
+                    .collect(),
+                outputs,
+            });
+        }
+
+        let pin_widths = |pins: &[PinDecl]| {
+            pins.iter()
+                .map(|pin| (pin.name.clone(), pin.width))
+                .collect()
+        };
+        Ok(Layout {
+            name: def.name.clone(),
+            inputs: pin_widths(&def.inputs),
+            outputs: pin_widths(&def.outputs),
+            internals,
+            parts: part_layouts,
+        })
+    }
+}
+
+/// `a`, `a[3]` or `a[0..7]`, as written.
+fn bus_text(bus: &Bus) -> String {
+    match bus.range {
+        None => bus.name.clone(),
+        Some((first, last)) if first == last => format!("{}[{}]", bus.name, first),
+        Some((first, last)) => format!("{}[{}..{}]", bus.name, first, last),
+    }
+}
+
+/// Flattens a chip into gates, merging the nets joined by wires.
+struct Builder {
+    /// Union-find forest of the nets.
+    parents: Vec<Net>,
+    gates: Vec<Gate>,
+    /// Full names of the pins met, when collecting them.
+    names: Option<Vec<(String, Vec<Net>)>>,
+    /// Labels of the chips being instantiated, outermost first.
+    path: Vec<String>,
+}
+
+/// A chip flattened into gates, with the nets of its pins.
+struct Flat {
+    inputs: Vec<(String, Vec<Net>)>,
+    outputs: Vec<(String, Vec<Net>)>,
+    gates: Vec<Gate>,
+    /// Number of nets.
+    nets: usize,
+    /// With `naming`, every pin of every chip in the hierarchy but Nand and DFF, outermost
+    /// first, named like `RAM8.Register#3.out`.
+    names: Vec<(String, Vec<Net>)>,
+}
+
+impl Builder {
+    fn net(&mut self) -> Net {
+        self.parents.push(self.parents.len());
+        self.parents.len() - 1
+    }
+
+    fn find(&mut self, mut net: Net) -> Net {
+        while self.parents[net] != net {
+            self.parents[net] = self.parents[self.parents[net]];
+            net = self.parents[net];
+        }
+        net
+    }
+
+    fn union(&mut self, a: Net, b: Net) {
+        let (a, b) = (self.find(a), self.find(b));
+        // Keep the constants as roots.
+        if a < b {
+            self.parents[b] = a;
+        } else {
+            self.parents[a] = b;
+        }
+    }
+
+    fn name_pins(&mut self, pins: &[(&str, usize)], nets: &[Vec<Net>]) {
+        if let Some(names) = &mut self.names {
+            let prefix = self.path.join(".");
+            for ((name, _), nets) in pins.iter().zip(nets) {
+                names.push((format!("{}.{}", prefix, name), nets.clone()));
+            }
+        }
+    }
+
+    fn instantiate(&mut self, kind: &Kind, inputs: Vec<Vec<Net>>, outputs: Vec<Vec<Net>>) {
+        // Nand and DFF are too many to be worth naming.
+        if self.names.is_some() && !matches!(kind, Kind::Primitive(_)) {
+            let pins: Vec<_> = kind.inputs().into_iter().chain(kind.outputs()).collect();
+            let nets: Vec<_> = inputs.iter().chain(&outputs).cloned().collect();
+            self.name_pins(&pins, &nets);
+        }
+        let layout = match kind {
+            Kind::Primitive(primitive) => {
+                self.gates.push(primitive.gate(&inputs, &outputs));
+                return;
+            }
+            Kind::Native(spec) => {
+                let combinational = spec
+                    .inputs
+                    .iter()
+                    .map(|(name, _)| !spec.clocked.contains(name))
+                    .collect();
+                self.gates.push(Gate::Native {
+                    name: spec.name,
+                    chip: spec.instantiate(),
+                    inputs,
+                    outputs,
+                    combinational,
+                });
+                return;
+            }
+            Kind::Composite(layout) => layout,
+        };
+        let mut pins = inputs;
+        pins.extend(outputs);
+        let declared = pins.len();
+        for &(_, width) in &layout.internals {
+            let nets = (0..width).map(|_| self.net()).collect();
+            pins.push(nets);
+        }
+        self.name_pins(&pin_list(&layout.internals), &pins[declared..]);
+        for part in &layout.parts {
+            let inputs = part
+                .inputs
+                .iter()
+                .map(|bits| {
+                    bits.iter()
+                        .map(|source| match *source {
+                            Source::Constant(false) => FALSE,
+                            Source::Constant(true) => TRUE,
+                            Source::Pin(number, bit) => pins[number][bit],
+                        })
+                        .collect()
+                })
+                .collect();
+            let mut outputs = vec![];
+            for bits in &part.outputs {
+                let mut nets = vec![];
+                for targets in bits {
+                    // An output bit feeding several pins joins them; one feeding none gets
+                    // a net of its own.
+                    let net = match targets.split_first() {
+                        Some((&(number, bit), rest)) => {
+                            for &(other, other_bit) in rest {
+                                self.union(pins[number][bit], pins[other][other_bit]);
+                            }
+                            pins[number][bit]
+                        }
+                        None => self.net(),
+                    };
+                    nets.push(net);
+                }
+                outputs.push(nets);
+            }
+            if self.names.is_some() {
+                self.path.push(part.label.clone());
+            }
+            self.instantiate(&part.kind, inputs, outputs);
+            if self.names.is_some() {
+                self.path.pop();
+            }
+        }
+    }
+}
+
+/// Flattens `kind`, named `name`, collecting the names of all its pins if `naming`.
+fn flatten(kind: &Kind, name: &str, naming: bool) -> Flat {
+    let mut builder = Builder {
+        parents: vec![FALSE, TRUE],
+        gates: vec![],
+        names: if naming { Some(vec![]) } else { None },
+        path: vec![name.to_string()],
+    };
+    let mut allocate = |pins: &[(&str, usize)]| -> Vec<(String, Vec<Net>)> {
+        pins.iter()
+            .map(|&(name, width)| {
+                (
+                    name.to_string(),
+                    (0..width).map(|_| builder.net()).collect(),
+                )
+            })
+            .collect()
+    };
+    let inputs = allocate(&kind.inputs());
+    let outputs = allocate(&kind.outputs());
+    builder.instantiate(
+        kind,
+        inputs.iter().map(|(_, nets)| nets.clone()).collect(),
+        outputs.iter().map(|(_, nets)| nets.clone()).collect(),
+    );
+
+    // Number the merged nets densely.
+    let mut numbers = vec![usize::MAX; builder.parents.len()];
+    let mut count = 0;
+    for net in 0..builder.parents.len() {
+        let root = builder.find(net);
+        if numbers[root] == usize::MAX {
+            numbers[root] = count;
+            count += 1;
+        }
+        numbers[net] = numbers[root];
+    }
+    let mut gates = builder.gates;
+    for gate in &mut gates {
+        gate.map_nets(|net| numbers[net]);
+    }
+    let renumber = |pins: Vec<(String, Vec<Net>)>| {
+        pins.into_iter()
+            .map(|(name, nets)| (name, nets.iter().map(|&net| numbers[net]).collect()))
+            .collect()
+    };
+    Flat {
+        inputs: renumber(inputs),
+        outputs: renumber(outputs),
+        gates,
+        nets: count,
+        names: renumber(builder.names.unwrap_or_default()),
+    }
+}
+
+/// A pin of a chip or of one of its parts, named like `RAM8.Register#3.out`, whose value
+/// `Chip::read` gives.
+#[derive(Debug, Clone)]
+pub struct Probe {
+    pub name: String,
+    pub width: usize,
+    nets: Vec<Net>,
+}
+
+/// A chip flattened into gates, ready to be simulated.
+pub struct Chip {
+    name: String,
+    /// How the chips loaded by `load` are built.
+    options: Options,
+    /// What the chip was flattened from, to name its internal pins.
+    kind: Option<Kind>,
+    inputs: Vec<(String, Vec<Net>)>,
+    outputs: Vec<(String, Vec<Net>)>,
+    /// In evaluation order: every gate after those feeding it.
+    gates: Vec<Gate>,
+    values: Vec<bool>,
+    /// Whether the clock is high: after `tick`, before `tock`.
+    clock: bool,
+}
+
+impl Chip {
+    /// An empty chip, to be replaced by `load`.
+    pub fn new(options: &Options) -> Self {
+        Self {
+            name: String::new(),
+            options: options.clone(),
+            kind: None,
+            inputs: vec![],
+            outputs: vec![],
+            gates: vec![],
+            values: vec![false, true],
+            clock: false,
+        }
+    }
+
+    /// Loads the chip of `hdl_path`. Its parts are found as `options` say, but the chip
+    /// itself is always built from `hdl_path`.
+    pub fn load(hdl_path: &Path, options: &Options) -> Result<Self, HdlError> {
+        let mut library = Library::new(options);
+        let kind = library.load(hdl_path)?;
+        let name = kind.name().to_string();
+        let flat = flatten(&kind, &name, false);
+        let gates = match sort_gates(flat.gates, flat.nets) {
+            Ok(gates) => gates,
+            Err(cycle) => {
+                // Flattening again gives the same nets, this time with their names.
+                let names = flatten(&kind, &name, true).names;
+                let name_of = |net: Net| {
+                    let (name, nets) = names.iter().find(|(_, nets)| nets.contains(&net)).unwrap();
+                    match nets.iter().position(|&other| other == net) {
+                        Some(bit) if nets.len() > 1 => format!("{}[{}]", name, bit),
+                        _ => name.clone(),
+                    }
+                };
+                let mut path: Vec<String> = cycle.iter().map(|&net| name_of(net)).collect();
+                // Start from the outermost pin.
+                let depth = |name: &String| name.matches('.').count();
+                let start = (0..path.len()).min_by_key(|&i| depth(&path[i])).unwrap();
+                path.rotate_left(start);
+                path.push(path[0].clone());
+                let message = format!("combinational loop: {}", path.join(" -> "));
+                return Err(HdlError::new(&message));
+            }
+        };
+        let mut chip = Self {
+            name,
+            options: options.clone(),
+            kind: Some(kind),
+            inputs: flat.inputs,
+            outputs: flat.outputs,
+            gates,
+            values: vec![false; flat.nets],
+            clock: false,
+        };
+        chip.values[TRUE] = true;
+        chip.eval();
+        Ok(chip)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// Names and widths of the input pins.
+    pub fn inputs(&self) -> Vec<(&str, usize)> {
+        self.inputs
+            .iter()
+            .map(|(name, nets)| (name.as_str(), nets.len()))
+            .collect()
+    }
+
+    /// Names and widths of the output pins.
+    pub fn outputs(&self) -> Vec<(&str, usize)> {
+        self.outputs
+            .iter()
+            .map(|(name, nets)| (name.as_str(), nets.len()))
+            .collect()
+    }
+
+    /// Width of the input or output pin `name`.
+    pub fn width(&self, name: &str) -> Option<usize> {
+        self.pin(name).map(|nets| nets.len())
+    }
+
+    fn pin(&self, name: &str) -> Option<&[Net]> {
+        self.inputs
+            .iter()
+            .chain(&self.outputs)
+            .find(|(pin, _)| pin == name)
+            .map(|(_, nets)| nets.as_slice())
+    }
+
+    /// The first builtin part named `name`, like `RAM16K`.
+    pub fn builtin(&self, name: &str) -> Option<&dyn Builtin> {
+        self.gates.iter().find_map(|gate| match gate {
+            Gate::Native {
+                name: part, chip, ..
+            } if *part == name => Some(chip.as_ref()),
+            _ => None,
+        })
+    }
+
+    pub fn builtin_mut(&mut self, name: &str) -> Option<&mut (dyn Builtin + 'static)> {
+        self.gates.iter_mut().find_map(|gate| match gate {
+            Gate::Native {
+                name: part, chip, ..
+            } if *part == name => Some(chip.as_mut()),
+            _ => None,
+        })
+    }
+
+    /// Sets the input pin `name`, or the register or memory word of a builtin part, written
+    /// like `PC[]` or `RAM16K[3]`; the outputs follow at the next `eval`.
+    pub fn set(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let nets = match self.inputs.iter().find(|(pin, _)| pin == name) {
+            Some((_, nets)) => nets,
+            None => {
+                let set = parse_part_variable(name)
+                    .and_then(|(part, index)| Some(self.builtin_mut(part)?.set(index, value)));
+                return match set {
+                    Some(true) => Ok(()),
+                    _ => Err(format!("chip `{}` has no input pin `{}`.", self.name, name)),
+                };
+            }
+        };
+        for (bit, &net) in nets.iter().enumerate() {
+            self.values[net] = value >> bit & 1 == 1;
+        }
+        Ok(())
+    }
+
+    /// Value of the input or output pin `name`, or of the register or memory word of a
+    /// builtin part, as for `set`.
+    pub fn get(&self, name: &str) -> Result<u16, String> {
+        if let Some(nets) = self.pin(name) {
+            return Ok(self.value(nets));
+        }
+        parse_part_variable(name)
+            .and_then(|(part, index)| self.builtin(part)?.get(index))
+            .ok_or_else(|| format!("chip `{}` has no pin `{}`.", self.name, name))
+    }
+
+    /// The input and output pins, and with `internal` the internal pins of the chip and the
+    /// pins of all its parts but Nand and DFF, outermost first.
+    pub fn probes(&self, internal: bool) -> Vec<Probe> {
+        let pins = match &self.kind {
+            // Flattening again gives the same nets, this time with their names.
+            Some(kind) if internal => flatten(kind, &self.name, true).names,
+            _ => self
+                .inputs
+                .iter()
+                .chain(&self.outputs)
+                .map(|(pin, nets)| (format!("{}.{}", self.name, pin), nets.clone()))
+                .collect(),
+        };
+        pins.into_iter()
+            .map(|(name, nets)| Probe {
+                name,
+                width: nets.len(),
+                nets,
+            })
+            .collect()
+    }
+
+    /// Value of the pin `probe`, one of the `probes` of this chip.
+    pub fn read(&self, probe: &Probe) -> u16 {
+        self.value(&probe.nets)
+    }
+
+    /// Whether the clock is high, from a `tick` to the next `tock`.
+    pub fn clock(&self) -> bool {
+        self.clock
+    }
+
+    fn value(&self, nets: &[Net]) -> u16 {
+        nets.iter().enumerate().fold(0, |value, (bit, &net)| {
+            value | (self.values[net] as u16) << bit
+        })
+    }
+
+    /// Propagates the inputs through the gates.
+    pub fn eval(&mut self) {
+        for gate in &self.gates {
+            gate.eval(&mut self.values);
+        }
+    }
+
+    /// The rising edge of the clock: clocked parts take in their inputs, as they are after
+    /// `eval`, but keep showing their previous state.
+    pub fn tick(&mut self) {
+        self.eval();
+        for gate in &mut self.gates {
+            gate.tick(&self.values);
+        }
+        self.clock = true;
+    }
+
+    /// The falling edge of the clock: clocked parts show their new state, and the chip is
+    /// evaluated with it.
+    pub fn tock(&mut self) {
+        for gate in &mut self.gates {
+            gate.tock(&mut self.values);
+        }
+        self.clock = false;
+        self.eval();
+    }
+
+    /// Sets `inputs`, evaluates the chip and returns the values of its output pins.
+    pub fn evaluate(&mut self, inputs: &[(&str, u16)]) -> Result<Vec<(String, u16)>, String> {
+        for &(name, value) in inputs {
+            self.set(name, value)?;
+        }
+        self.eval();
+        Ok(self
+            .outputs
+            .iter()
+            .map(|(name, nets)| (name.clone(), self.value(nets)))
+            .collect())
+    }
+}
+
+/// Splits `PC[]` into `("PC", None)` and `RAM16K[3]` into `("RAM16K", Some(3))`.
+fn parse_part_variable(variable: &str) -> Option<(&str, Option<usize>)> {
+    let (part, index) = variable.strip_suffix(']')?.split_once('[')?;
+    match index {
+        "" => Some((part, None)),
+        _ => Some((part, Some(index.parse().ok()?))),
+    }
+}
+
+/// Orders `gates` so that each comes after the gates feeding it, or returns the nets of a
+/// loop in them, in the direction values flow.
+fn sort_gates(gates: Vec<Gate>, nets: usize) -> Result<Vec<Gate>, Vec<Net>> {
+    let mut driver = vec![None; nets];
+    for (index, gate) in gates.iter().enumerate() {
+        for net in gate.outputs() {
+            driver[net] = Some(index);
+        }
+    }
+    let mut fed = vec![vec![]; gates.len()];
+    let mut pending = vec![0; gates.len()];
+    for (index, gate) in gates.iter().enumerate() {
+        for net in gate.inputs() {
+            if let Some(source) = driver[net] {
+                fed[source].push(index);
+                pending[index] += 1;
+            }
+        }
+    }
+    let mut ready: VecDeque<usize> = (0..gates.len()).filter(|&i| pending[i] == 0).collect();
+    let mut order = vec![];
+    while let Some(index) = ready.pop_front() {
+        order.push(index);
+        for &next in &fed[index] {
+            pending[next] -= 1;
+            if pending[next] == 0 {
+                ready.push_back(next);
+            }
+        }
+    }
+    if order.len() < gates.len() {
+        // Every gate left waits for another gate left: walk back through them until one
+        // comes round again.
+        let mut index = (0..gates.len()).find(|&i| pending[i] > 0).unwrap();
+        let mut walk: Vec<(usize, Net)> = vec![];
+        loop {
+            if let Some(start) = walk.iter().position(|&(other, _)| other == index) {
+                let mut cycle: Vec<Net> = walk[start..].iter().map(|&(_, net)| net).collect();
+                cycle.reverse();
+                return Err(cycle);
+            }
+            let (net, source) = gates[index]
+                .inputs()
+                .into_iter()
+                .filter_map(|net| driver[net].map(|source| (net, source)))
+                .find(|&(_, source)| pending[source] > 0)
+                .unwrap();
+            walk.push((index, net));
+            index = source;
+        }
+    }
+    let mut gates: Vec<Option<Gate>> = gates.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .map(|index| gates[index].take().unwrap())
+        .collect())
+}
@@ -0,0 +1,262 @@
+use std::fmt;
+
+use crate::cpu_emulator::machine::Machine;
+use crate::cpu_emulator::screen::{KBD, SCREEN};
+use crate::disassembler::Instruction;
+use crate::hdl::chip::{Chip, Probe};
+
+/// Builtin part of `Computer.hdl` the program is loaded into.
+const ROM: &str = "ROM32K";
+
+/// Parts of `Computer.CPU` holding PC, A and D.
+const REGISTERS: [(&str, &str); 3] = [("PC", "PC"), ("A", "ARegister"), ("D", "DRegister")];
+
+/// Builtin parts of `Computer.Memory` telling what was written to them, with the addresses
+/// they are mapped to.
+const MEMORIES: [(&str, usize, usize); 2] = [("RAM16K", 0, SCREEN), ("Screen", SCREEN, KBD)];
+
+/// How the program is run.
+pub struct Options {
+    /// Clock cycles to run, each executing one instruction.
+    pub cycles: u64,
+    /// Code of the key held down, for both machines.
+    pub key: u16,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            cycles: 1_000_000,
+            key: 0,
+        }
+    }
+}
+
+/// Where the simulated chip and the CPU emulator stopped agreeing.
+#[derive(Debug)]
+pub struct Divergence {
+    /// The clock cycle, counted from 1, after which they disagreed, `None` if the program could
+    /// not be run at all.
+    pub cycle: Option<u64>,
+    /// ROM address of the instruction executed in that cycle.
+    pub pc: u16,
+    pub instruction: u16,
+    pub message: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.cycle {
+            Some(cycle) => {
+                let instruction = match Instruction::decode(self.instruction) {
+                    Ok(instruction) => instruction.to_string(),
+                    Err(_) => format!("{:016b}", self.instruction),
+                };
+                write!(
+                    f,
+                    "diverged at cycle {}, executing `{}` at ROM[{}]:\n{}",
+                    cycle, instruction, self.pc, self.message
+                )
+            }
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Where the state of the computer is read from: its builtin parts, or, for the parts built
+/// from their `.hdl` files, pins of `Computer.CPU`.
+struct Taps {
+    /// For PC, A and D, the `out` pin of the register part when it is not a builtin.
+    registers: Vec<Option<Probe>>,
+    /// `writeM`, `addressM` and `outM` of the CPU when RAM16K or Screen is not a builtin. For
+    /// its addresses, they give the word the CPU asks to write rather than the one written.
+    cpu_write: Option<[Probe; 3]>,
+}
+
+impl Taps {
+    fn new(computer: &Chip) -> Result<Self, String> {
+        let missing = |part: &str| computer.builtin(part).is_none();
+        let cpu_pins = REGISTERS.iter().any(|(_, part)| missing(part))
+            || MEMORIES.iter().any(|(part, _, _)| missing(part));
+        let probes = if cpu_pins {
+            computer.probes(true)
+        } else {
+            vec![]
+        };
+        let cpu_pin = |pin: &str| {
+            let name = format!("{}.CPU.{}", computer.name(), pin);
+            probes
+                .iter()
+                .find(|probe| probe.name == name)
+                .cloned()
+                .ok_or_else(|| {
+                    format!(
+                        "chip `{}` has no pin `{}` to compare with the CPU emulator.",
+                        computer.name(),
+                        name
+                    )
+                })
+        };
+        let registers = REGISTERS
+            .iter()
+            .map(|(_, part)| match missing(part) {
+                true => cpu_pin(&format!("{}.out", part)).map(Some),
+                false => Ok(None),
+            })
+            .collect::<Result<_, _>>()?;
+        let cpu_write = match MEMORIES.iter().any(|(part, _, _)| missing(part)) {
+            true => Some([cpu_pin("writeM")?, cpu_pin("addressM")?, cpu_pin("outM")?]),
+            false => None,
+        };
+        Ok(Self {
+            registers,
+            cpu_write,
+        })
+    }
+
+    /// The word the CPU asks to write in this cycle, to be read before the clock ticks, if
+    /// some memory cannot tell what it was written.
+    fn cpu_write(&self, computer: &Chip) -> Option<(usize, u16)> {
+        let [write, address, value] = self.cpu_write.as_ref()?;
+        (computer.read(write) == 1).then(|| (computer.read(address) as usize, computer.read(value)))
+    }
+}
+
+/// Loads `program` into the ROM32K of `computer`, a freshly loaded `Computer.hdl`, and runs it
+/// cycle by cycle next to the CPU emulator, comparing PC, A, D and the RAM word written after
+/// every cycle. Returns the number of cycles run.
+///
+/// PC, A and D are read from the builtin PC, ARegister and DRegister, or from the `out` pins
+/// of those parts of `Computer.CPU` when they are built from their `.hdl` files, and the words
+/// written from the builtin RAM16K and Screen, or from the pins of the CPU when either is not
+/// a builtin.
+///
+/// Writes from the keyboard up are dropped by the memory of the Hack computer, so the CPU
+/// emulator writing there expects no write from the chip.
+pub fn compare(
+    computer: &mut Chip,
+    program: &[u16],
+    options: &Options,
+) -> Result<u64, Box<Divergence>> {
+    let setup_error = |message: String| {
+        Box::new(Divergence {
+            cycle: None,
+            pc: 0,
+            instruction: 0,
+            message,
+        })
+    };
+    let mut machine = Machine::new(program.to_vec()).map_err(setup_error)?;
+    if computer.builtin(ROM).is_none() {
+        return Err(setup_error(format!(
+            "chip `{}` has no builtin {} part to load the program into.",
+            computer.name(),
+            ROM
+        )));
+    }
+    let rom = computer.builtin_mut(ROM).unwrap();
+    for (address, &word) in program.iter().enumerate() {
+        rom.set(Some(address), word);
+    }
+    let taps = Taps::new(computer).map_err(setup_error)?;
+    machine.ram_mut()[KBD] = options.key;
+    if let Some(keyboard) = computer.builtin_mut("Keyboard") {
+        keyboard.set(None, options.key);
+    }
+    computer.set("reset", 0).map_err(setup_error)?;
+    computer.eval();
+
+    for cycle in 1..=options.cycles {
+        let pc = machine.pc();
+        let instruction = machine.rom()[pc as usize];
+        let divergence = |message: String| {
+            Box::new(Divergence {
+                cycle: Some(cycle),
+                pc,
+                instruction,
+                message,
+            })
+        };
+        // A C-instruction with M in its destination writes RAM[A], A as it was before.
+        let address = machine.a() as usize;
+        machine
+            .step()
+            .map_err(|e| divergence(format!("CPU emulator: {}", e)))?;
+        let expected = (instruction & 0x8008 == 0x8008 && address < KBD)
+            .then(|| (address, machine.ram()[address]));
+        let cpu_write = taps.cpu_write(computer);
+        computer.tick();
+        computer.tock();
+        compare_state(&machine, computer, &taps, cpu_write, expected).map_err(divergence)?;
+    }
+    Ok(options.cycles)
+}
+
+/// Compares the registers, and the word written in the last cycle with `expected`, taking
+/// `cpu_write` for the memories that cannot tell. PC is compared on the 15 bits addressing the
+/// ROM.
+fn compare_state(
+    machine: &Machine,
+    computer: &Chip,
+    taps: &Taps,
+    cpu_write: Option<(usize, u16)>,
+    expected: Option<(usize, u16)>,
+) -> Result<(), String> {
+    let emulated = [machine.pc(), machine.a(), machine.d()];
+    let mut differences = vec![];
+    for (((name, part), probe), expected) in REGISTERS.iter().zip(&taps.registers).zip(emulated) {
+        let actual = match probe {
+            Some(probe) => computer.read(probe),
+            None => computer.builtin(part).unwrap().get(None).unwrap(),
+        };
+        let actual = match *part {
+            "PC" => actual & 0x7fff,
+            _ => actual,
+        };
+        if expected != actual {
+            differences.push(format!(
+                "{} is {} on the CPU emulator but {} on {}.",
+                name,
+                expected as i16,
+                actual as i16,
+                computer.name()
+            ));
+        }
+    }
+    let expected: Vec<(usize, u16)> = expected.into_iter().collect();
+    let written: Vec<(usize, u16)> = MEMORIES
+        .iter()
+        .filter_map(|&(part, start, end)| match computer.builtin(part) {
+            Some(memory) => {
+                let (index, value) = memory.written()?;
+                Some((start + index, value))
+            }
+            None => cpu_write.filter(|&(address, _)| (start..end).contains(&address)),
+        })
+        .collect();
+    if written != expected {
+        differences.push(format!(
+            "the CPU emulator wrote {} but {} wrote {}.",
+            writes(&expected),
+            computer.name(),
+            writes(&written)
+        ));
+    }
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(differences.join("\n"))
+    }
+}
+
+/// `RAM[16]=5`, for each word written, or `nothing`.
+fn writes(words: &[(usize, u16)]) -> String {
+    if words.is_empty() {
+        return "nothing".to_string();
+    }
+    words
+        .iter()
+        .map(|(address, value)| format!("RAM[{}]={}", address, *value as i16))
+        .collect::<Vec<_>>()
+        .join(" and ")
+}
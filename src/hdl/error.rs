@@ -0,0 +1,53 @@
+use std::fmt;
+
+use crate::diagnostic::write_diagnostic;
+pub use crate::diagnostic::Location;
+
+/// A malformed `.hdl` file, or a chip that cannot be built or simulated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdlError {
+    pub message: String,
+    pub location: Option<Location>,
+    /// Length of the text the error points at, used for the `^^^` marker.
+    pub width: usize,
+}
+
+impl HdlError {
+    pub fn new(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+            location: None,
+            width: 1,
+        }
+    }
+
+    pub fn at(message: &str, location: &Location, width: usize) -> Self {
+        Self {
+            message: message.to_string(),
+            location: Some(location.clone()),
+            width: width.max(1),
+        }
+    }
+}
+
+/// Formats the error like a compiler diagnostic, in the same layout as
+/// [`AsmError`](crate::assembler::error::AsmError).
+impl fmt::Display for HdlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_diagnostic(
+            f,
+            "error",
+            &self.message,
+            self.location.as_ref(),
+            self.width,
+        )
+    }
+}
+
+impl std::error::Error for HdlError {}
+
+impl From<HdlError> for String {
+    fn from(error: HdlError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,8 @@
+pub mod builtin;
+pub mod chip;
+pub mod differential;
+pub mod error;
+pub mod parser;
+pub mod vcd;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,386 @@
+use std::fs;
+
+use crate::hdl::error::{HdlError, Location};
+
+/// Widest bus a pin may declare: pin values are read and written as `u16`.
+pub const MAX_WIDTH: usize = 16;
+
+/// A chip as written in an `.hdl` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChipDef {
+    pub name: String,
+    /// Name of the `.hdl` file, used in diagnostics.
+    pub file: String,
+    pub inputs: Vec<PinDecl>,
+    pub outputs: Vec<PinDecl>,
+    pub body: Body,
+}
+
+/// `a` or `a[16]` in an `IN` or `OUT` list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinDecl {
+    pub name: String,
+    pub width: usize,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    /// `PARTS:` followed by the parts of the chip.
+    Parts(Vec<Part>),
+    /// `BUILTIN Name;`, optionally with `CLOCKED pin, ...;`: the chip is implemented by the
+    /// simulator.
+    Builtin { name: String, clocked: Vec<String> },
+}
+
+/// `Chip(pin=wire, ...);`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub chip: String,
+    pub connections: Vec<Connection>,
+    pub location: Location,
+}
+
+/// `pin=wire`: `pin` belongs to the part, `wire` to the chip using it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub pin: Bus,
+    pub wire: Wire,
+}
+
+/// A pin, `a`, or some of its bits, `a[3]` or `a[0..7]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bus {
+    pub name: String,
+    /// First and last bit, inclusive.
+    pub range: Option<(usize, usize)>,
+    pub location: Location,
+}
+
+impl Bus {
+    /// Bits of a pin `width` wide this refers to, or what is wrong with the range.
+    pub fn bits(&self, width: usize) -> Result<(usize, usize), HdlError> {
+        match self.range {
+            None => Ok((0, width - 1)),
+            Some((_, last)) if last >= width => Err(HdlError::at(
+                &format!(
+                    "bit {} is out of range for pin `{}` of width {}",
+                    last, self.name, width
+                ),
+                &self.location,
+                self.name.len(),
+            )),
+            Some(range) => Ok(range),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Wire {
+    Bus(Bus),
+    /// `true` or `false`, setting every bit of the pin.
+    Constant(bool, Location),
+}
+
+/// Reads and parses the `.hdl` file at `hdl_path`.
+pub fn parse_file(hdl_path: &str) -> Result<ChipDef, HdlError> {
+    let hdl =
+        fs::read_to_string(hdl_path).map_err(|e| HdlError::new(&format!("{}: {}", hdl_path, e)))?;
+    parse(hdl_path, &hdl)
+}
+
+/// Parses the text `hdl`; `hdl_name` is the file name used in diagnostics.
+pub fn parse(hdl_name: &str, hdl: &str) -> Result<ChipDef, HdlError> {
+    let tokens = tokenize(hdl_name, hdl)?;
+    let mut parser = HdlParser {
+        tokens,
+        current: 0,
+        file: hdl_name,
+        hdl,
+    };
+    let chip = parser.chip()?;
+    match parser.peek() {
+        Some(token) => Err(parser.unexpected(token, "end of file")),
+        None => Ok(chip),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn is_name(&self) -> bool {
+        self.text
+            .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+    }
+}
+
+fn tokenize(hdl_name: &str, hdl: &str) -> Result<Vec<Token>, HdlError> {
+    let mut tokens = vec![];
+    let chars: Vec<char> = hdl.chars().collect();
+    let (mut line, mut column) = (1, 1);
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let (start_line, start_column) = (line, column);
+        let length = if c == '/' && chars.get(i + 1) == Some(&'/') {
+            chars[i..].iter().take_while(|&&c| c != '\n').count()
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            match (i + 3..chars.len()).find(|&j| chars[j - 1] == '*' && chars[j] == '/') {
+                Some(end) => end + 1 - i,
+                None => {
+                    let location = Location::new(hdl_name, hdl, line, column);
+                    return Err(HdlError::at("unterminated comment", &location, 2));
+                }
+            }
+        } else if c.is_whitespace() {
+            1
+        } else {
+            let length = if c.is_ascii_alphanumeric() || c == '_' {
+                chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count()
+            } else if c == '.' && chars.get(i + 1) == Some(&'.') {
+                2
+            } else if "{}()[],;:=".contains(c) {
+                1
+            } else {
+                let location = Location::new(hdl_name, hdl, line, column);
+                return Err(HdlError::at(
+                    &format!("invalid character `{}`", c),
+                    &location,
+                    1,
+                ));
+            };
+            tokens.push(Token {
+                text: chars[i..i + length].iter().collect(),
+                line: start_line,
+                column: start_column,
+            });
+            length
+        };
+        for &c in &chars[i..i + length] {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        i += length;
+    }
+    Ok(tokens)
+}
+
+struct HdlParser<'a> {
+    tokens: Vec<Token>,
+    current: usize,
+    file: &'a str,
+    hdl: &'a str,
+}
+
+impl<'a> HdlParser<'a> {
+    fn chip(&mut self) -> Result<ChipDef, HdlError> {
+        self.expect("CHIP")?;
+        let (name, _) = self.name("a chip name")?;
+        self.expect("{")?;
+        let inputs = if self.accept("IN") {
+            self.pin_decls()?
+        } else {
+            vec![]
+        };
+        let outputs = if self.accept("OUT") {
+            self.pin_decls()?
+        } else {
+            vec![]
+        };
+        let body = if self.accept("BUILTIN") {
+            let (builtin, _) = self.name("a chip name")?;
+            self.expect(";")?;
+            let mut clocked = vec![];
+            if self.accept("CLOCKED") {
+                loop {
+                    clocked.push(self.name("a pin name")?.0);
+                    if !self.accept(",") {
+                        break;
+                    }
+                }
+                self.expect(";")?;
+            }
+            Body::Builtin {
+                name: builtin,
+                clocked,
+            }
+        } else {
+            self.expect("PARTS")?;
+            self.expect(":")?;
+            let mut parts = vec![];
+            while self.peek().is_some_and(|token| token.is_name()) {
+                parts.push(self.part()?);
+            }
+            Body::Parts(parts)
+        };
+        self.expect("}")?;
+        Ok(ChipDef {
+            name,
+            file: self.file.to_string(),
+            inputs,
+            outputs,
+            body,
+        })
+    }
+
+    /// `a, b[16], ...;`
+    fn pin_decls(&mut self) -> Result<Vec<PinDecl>, HdlError> {
+        let mut pins = vec![];
+        loop {
+            let (name, location) = self.name("a pin name")?;
+            let width = if self.accept("[") {
+                let width = self.number()?;
+                if width == 0 || width > MAX_WIDTH {
+                    let message = format!("bus width must be between 1 and {}", MAX_WIDTH);
+                    return Err(HdlError::at(&message, &location, name.len()));
+                }
+                self.expect("]")?;
+                width
+            } else {
+                1
+            };
+            pins.push(PinDecl {
+                name,
+                width,
+                location,
+            });
+            if !self.accept(",") {
+                break;
+            }
+        }
+        self.expect(";")?;
+        Ok(pins)
+    }
+
+    /// `Chip(pin=wire, ...);`
+    fn part(&mut self) -> Result<Part, HdlError> {
+        let (chip, location) = self.name("a chip name")?;
+        self.expect("(")?;
+        let mut connections = vec![];
+        if !self.accept(")") {
+            loop {
+                let pin = self.bus()?;
+                self.expect("=")?;
+                let wire = match self.peek().map(|token| token.text.as_str()) {
+                    Some("true") | Some("false") => {
+                        let token = self.next("`true` or `false`")?;
+                        Wire::Constant(token.text == "true", self.location(&token))
+                    }
+                    _ => Wire::Bus(self.bus()?),
+                };
+                connections.push(Connection { pin, wire });
+                if !self.accept(",") {
+                    break;
+                }
+            }
+            self.expect(")")?;
+        }
+        self.expect(";")?;
+        Ok(Part {
+            chip,
+            connections,
+            location,
+        })
+    }
+
+    /// `a`, `a[3]` or `a[0..7]`
+    fn bus(&mut self) -> Result<Bus, HdlError> {
+        let (name, location) = self.name("a pin name")?;
+        let range = if self.accept("[") {
+            let first = self.number()?;
+            let last = if self.accept("..") {
+                self.number()?
+            } else {
+                first
+            };
+            self.expect("]")?;
+            if last < first {
+                let message = format!("empty sub-bus `{}[{}..{}]`", name, first, last);
+                return Err(HdlError::at(&message, &location, name.len()));
+            }
+            Some((first, last))
+        } else {
+            None
+        };
+        Ok(Bus {
+            name,
+            range,
+            location,
+        })
+    }
+
+    fn name(&mut self, expected: &str) -> Result<(String, Location), HdlError> {
+        let token = self.next(expected)?;
+        if !token.is_name() {
+            return Err(self.unexpected(&token, expected));
+        }
+        Ok((token.text.clone(), self.location(&token)))
+    }
+
+    fn number(&mut self) -> Result<usize, HdlError> {
+        let token = self.next("a number")?;
+        token
+            .text
+            .parse()
+            .map_err(|_| self.unexpected(&token, "a number"))
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), HdlError> {
+        let expected = format!("`{}`", text);
+        let token = self.next(&expected)?;
+        if token.text != text {
+            return Err(self.unexpected(&token, &expected));
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, text: &str) -> bool {
+        if self.peek().is_some_and(|token| token.text == text) {
+            self.current += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<Token, HdlError> {
+        match self.tokens.get(self.current).cloned() {
+            Some(token) => {
+                self.current += 1;
+                Ok(token)
+            }
+            None => {
+                let line = self.hdl.lines().count().max(1);
+                let column = self.hdl.lines().last().unwrap_or("").len() + 1;
+                let location = Location::new(self.file, self.hdl, line, column);
+                let message = format!("expected {}, found end of file", expected);
+                Err(HdlError::at(&message, &location, 1))
+            }
+        }
+    }
+
+    fn location(&self, token: &Token) -> Location {
+        Location::new(self.file, self.hdl, token.line, token.column)
+    }
+
+    fn unexpected(&self, token: &Token, expected: &str) -> HdlError {
+        let message = format!("expected {}, found `{}`", expected, token.text);
+        HdlError::at(&message, &self.location(token), token.text.chars().count())
+    }
+}
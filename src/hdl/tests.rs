@@ -0,0 +1,447 @@
+//! Chips of the projects, built from their `.hdl` files down to Nand and DFF, must follow
+//! their truth tables; broken chips must be reported where they go wrong.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assembler::parser::Parser;
+use crate::assembler::writer::Writer as Assembler;
+use crate::hdl::builtin;
+use crate::hdl::chip::{Chip, Options};
+use crate::hdl::differential;
+use crate::hdl::error::HdlError;
+use crate::vm_emulator::differential::Rng;
+
+/// Adds 10 + 9 + ... + 1 into R0, counting down in R1.
+const SUM: &str = "@10
+D=A
+@R1
+M=D
+(LOOP)
+@R1
+D=M
+@R0
+M=D+M
+@R1
+MD=M-1
+@LOOP
+D;JGT
+(END)
+@END
+0;JMP
+";
+
+/// Directories of the projects whose chips the others are made of.
+const PROJECT_DIRS: [&str; 5] = ["01", "02", "03/a", "03/b", "05"];
+
+fn project_dirs() -> Vec<PathBuf> {
+    PROJECT_DIRS
+        .iter()
+        .map(|dir| {
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("projects")
+                .join(dir)
+        })
+        .collect()
+}
+
+/// The chip at `projects/{path}`, with no builtin parts.
+fn project_chip(path: &str) -> Chip {
+    let options = Options {
+        dirs: project_dirs(),
+        builtins: false,
+        hdl_chips: vec![],
+    };
+    let hdl_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("projects")
+        .join(path);
+    Chip::load(&hdl_path, &options).unwrap()
+}
+
+/// Writes `hdl` to `{name}.hdl` in a scratch directory.
+fn write_hdl(name: &str, hdl: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("nand2tetris-hdl-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let hdl_path = dir.join(format!("{}.hdl", name));
+    fs::create_dir_all(hdl_path.parent().unwrap()).unwrap();
+    fs::write(&hdl_path, hdl).unwrap();
+    hdl_path
+}
+
+/// Builds `hdl`, named `name`, from the project chips.
+fn load_hdl(name: &str, hdl: &str) -> Result<Chip, HdlError> {
+    let hdl_path = write_hdl(name, hdl);
+    let options = Options {
+        dirs: project_dirs(),
+        builtins: false,
+        hdl_chips: vec![],
+    };
+    Chip::load(&hdl_path, &options)
+}
+
+/// Line, column and message of the error building `hdl`.
+fn load_error(name: &str, hdl: &str) -> (usize, usize, String) {
+    let error = load_hdl(name, hdl).err().unwrap();
+    let location = error.location.unwrap();
+    (location.line, location.column, error.message)
+}
+
+#[test]
+fn mux16() {
+    let mut chip = project_chip("01/Mux16.hdl");
+    let rows = [
+        (0x0000, 0x0000, 0, 0x0000),
+        (0x0000, 0x1234, 0, 0x0000),
+        (0x0000, 0x1234, 1, 0x1234),
+        (0x9876, 0x0000, 0, 0x9876),
+        (0x9876, 0x0000, 1, 0x0000),
+        (0xaa55, 0x55aa, 0, 0xaa55),
+        (0xaa55, 0x55aa, 1, 0x55aa),
+    ];
+    for &(a, b, sel, out) in &rows {
+        let outputs = chip.evaluate(&[("a", a), ("b", b), ("sel", sel)]).unwrap();
+        assert_eq!(
+            outputs,
+            vec![("out".to_string(), out)],
+            "a={:x} b={:x} sel={}",
+            a,
+            b,
+            sel
+        );
+    }
+}
+
+#[test]
+fn alu() {
+    let mut chip = project_chip("02/ALU.hdl");
+    // zx nx zy ny f no, and out for x = 17, y = 3.
+    let rows = [
+        ("101010", 0),
+        ("111111", 1),
+        ("111010", -1),
+        ("001100", 17),
+        ("110000", 3),
+        ("001101", !17),
+        ("110001", !3),
+        ("001111", -17),
+        ("110011", -3),
+        ("011111", 18),
+        ("110111", 4),
+        ("001110", 16),
+        ("110010", 2),
+        ("000010", 20),
+        ("010011", 14),
+        ("000111", -14),
+        ("000000", 1),
+        ("010101", 19),
+    ];
+    for &(control, out) in &rows {
+        let mut inputs = vec![("x", 17), ("y", 3)];
+        let names = ["zx", "nx", "zy", "ny", "f", "no"];
+        for (name, bit) in names.iter().zip(control.chars()) {
+            inputs.push((name, (bit == '1') as u16));
+        }
+        let outputs = chip.evaluate(&inputs).unwrap();
+        let expected = vec![
+            ("out".to_string(), out as u16),
+            ("zr".to_string(), (out == 0) as u16),
+            ("ng".to_string(), (out < 0) as u16),
+        ];
+        assert_eq!(outputs, expected, "{}", control);
+    }
+}
+
+#[test]
+fn sub_bus_out_of_range() {
+    let hdl = "CHIP Wide {
+    IN a[16];
+    OUT out[8];
+    PARTS:
+    Not16(in[0..7]=a[8..16], out[0..7]=out);
+}
+";
+    let message = "bit 16 is out of range for pin `a` of width 16".to_string();
+    assert_eq!(load_error("Wide", hdl), (5, 20, message));
+}
+
+#[test]
+fn empty_sub_bus() {
+    let hdl = "CHIP Empty {
+    IN a[16];
+    OUT out;
+    PARTS:
+    Or8Way(in=a[7..0], out=out);
+}
+";
+    let message = "empty sub-bus `a[7..0]`".to_string();
+    assert_eq!(load_error("Empty", hdl), (5, 15, message));
+}
+
+#[test]
+fn pin_fed_twice() {
+    let hdl = "CHIP Twice {
+    IN a, b;
+    OUT out;
+    PARTS:
+    Not(in=a, out=out);
+    Not(in=b, out=out);
+}
+";
+    let message = "`out` is fed by more than one part".to_string();
+    assert_eq!(load_error("Twice", hdl), (6, 19, message));
+}
+
+#[test]
+fn input_connected_twice() {
+    let hdl = "CHIP Both {
+    IN a, b;
+    OUT out;
+    PARTS:
+    Not(in=a, in=b, out=out);
+}
+";
+    let message = "pin `in` is connected twice".to_string();
+    assert_eq!(load_error("Both", hdl), (5, 15, message));
+}
+
+#[test]
+fn unknown_chip() {
+    let hdl = "CHIP Unknown {
+    IN a;
+    OUT out;
+    PARTS:
+    Frobnicate(in=a, out=out);
+}
+";
+    let message = "chip `Frobnicate` not found".to_string();
+    assert_eq!(load_error("Unknown", hdl), (5, 5, message));
+}
+
+/// Sets `inputs` and runs a clock cycle, checking `out` keeps its value until the tock and
+/// shows `out` after it.
+fn cycle(chip: &mut Chip, inputs: &[(&str, u16)], out: u16) {
+    for &(name, value) in inputs {
+        chip.set(name, value).unwrap();
+    }
+    let before = chip.get("out").unwrap();
+    chip.tick();
+    assert_eq!(chip.get("out").unwrap(), before, "tick {:?}", inputs);
+    chip.tock();
+    assert_eq!(chip.get("out").unwrap(), out, "tock {:?}", inputs);
+}
+
+#[test]
+fn bit() {
+    let mut chip = project_chip("03/a/Bit.hdl");
+    // in, load, and out after the cycle.
+    let rows = [
+        (0, 0, 0),
+        (1, 0, 0),
+        (1, 1, 1),
+        (0, 0, 1),
+        (0, 1, 0),
+        (1, 0, 0),
+    ];
+    for &(input, load, out) in &rows {
+        cycle(&mut chip, &[("in", input), ("load", load)], out);
+    }
+}
+
+#[test]
+fn register() {
+    let mut chip = project_chip("03/a/Register.hdl");
+    let rows = [
+        (0, 0, 0),
+        (-32123, 0, 0),
+        (-32123, 1, -32123),
+        (11111, 0, -32123),
+        (-1, 1, -1),
+        (0, 1, 0),
+    ];
+    for &(input, load, out) in &rows {
+        cycle(
+            &mut chip,
+            &[("in", input as u16), ("load", load)],
+            out as u16,
+        );
+    }
+}
+
+#[test]
+fn pc() {
+    let mut chip = project_chip("03/a/PC.hdl");
+    // in, reset, load, inc, and out after the cycle: reset wins over load, load over inc.
+    let rows = [
+        (0, 0, 0, 0, 0),
+        (0, 0, 0, 1, 1),
+        (-32123, 0, 0, 1, 2),
+        (-32123, 0, 1, 1, -32123),
+        (-32123, 0, 0, 1, -32122),
+        (12345, 1, 1, 1, 0),
+        (12345, 0, 1, 1, 12345),
+        (12345, 1, 1, 1, 0),
+        (12345, 0, 0, 1, 1),
+        (12345, 1, 0, 1, 0),
+        (0, 0, 1, 1, 0),
+        (0, 0, 0, 1, 1),
+        (22222, 1, 0, 0, 0),
+    ];
+    for &(input, reset, load, inc, out) in &rows {
+        let inputs = [
+            ("in", input as u16),
+            ("reset", reset),
+            ("load", load),
+            ("inc", inc),
+        ];
+        cycle(&mut chip, &inputs, out as u16);
+    }
+}
+
+#[test]
+fn combinational_loop() {
+    let hdl = "CHIP Loop {
+    IN a;
+    OUT out;
+    PARTS:
+    Nand(a=a, b=back, out=x);
+    Not(in=x, out=back);
+    Nand(a=back, b=back, out=out);
+}
+";
+    let error = load_hdl("Loop", hdl).err().unwrap();
+    assert_eq!(error.location, None);
+    assert_eq!(
+        error.message,
+        "combinational loop: Loop.x -> Loop.back -> Loop.x"
+    );
+}
+
+/// `value` cut to `width` bits.
+fn mask(value: u64, width: usize) -> u16 {
+    (value & ((1 << width) - 1)) as u16
+}
+
+/// Every builtin chip with a `.hdl` file in the projects must behave like it, as parts do
+/// with `--use-hdl`: both get the same random inputs and clock cycles, and their outputs must
+/// agree after every `eval`, `tick` and `tock`.
+#[test]
+fn builtins_match_their_hdl() {
+    let mut rng = Rng::new(7);
+    for name in builtin::names() {
+        let file_name = format!("{}.hdl", name);
+        if !project_dirs()
+            .iter()
+            .any(|dir| dir.join(&file_name).is_file())
+        {
+            continue;
+        }
+        let spec = builtin::spec(name).unwrap();
+        let connections: Vec<String> = spec
+            .inputs
+            .iter()
+            .chain(spec.outputs)
+            .map(|(pin, _)| format!("{}={}", pin, pin))
+            .collect();
+        let declare = |pins: &[(&str, usize)]| {
+            let declared: Vec<String> = pins
+                .iter()
+                .map(|&(pin, width)| match width {
+                    1 => pin.to_string(),
+                    _ => format!("{}[{}]", pin, width),
+                })
+                .collect();
+            declared.join(", ")
+        };
+        let hdl = format!(
+            "CHIP Wrap{name} {{\n    IN {};\n    OUT {};\n    PARTS:\n    {name}({});\n}}\n",
+            declare(spec.inputs),
+            declare(spec.outputs),
+            connections.join(", "),
+            name = name
+        );
+        let hdl_path = write_hdl(&format!("Wrap{}", name), &hdl);
+        let mut options = Options {
+            dirs: project_dirs(),
+            builtins: true,
+            hdl_chips: vec![],
+        };
+        let mut native = Chip::load(&hdl_path, &options).unwrap();
+        options.hdl_chips = vec![name.to_string()];
+        let mut from_hdl = Chip::load(&hdl_path, &options).unwrap();
+        assert!(native.builtin(name).is_some(), "{}", name);
+        assert!(from_hdl.builtin(name).is_none(), "{}", name);
+
+        // A few addresses, so that memories read back what they were written.
+        let addresses: Vec<u64> = (0..4).map(|_| rng.next_u64()).collect();
+        let compare = |native: &Chip, from_hdl: &Chip, step: &str| {
+            for (pin, _) in spec.outputs {
+                let (expected, actual) = (native.get(pin).unwrap(), from_hdl.get(pin).unwrap());
+                assert_eq!(actual, expected, "{}.{} at {}", name, pin, step);
+            }
+        };
+        for cycle in 0..100 {
+            for &(pin, width) in spec.inputs {
+                let value = match pin {
+                    "address" => addresses[rng.below(addresses.len())],
+                    _ => rng.next_u64(),
+                };
+                native.set(pin, mask(value, width)).unwrap();
+                from_hdl.set(pin, mask(value, width)).unwrap();
+            }
+            native.eval();
+            from_hdl.eval();
+            compare(&native, &from_hdl, &format!("eval {}", cycle));
+            native.tick();
+            from_hdl.tick();
+            compare(&native, &from_hdl, &format!("tick {}", cycle));
+            native.tock();
+            from_hdl.tock();
+            compare(&native, &from_hdl, &format!("tock {}", cycle));
+        }
+    }
+}
+
+/// Runs `SUM` on the `Computer.hdl` at `hdl_path` next to the CPU emulator, with PC built
+/// from its `.hdl` file.
+fn run_sum(hdl_path: &Path, cycles: u64) -> (Chip, Result<u64, Box<differential::Divergence>>) {
+    let options = Options {
+        dirs: project_dirs(),
+        builtins: true,
+        hdl_chips: vec!["PC".to_string()],
+    };
+    let mut computer = Chip::load(hdl_path, &options).unwrap();
+    assert!(computer.builtin("PC").is_none());
+    let words = Assembler::assemble(Parser::from_str("Sum.asm", SUM)).unwrap();
+    let options = differential::Options {
+        cycles,
+        ..Default::default()
+    };
+    let result = differential::compare(&mut computer, &words, &options);
+    (computer, result)
+}
+
+#[test]
+fn computer_with_hdl_pc() {
+    let hdl_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("projects/05/Computer.hdl");
+    let (computer, result) = run_sum(&hdl_path, 100);
+    assert_eq!(result.unwrap(), 100);
+    assert_eq!(computer.get("RAM16K[0]"), Ok(55));
+    assert_eq!(computer.get("RAM16K[1]"), Ok(0));
+}
+
+#[test]
+fn computer_with_broken_cpu() {
+    // The jump bits are swapped: JEQ tests j1 and JGT tests j2.
+    let cpu = include_str!("../../projects/05/CPU.hdl")
+        .replace("And(a=zr, b=cInstruction1", "And(a=zr, b=cInstruction2")
+        .replace("And(a=pg, b=cInstruction0", "And(a=pg, b=cInstruction1");
+    write_hdl("broken/CPU", &cpu);
+    let computer = include_str!("../../projects/05/Computer.hdl");
+    let hdl_path = write_hdl("broken/Computer", computer);
+    let (_, result) = run_sum(&hdl_path, 100);
+    let divergence = result.unwrap_err();
+    // The first `D;JGT`, at ROM[11], falls through.
+    assert_eq!(divergence.cycle, Some(12));
+    assert_eq!(divergence.pc, 11);
+    assert_eq!(divergence.instruction, 0xe301);
+}
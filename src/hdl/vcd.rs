@@ -0,0 +1,209 @@
+use std::io::{self, Write};
+
+use crate::hdl::chip::{Chip, Probe};
+
+/// Writes the pins of a chip over time as a Value Change Dump, the `.vcd` files waveform
+/// viewers such as GTKWave read.
+///
+/// Each chip of the hierarchy gets a scope of its own, and the chip's scope a `clk` signal,
+/// high from a `tick` to the next `tock`. Times are whatever the caller counts in, such as
+/// the steps of a `.tst` script.
+pub struct Recorder<W: Write> {
+    out: W,
+    probes: Vec<Probe>,
+    /// Identifier codes of `clk` and then of each probe.
+    codes: Vec<String>,
+    /// Values last written, `clk` first.
+    values: Vec<Option<u16>>,
+    /// Time of the last `#` line written.
+    time: Option<u64>,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Writes the header declaring the pins of `chip`, with `internal` the internal ones too,
+    /// to `out`.
+    pub fn new(mut out: W, chip: &Chip, internal: bool) -> io::Result<Self> {
+        let probes = chip.probes(internal);
+        let codes: Vec<String> = (0..=probes.len()).map(code).collect();
+        writeln!(out, "$version nand2tetris $end")?;
+        writeln!(out, "$timescale 1ns $end")?;
+        writeln!(out, "$scope module {} $end", chip.name())?;
+        writeln!(out, "$var wire 1 {} clk $end", codes[0])?;
+        let mut scopes = vec![chip.name()];
+        for (probe, code) in probes.iter().zip(&codes[1..]) {
+            let mut path: Vec<&str> = probe.name.split('.').collect();
+            let pin = path.pop().unwrap();
+            let common = scopes
+                .iter()
+                .zip(&path)
+                .take_while(|(scope, part)| scope == part)
+                .count();
+            for _ in common..scopes.len() {
+                writeln!(out, "$upscope $end")?;
+            }
+            for part in &path[common..] {
+                writeln!(out, "$scope module {} $end", part)?;
+            }
+            scopes = path;
+            match probe.width {
+                1 => writeln!(out, "$var wire 1 {} {} $end", code, pin)?,
+                width => writeln!(
+                    out,
+                    "$var wire {} {} {} [{}:0] $end",
+                    width,
+                    code,
+                    pin,
+                    width - 1
+                )?,
+            }
+        }
+        for _ in 0..scopes.len() {
+            writeln!(out, "$upscope $end")?;
+        }
+        writeln!(out, "$enddefinitions $end")?;
+        Ok(Self {
+            out,
+            values: vec![None; codes.len()],
+            probes,
+            codes,
+            time: None,
+        })
+    }
+
+    /// Writes the pins of `chip` that changed since the last call, at `time`, which must not
+    /// be earlier than that of the last call. The first call dumps every pin.
+    pub fn record(&mut self, time: u64, chip: &Chip) -> io::Result<()> {
+        let first = self.time.is_none();
+        let values = std::iter::once(chip.clock() as u16)
+            .chain(self.probes.iter().map(|probe| chip.read(probe)));
+        for (index, value) in values.enumerate() {
+            if self.values[index] == Some(value) {
+                continue;
+            }
+            if self.time != Some(time) {
+                writeln!(self.out, "#{}", time)?;
+                if first {
+                    writeln!(self.out, "$dumpvars")?;
+                }
+                self.time = Some(time);
+            }
+            let width = match index {
+                0 => 1,
+                _ => self.probes[index - 1].width,
+            };
+            match width {
+                1 => writeln!(self.out, "{}{}", value, self.codes[index])?,
+                _ => writeln!(self.out, "b{:0w$b} {}", value, self.codes[index], w = width)?,
+            }
+            self.values[index] = Some(value);
+        }
+        if first {
+            writeln!(self.out, "$end")?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the dump and returns where it was written.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Identifier code of the signal numbered `index`: printable characters from `!`, in base 94.
+fn code(mut index: usize) -> String {
+    let mut code = String::new();
+    loop {
+        code.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            return code;
+        }
+        index -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hdl::chip::Options;
+    use std::path::Path;
+
+    /// Records PC counting up from 0 over two clock cycles.
+    fn record_pc(internal: bool) -> String {
+        let hdl_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("projects/03/a/PC.hdl");
+        let mut chip = Chip::load(&hdl_path, &Options::default()).unwrap();
+        let mut recorder = Recorder::new(vec![], &chip, internal).unwrap();
+        recorder.record(0, &chip).unwrap();
+        chip.set("inc", 1).unwrap();
+        for time in 1..=4 {
+            if time % 2 == 1 {
+                chip.tick();
+            } else {
+                chip.tock();
+            }
+            recorder.record(time, &chip).unwrap();
+        }
+        String::from_utf8(recorder.into_inner().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn pc() {
+        let vcd = "$version nand2tetris $end
+$timescale 1ns $end
+$scope module PC $end
+$var wire 1 ! clk $end
+$var wire 16 \" in [15:0] $end
+$var wire 1 # load $end
+$var wire 1 $ inc $end
+$var wire 1 % reset $end
+$var wire 16 & out [15:0] $end
+$upscope $end
+$enddefinitions $end
+#0
+$dumpvars
+0!
+b0000000000000000 \"
+0#
+0$
+0%
+b0000000000000000 &
+$end
+#1
+1!
+1$
+#2
+0!
+b0000000000000001 &
+#3
+1!
+#4
+0!
+b0000000000000010 &
+";
+        assert_eq!(record_pc(false), vcd);
+    }
+
+    #[test]
+    fn pc_internal() {
+        let vcd = record_pc(true);
+        // The parts get a scope each, inside that of the chip.
+        let scopes = "$var wire 16 ) outcopy [15:0] $end
+$scope module Inc16 $end
+$var wire 16 * in [15:0] $end
+$var wire 16 + out [15:0] $end
+$upscope $end
+$scope module Mux8Way16 $end
+";
+        assert!(vcd.contains(scopes), "{}", vcd);
+        assert!(vcd.contains("$upscope $end\n$upscope $end\n$enddefinitions $end\n"));
+        // The register takes in 1 at the tick but shows it only from the tock.
+        assert!(vcd.contains("#1\n1!\n1$\nb0000000000000001 (\n"), "{}", vcd);
+        // Only the pins that changed are written.
+        assert!(
+            vcd.contains("#3\n1!\n#4\n0!\nb0000000000000010 &\n"),
+            "{}",
+            vcd
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use crate::jack_compiler::tokenizer::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: String,
+    pub class_var_decs: Vec<ClassVarDec>,
+    pub subroutine_decs: Vec<SubroutineDec>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassVarKind {
+    Static,
+    Field,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassVarDec {
+    pub kind: ClassVarKind,
+    pub var_type: Type,
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Int,
+    Char,
+    Boolean,
+    Class(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubroutineKind {
+    Constructor,
+    Function,
+    Method,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineDec {
+    pub kind: SubroutineKind,
+    /// `None` for `void`.
+    pub return_type: Option<Type>,
+    pub name: String,
+    pub parameters: Vec<(Type, String)>,
+    pub body: SubroutineBody,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineBody {
+    pub var_decs: Vec<VarDec>,
+    pub statements: Vec<Statement>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDec {
+    pub var_type: Type,
+    pub names: Vec<String>,
+}
+
+/// A name used inside a subroutine body, with where it appears.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Let {
+        name: Identifier,
+        index: Option<Expression>,
+        value: Expression,
+    },
+    If {
+        condition: Expression,
+        then_statements: Vec<Statement>,
+        else_statements: Option<Vec<Statement>>,
+    },
+    While {
+        condition: Expression,
+        statements: Vec<Statement>,
+    },
+    Do(SubroutineCall),
+    Return(Option<Expression>),
+}
+
+/// Jack has no operator precedence, so an expression is a term followed by
+/// `(op, term)` pairs evaluated from left to right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub term: Term,
+    pub ops: Vec<(BinaryOp, Term)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    IntegerConstant(u16),
+    StringConstant(String),
+    KeywordConstant(KeywordConstant),
+    Variable(Identifier),
+    Index(Identifier, Box<Expression>),
+    Call(SubroutineCall),
+    Parenthesized(Box<Expression>),
+    Unary(UnaryOp, Box<Term>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeywordConstant {
+    True,
+    False,
+    Null,
+    This,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Lt,
+    Gt,
+    Eq,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+            BinaryOp::And => '&',
+            BinaryOp::Or => '|',
+            BinaryOp::Lt => '<',
+            BinaryOp::Gt => '>',
+            BinaryOp::Eq => '=',
+        }
+    }
+
+    pub fn from_symbol(symbol: char) -> Option<Self> {
+        match symbol {
+            '+' => Some(BinaryOp::Add),
+            '-' => Some(BinaryOp::Sub),
+            '*' => Some(BinaryOp::Mul),
+            '/' => Some(BinaryOp::Div),
+            '&' => Some(BinaryOp::And),
+            '|' => Some(BinaryOp::Or),
+            '<' => Some(BinaryOp::Lt),
+            '>' => Some(BinaryOp::Gt),
+            '=' => Some(BinaryOp::Eq),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+impl UnaryOp {
+    pub fn symbol(&self) -> char {
+        match self {
+            UnaryOp::Neg => '-',
+            UnaryOp::Not => '~',
+        }
+    }
+}
+
+/// `name(arguments)` or `receiver.name(arguments)`, where `receiver` is a class or a variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubroutineCall {
+    pub receiver: Option<Identifier>,
+    pub name: Identifier,
+    pub arguments: Vec<Expression>,
+}
@@ -0,0 +1,318 @@
+use std::fs;
+
+use crate::jack_compiler::ast::*;
+use crate::jack_compiler::compilation_engine::CompilationEngine;
+use crate::jack_compiler::error::{JackError, Location};
+use crate::jack_compiler::symbol_table::{Kind, SymbolTable};
+use crate::jack_compiler::vm_writer::VmWriter;
+
+/// Walks the AST of a class and emits its VM code.
+pub struct CodeGenerator {
+    jack_path: String,
+    source: String,
+    class_name: String,
+    symbol_table: SymbolTable,
+    writer: VmWriter,
+    subroutine_kind: SubroutineKind,
+    n_label: usize,
+}
+
+impl CodeGenerator {
+    /// `source` is the text of `jack_path`, used for error messages.
+    pub fn new(jack_path: &str, source: &str) -> Self {
+        Self {
+            jack_path: jack_path.to_string(),
+            source: source.to_string(),
+            class_name: "".to_string(),
+            symbol_table: SymbolTable::new(),
+            writer: VmWriter::new(),
+            subroutine_kind: SubroutineKind::Function,
+            n_label: 0,
+        }
+    }
+
+    /// Returns the VM code of `class`.
+    pub fn generate(mut self, class: &Class) -> Result<String, JackError> {
+        self.class_name = class.name.clone();
+        for class_var_dec in &class.class_var_decs {
+            let kind = match class_var_dec.kind {
+                ClassVarKind::Static => Kind::Static,
+                ClassVarKind::Field => Kind::Field,
+            };
+            for name in &class_var_dec.names {
+                self.symbol_table
+                    .define(name, class_var_dec.var_type.clone(), kind);
+            }
+        }
+        for subroutine_dec in &class.subroutine_decs {
+            self.subroutine_dec(subroutine_dec)?;
+        }
+        Ok(self.writer.close())
+    }
+
+    fn subroutine_dec(&mut self, subroutine_dec: &SubroutineDec) -> Result<(), JackError> {
+        self.symbol_table.start_subroutine();
+        self.subroutine_kind = subroutine_dec.kind;
+        self.n_label = 0;
+        if subroutine_dec.kind == SubroutineKind::Method {
+            self.symbol_table
+                .define("this", Type::Class(self.class_name.clone()), Kind::Arg);
+        }
+        for (var_type, name) in &subroutine_dec.parameters {
+            self.symbol_table.define(name, var_type.clone(), Kind::Arg);
+        }
+        for var_dec in &subroutine_dec.body.var_decs {
+            for name in &var_dec.names {
+                self.symbol_table
+                    .define(name, var_dec.var_type.clone(), Kind::Var);
+            }
+        }
+
+        self.writer.write_function(
+            &format!("{}.{}", self.class_name, subroutine_dec.name),
+            self.symbol_table.var_count(Kind::Var),
+        );
+        match subroutine_dec.kind {
+            SubroutineKind::Constructor => {
+                self.writer
+                    .write_push("constant", self.symbol_table.var_count(Kind::Field));
+                self.writer.write_call("Memory.alloc", 1);
+                self.writer.write_pop("pointer", 0);
+            }
+            SubroutineKind::Method => {
+                self.writer.write_push("argument", 0);
+                self.writer.write_pop("pointer", 0);
+            }
+            SubroutineKind::Function => {}
+        }
+        self.statements(&subroutine_dec.body.statements)
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), JackError> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), JackError> {
+        match statement {
+            Statement::Let {
+                name,
+                index: None,
+                value,
+            } => {
+                self.expression(value)?;
+                let (segment, index) = self.variable(name)?;
+                self.writer.write_pop(segment, index);
+            }
+            Statement::Let {
+                name,
+                index: Some(index),
+                value,
+            } => {
+                let (segment, i) = self.variable(name)?;
+                self.writer.write_push(segment, i);
+                self.expression(index)?;
+                self.writer.write_arithmetic("add");
+                // `value` may itself use `that`, so the address stays on the stack meanwhile.
+                self.expression(value)?;
+                self.writer.write_pop("temp", 0);
+                self.writer.write_pop("pointer", 1);
+                self.writer.write_push("temp", 0);
+                self.writer.write_pop("that", 0);
+            }
+            Statement::If {
+                condition,
+                then_statements,
+                else_statements,
+            } => {
+                let n = self.next_label();
+                let (else_label, end_label) = (format!("IF_ELSE{}", n), format!("IF_END{}", n));
+                self.expression(condition)?;
+                self.writer.write_arithmetic("not");
+                self.writer.write_if(&else_label);
+                self.statements(then_statements)?;
+                match else_statements {
+                    Some(else_statements) => {
+                        self.writer.write_goto(&end_label);
+                        self.writer.write_label(&else_label);
+                        self.statements(else_statements)?;
+                        self.writer.write_label(&end_label);
+                    }
+                    None => self.writer.write_label(&else_label),
+                }
+            }
+            Statement::While {
+                condition,
+                statements,
+            } => {
+                let n = self.next_label();
+                let (exp_label, end_label) = (format!("WHILE_EXP{}", n), format!("WHILE_END{}", n));
+                self.writer.write_label(&exp_label);
+                self.expression(condition)?;
+                self.writer.write_arithmetic("not");
+                self.writer.write_if(&end_label);
+                self.statements(statements)?;
+                self.writer.write_goto(&exp_label);
+                self.writer.write_label(&end_label);
+            }
+            Statement::Do(call) => {
+                self.subroutine_call(call)?;
+                self.writer.write_pop("temp", 0);
+            }
+            Statement::Return(value) => {
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.writer.write_push("constant", 0),
+                }
+                self.writer.write_return();
+            }
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<(), JackError> {
+        self.term(&expression.term)?;
+        for (op, term) in &expression.ops {
+            self.term(term)?;
+            match op {
+                BinaryOp::Add => self.writer.write_arithmetic("add"),
+                BinaryOp::Sub => self.writer.write_arithmetic("sub"),
+                BinaryOp::Mul => self.writer.write_call("Math.multiply", 2),
+                BinaryOp::Div => self.writer.write_call("Math.divide", 2),
+                BinaryOp::And => self.writer.write_arithmetic("and"),
+                BinaryOp::Or => self.writer.write_arithmetic("or"),
+                BinaryOp::Lt => self.writer.write_arithmetic("lt"),
+                BinaryOp::Gt => self.writer.write_arithmetic("gt"),
+                BinaryOp::Eq => self.writer.write_arithmetic("eq"),
+            }
+        }
+        Ok(())
+    }
+
+    fn term(&mut self, term: &Term) -> Result<(), JackError> {
+        match term {
+            Term::IntegerConstant(value) => self.writer.write_push("constant", *value as usize),
+            Term::StringConstant(value) => {
+                self.writer.write_push("constant", value.chars().count());
+                self.writer.write_call("String.new", 1);
+                for c in value.chars() {
+                    self.writer.write_push("constant", c as usize);
+                    self.writer.write_call("String.appendChar", 2);
+                }
+            }
+            Term::KeywordConstant(KeywordConstant::True) => {
+                self.writer.write_push("constant", 0);
+                self.writer.write_arithmetic("not");
+            }
+            Term::KeywordConstant(KeywordConstant::False)
+            | Term::KeywordConstant(KeywordConstant::Null) => self.writer.write_push("constant", 0),
+            Term::KeywordConstant(KeywordConstant::This) => self.writer.write_push("pointer", 0),
+            Term::Variable(name) => {
+                let (segment, index) = self.variable(name)?;
+                self.writer.write_push(segment, index);
+            }
+            Term::Index(name, index) => {
+                let (segment, i) = self.variable(name)?;
+                self.writer.write_push(segment, i);
+                self.expression(index)?;
+                self.writer.write_arithmetic("add");
+                self.writer.write_pop("pointer", 1);
+                self.writer.write_push("that", 0);
+            }
+            Term::Call(call) => self.subroutine_call(call)?,
+            Term::Parenthesized(expression) => self.expression(expression)?,
+            Term::Unary(op, term) => {
+                self.term(term)?;
+                match op {
+                    UnaryOp::Neg => self.writer.write_arithmetic("neg"),
+                    UnaryOp::Not => self.writer.write_arithmetic("not"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn subroutine_call(&mut self, call: &SubroutineCall) -> Result<(), JackError> {
+        let (class_name, n_this) = match &call.receiver {
+            // `f(...)` is a method call on `this`.
+            None => {
+                if self.subroutine_kind == SubroutineKind::Function {
+                    return Err(JackError::MethodCallInFunction {
+                        name: call.name.name.clone(),
+                        location: self.location(&call.name),
+                    });
+                }
+                self.writer.write_push("pointer", 0);
+                (self.class_name.clone(), 1)
+            }
+            Some(receiver) => match self.symbol_table.type_of(&receiver.name) {
+                // `obj.f(...)` is a method call on a variable.
+                Some(var_type) => {
+                    let class_name = match var_type {
+                        Type::Class(name) => name.clone(),
+                        Type::Int => "int".to_string(),
+                        Type::Char => "char".to_string(),
+                        Type::Boolean => "boolean".to_string(),
+                    };
+                    let (segment, index) = self.variable(receiver)?;
+                    self.writer.write_push(segment, index);
+                    (class_name, 1)
+                }
+                // `Class.f(...)` is a function or constructor call.
+                None => (receiver.name.clone(), 0),
+            },
+        };
+        for argument in &call.arguments {
+            self.expression(argument)?;
+        }
+        self.writer.write_call(
+            &format!("{}.{}", class_name, call.name.name),
+            call.arguments.len() + n_this,
+        );
+        Ok(())
+    }
+
+    /// Segment and index of a variable.
+    fn variable(&self, name: &Identifier) -> Result<(&'static str, usize), JackError> {
+        match (
+            self.symbol_table.kind_of(&name.name),
+            self.symbol_table.index_of(&name.name),
+        ) {
+            (Some(kind), Some(index)) => Ok((kind.segment(), index)),
+            _ => Err(JackError::UndefinedVariable {
+                name: name.name.clone(),
+                location: self.location(name),
+            }),
+        }
+    }
+
+    fn next_label(&mut self) -> usize {
+        self.n_label += 1;
+        self.n_label - 1
+    }
+
+    fn location(&self, name: &Identifier) -> Location {
+        Location::new(
+            &self.jack_path,
+            &self.source,
+            name.span.line,
+            name.span.column,
+        )
+    }
+}
+
+/// Compiles `jack_path` into `vm_path`.
+pub fn compile_file(jack_path: &str, vm_path: &str) -> Result<(), JackError> {
+    let source = fs::read_to_string(jack_path).map_err(|error| JackError::Io {
+        file: jack_path.to_string(),
+        error,
+    })?;
+    let class = CompilationEngine::from_source(jack_path, &source)?.compile_class()?;
+    let vm = CodeGenerator::new(jack_path, &source).generate(&class)?;
+    fs::write(vm_path, vm).map_err(|error| JackError::Io {
+        file: vm_path.to_string(),
+        error,
+    })
+}
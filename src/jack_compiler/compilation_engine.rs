@@ -0,0 +1,440 @@
+use std::fs;
+
+use crate::jack_compiler::ast::*;
+use crate::jack_compiler::error::{JackError, Location};
+use crate::jack_compiler::tokenizer::{Keyword, Token, TokenKind, Tokenizer};
+
+/// Recursive-descent parser turning the tokens of one `.jack` file into a [`Class`].
+pub struct CompilationEngine {
+    jack_path: String,
+    source: String,
+    tokenizer: Tokenizer,
+}
+
+impl CompilationEngine {
+    pub fn new(jack_path: &str) -> Result<Self, JackError> {
+        let source = fs::read_to_string(jack_path).map_err(|error| JackError::Io {
+            file: jack_path.to_string(),
+            error,
+        })?;
+        Self::from_source(jack_path, &source)
+    }
+
+    /// Parses `source`, reporting errors against the file name `jack_path`.
+    pub fn from_source(jack_path: &str, source: &str) -> Result<Self, JackError> {
+        Ok(Self {
+            jack_path: jack_path.to_string(),
+            source: source.to_string(),
+            tokenizer: Tokenizer::from_source(jack_path, source)?,
+        })
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    /// 'class' className '{' classVarDec* subroutineDec* '}'
+    pub fn compile_class(&mut self) -> Result<Class, JackError> {
+        self.expect_keyword(Keyword::Class)?;
+        let name = self.expect_identifier()?.name;
+        self.expect_symbol('{')?;
+        let mut class_var_decs = vec![];
+        while self.peek_keyword(Keyword::Static) || self.peek_keyword(Keyword::Field) {
+            class_var_decs.push(self.compile_class_var_dec()?);
+        }
+        let mut subroutine_decs = vec![];
+        while self.peek_keyword(Keyword::Constructor)
+            || self.peek_keyword(Keyword::Function)
+            || self.peek_keyword(Keyword::Method)
+        {
+            subroutine_decs.push(self.compile_subroutine()?);
+        }
+        self.expect_symbol('}')?;
+        if let Some(token) = self.tokenizer.peek().cloned() {
+            return Err(self.unexpected(&token, "end of file"));
+        }
+        Ok(Class {
+            name,
+            class_var_decs,
+            subroutine_decs,
+        })
+    }
+
+    /// ('static' | 'field') type varName (',' varName)* ';'
+    fn compile_class_var_dec(&mut self) -> Result<ClassVarDec, JackError> {
+        let kind = if self.peek_keyword(Keyword::Static) {
+            ClassVarKind::Static
+        } else {
+            ClassVarKind::Field
+        };
+        self.next("`static` or `field`")?;
+        let var_type = self.compile_type()?;
+        let names = self.compile_var_names()?;
+        Ok(ClassVarDec {
+            kind,
+            var_type,
+            names,
+        })
+    }
+
+    /// ('constructor' | 'function' | 'method') ('void' | type) subroutineName
+    /// '(' parameterList ')' subroutineBody
+    fn compile_subroutine(&mut self) -> Result<SubroutineDec, JackError> {
+        let token = self.next("`constructor`, `function` or `method`")?;
+        let kind = match token.kind {
+            TokenKind::Keyword(Keyword::Constructor) => SubroutineKind::Constructor,
+            TokenKind::Keyword(Keyword::Function) => SubroutineKind::Function,
+            _ => SubroutineKind::Method,
+        };
+        let return_type = if self.peek_keyword(Keyword::Void) {
+            self.next("`void`")?;
+            None
+        } else {
+            Some(self.compile_type()?)
+        };
+        let name = self.expect_identifier()?.name;
+        self.expect_symbol('(')?;
+        let parameters = self.compile_parameter_list()?;
+        self.expect_symbol(')')?;
+        let body = self.compile_subroutine_body()?;
+        Ok(SubroutineDec {
+            kind,
+            return_type,
+            name,
+            parameters,
+            body,
+        })
+    }
+
+    /// ((type varName) (',' type varName)*)?
+    fn compile_parameter_list(&mut self) -> Result<Vec<(Type, String)>, JackError> {
+        let mut parameters = vec![];
+        if self.peek_symbol(')') {
+            return Ok(parameters);
+        }
+        loop {
+            let var_type = self.compile_type()?;
+            let name = self.expect_identifier()?.name;
+            parameters.push((var_type, name));
+            if !self.peek_symbol(',') {
+                return Ok(parameters);
+            }
+            self.next("`,`")?;
+        }
+    }
+
+    /// '{' varDec* statements '}'
+    fn compile_subroutine_body(&mut self) -> Result<SubroutineBody, JackError> {
+        self.expect_symbol('{')?;
+        let mut var_decs = vec![];
+        while self.peek_keyword(Keyword::Var) {
+            var_decs.push(self.compile_var_dec()?);
+        }
+        let statements = self.compile_statements()?;
+        self.expect_symbol('}')?;
+        Ok(SubroutineBody {
+            var_decs,
+            statements,
+        })
+    }
+
+    /// 'var' type varName (',' varName)* ';'
+    fn compile_var_dec(&mut self) -> Result<VarDec, JackError> {
+        self.expect_keyword(Keyword::Var)?;
+        let var_type = self.compile_type()?;
+        let names = self.compile_var_names()?;
+        Ok(VarDec { var_type, names })
+    }
+
+    /// varName (',' varName)* ';'
+    fn compile_var_names(&mut self) -> Result<Vec<String>, JackError> {
+        let mut names = vec![self.expect_identifier()?.name];
+        while self.peek_symbol(',') {
+            self.next("`,`")?;
+            names.push(self.expect_identifier()?.name);
+        }
+        self.expect_symbol(';')?;
+        Ok(names)
+    }
+
+    /// 'int' | 'char' | 'boolean' | className
+    fn compile_type(&mut self) -> Result<Type, JackError> {
+        let token = self.next("a type")?;
+        match token.kind {
+            TokenKind::Keyword(Keyword::Int) => Ok(Type::Int),
+            TokenKind::Keyword(Keyword::Char) => Ok(Type::Char),
+            TokenKind::Keyword(Keyword::Boolean) => Ok(Type::Boolean),
+            TokenKind::Identifier(name) => Ok(Type::Class(name)),
+            _ => Err(self.unexpected(&token, "a type")),
+        }
+    }
+
+    /// statement*
+    fn compile_statements(&mut self) -> Result<Vec<Statement>, JackError> {
+        let mut statements = vec![];
+        loop {
+            let keyword = match self.tokenizer.peek() {
+                Some(Token {
+                    kind: TokenKind::Keyword(keyword),
+                    ..
+                }) => *keyword,
+                _ => return Ok(statements),
+            };
+            let statement = match keyword {
+                Keyword::Let => self.compile_let()?,
+                Keyword::If => self.compile_if()?,
+                Keyword::While => self.compile_while()?,
+                Keyword::Do => self.compile_do()?,
+                Keyword::Return => self.compile_return()?,
+                _ => return Ok(statements),
+            };
+            statements.push(statement);
+        }
+    }
+
+    /// 'let' varName ('[' expression ']')? '=' expression ';'
+    fn compile_let(&mut self) -> Result<Statement, JackError> {
+        self.expect_keyword(Keyword::Let)?;
+        let name = self.expect_identifier()?;
+        let index = if self.peek_symbol('[') {
+            self.next("`[`")?;
+            let index = self.compile_expression()?;
+            self.expect_symbol(']')?;
+            Some(index)
+        } else {
+            None
+        };
+        self.expect_symbol('=')?;
+        let value = self.compile_expression()?;
+        self.expect_symbol(';')?;
+        Ok(Statement::Let { name, index, value })
+    }
+
+    /// 'if' '(' expression ')' '{' statements '}' ('else' '{' statements '}')?
+    fn compile_if(&mut self) -> Result<Statement, JackError> {
+        self.expect_keyword(Keyword::If)?;
+        let condition = self.compile_condition()?;
+        let then_statements = self.compile_block()?;
+        let else_statements = if self.peek_keyword(Keyword::Else) {
+            self.next("`else`")?;
+            Some(self.compile_block()?)
+        } else {
+            None
+        };
+        Ok(Statement::If {
+            condition,
+            then_statements,
+            else_statements,
+        })
+    }
+
+    /// 'while' '(' expression ')' '{' statements '}'
+    fn compile_while(&mut self) -> Result<Statement, JackError> {
+        self.expect_keyword(Keyword::While)?;
+        let condition = self.compile_condition()?;
+        let statements = self.compile_block()?;
+        Ok(Statement::While {
+            condition,
+            statements,
+        })
+    }
+
+    /// '(' expression ')'
+    fn compile_condition(&mut self) -> Result<Expression, JackError> {
+        self.expect_symbol('(')?;
+        let condition = self.compile_expression()?;
+        self.expect_symbol(')')?;
+        Ok(condition)
+    }
+
+    /// '{' statements '}'
+    fn compile_block(&mut self) -> Result<Vec<Statement>, JackError> {
+        self.expect_symbol('{')?;
+        let statements = self.compile_statements()?;
+        self.expect_symbol('}')?;
+        Ok(statements)
+    }
+
+    /// 'do' subroutineCall ';'
+    fn compile_do(&mut self) -> Result<Statement, JackError> {
+        self.expect_keyword(Keyword::Do)?;
+        let name = self.expect_identifier()?;
+        let call = self.compile_subroutine_call(name)?;
+        self.expect_symbol(';')?;
+        Ok(Statement::Do(call))
+    }
+
+    /// 'return' expression? ';'
+    fn compile_return(&mut self) -> Result<Statement, JackError> {
+        self.expect_keyword(Keyword::Return)?;
+        let value = if self.peek_symbol(';') {
+            None
+        } else {
+            Some(self.compile_expression()?)
+        };
+        self.expect_symbol(';')?;
+        Ok(Statement::Return(value))
+    }
+
+    /// term (op term)*
+    fn compile_expression(&mut self) -> Result<Expression, JackError> {
+        let term = self.compile_term()?;
+        let mut ops = vec![];
+        while let Some(Token {
+            kind: TokenKind::Symbol(symbol),
+            ..
+        }) = self.tokenizer.peek()
+        {
+            let op = match BinaryOp::from_symbol(*symbol) {
+                Some(op) => op,
+                None => break,
+            };
+            self.next("an operator")?;
+            ops.push((op, self.compile_term()?));
+        }
+        Ok(Expression { term, ops })
+    }
+
+    /// integerConstant | stringConstant | keywordConstant | varName | varName '[' expression ']'
+    /// | subroutineCall | '(' expression ')' | unaryOp term
+    fn compile_term(&mut self) -> Result<Term, JackError> {
+        let token = self.next("a term")?;
+        let term = match token.kind {
+            TokenKind::IntegerConstant(value) => Term::IntegerConstant(value),
+            TokenKind::StringConstant(value) => Term::StringConstant(value),
+            TokenKind::Keyword(Keyword::True) => Term::KeywordConstant(KeywordConstant::True),
+            TokenKind::Keyword(Keyword::False) => Term::KeywordConstant(KeywordConstant::False),
+            TokenKind::Keyword(Keyword::Null) => Term::KeywordConstant(KeywordConstant::Null),
+            TokenKind::Keyword(Keyword::This) => Term::KeywordConstant(KeywordConstant::This),
+            TokenKind::Symbol('(') => {
+                let expression = self.compile_expression()?;
+                self.expect_symbol(')')?;
+                Term::Parenthesized(Box::new(expression))
+            }
+            TokenKind::Symbol('-') => Term::Unary(UnaryOp::Neg, Box::new(self.compile_term()?)),
+            TokenKind::Symbol('~') => Term::Unary(UnaryOp::Not, Box::new(self.compile_term()?)),
+            TokenKind::Identifier(name) => {
+                let name = Identifier {
+                    name,
+                    span: token.span,
+                };
+                if self.peek_symbol('[') {
+                    self.next("`[`")?;
+                    let index = self.compile_expression()?;
+                    self.expect_symbol(']')?;
+                    Term::Index(name, Box::new(index))
+                } else if self.peek_symbol('(') || self.peek_symbol('.') {
+                    Term::Call(self.compile_subroutine_call(name)?)
+                } else {
+                    Term::Variable(name)
+                }
+            }
+            _ => return Err(self.unexpected(&token, "a term")),
+        };
+        Ok(term)
+    }
+
+    /// subroutineName '(' expressionList ')'
+    /// | (className | varName) '.' subroutineName '(' expressionList ')'
+    ///
+    /// `first` is the identifier already consumed.
+    fn compile_subroutine_call(&mut self, first: Identifier) -> Result<SubroutineCall, JackError> {
+        let (receiver, name) = if self.peek_symbol('.') {
+            self.next("`.`")?;
+            (Some(first), self.expect_identifier()?)
+        } else {
+            (None, first)
+        };
+        self.expect_symbol('(')?;
+        let arguments = self.compile_expression_list()?;
+        self.expect_symbol(')')?;
+        Ok(SubroutineCall {
+            receiver,
+            name,
+            arguments,
+        })
+    }
+
+    /// (expression (',' expression)*)?
+    fn compile_expression_list(&mut self) -> Result<Vec<Expression>, JackError> {
+        let mut expressions = vec![];
+        if self.peek_symbol(')') {
+            return Ok(expressions);
+        }
+        loop {
+            expressions.push(self.compile_expression()?);
+            if !self.peek_symbol(',') {
+                return Ok(expressions);
+            }
+            self.next("`,`")?;
+        }
+    }
+
+    fn peek_symbol(&self, symbol: char) -> bool {
+        matches!(self.tokenizer.peek(), Some(Token { kind: TokenKind::Symbol(s), .. }) if *s == symbol)
+    }
+
+    fn peek_keyword(&self, keyword: Keyword) -> bool {
+        matches!(self.tokenizer.peek(), Some(Token { kind: TokenKind::Keyword(k), .. }) if *k == keyword)
+    }
+
+    /// Consumes the next token; `expected` describes it for the end-of-file error.
+    fn next(&mut self, expected: &str) -> Result<Token, JackError> {
+        if self.tokenizer.has_more_tokens() {
+            return Ok(self.tokenizer.advance().clone());
+        }
+        let (line, column) = match self.tokenizer.tokens().last() {
+            Some(token) => (
+                token.span.line,
+                token.span.column + token.span.end - token.span.start,
+            ),
+            None => (1, 1),
+        };
+        Err(JackError::UnexpectedEof {
+            expected: expected.to_string(),
+            location: Location::new(&self.jack_path, &self.source, line, column),
+        })
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), JackError> {
+        let expected = format!("`{}`", symbol);
+        let token = self.next(&expected)?;
+        match token.kind {
+            TokenKind::Symbol(s) if s == symbol => Ok(()),
+            _ => Err(self.unexpected(&token, &expected)),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword) -> Result<(), JackError> {
+        let expected = format!("`{}`", keyword.as_str());
+        let token = self.next(&expected)?;
+        match token.kind {
+            TokenKind::Keyword(k) if k == keyword => Ok(()),
+            _ => Err(self.unexpected(&token, &expected)),
+        }
+    }
+
+    fn expect_identifier(&mut self) -> Result<Identifier, JackError> {
+        let token = self.next("an identifier")?;
+        match token.kind {
+            TokenKind::Identifier(name) => Ok(Identifier {
+                name,
+                span: token.span,
+            }),
+            _ => Err(self.unexpected(&token, "an identifier")),
+        }
+    }
+
+    fn unexpected(&self, token: &Token, expected: &str) -> JackError {
+        JackError::UnexpectedToken {
+            expected: expected.to_string(),
+            found: self.source[token.span.start..token.span.end].to_string(),
+            location: Location::new(
+                &self.jack_path,
+                &self.source,
+                token.span.line,
+                token.span.column,
+            ),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::io;
+
+use crate::diagnostic::write_diagnostic;
+pub use crate::diagnostic::Location;
+
+#[derive(Debug)]
+pub enum JackError {
+    InvalidCharacter {
+        character: char,
+        location: Location,
+    },
+    UnterminatedComment {
+        location: Location,
+    },
+    UnterminatedString {
+        location: Location,
+    },
+    IntegerOverflow {
+        value: String,
+        location: Location,
+    },
+    UnexpectedToken {
+        expected: String,
+        found: String,
+        location: Location,
+    },
+    UnexpectedEof {
+        expected: String,
+        location: Location,
+    },
+    UndefinedVariable {
+        name: String,
+        location: Location,
+    },
+    MethodCallInFunction {
+        name: String,
+        location: Location,
+    },
+    Io {
+        file: String,
+        error: io::Error,
+    },
+}
+
+impl JackError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            JackError::InvalidCharacter { location, .. }
+            | JackError::UnterminatedComment { location }
+            | JackError::UnterminatedString { location }
+            | JackError::IntegerOverflow { location, .. }
+            | JackError::UnexpectedToken { location, .. }
+            | JackError::UnexpectedEof { location, .. }
+            | JackError::UndefinedVariable { location, .. }
+            | JackError::MethodCallInFunction { location, .. } => Some(location),
+            JackError::Io { .. } => None,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            JackError::InvalidCharacter { character, .. } => {
+                format!("invalid character `{}`", character)
+            }
+            JackError::UnterminatedComment { .. } => "unterminated comment".to_string(),
+            JackError::UnterminatedString { .. } => "unterminated string constant".to_string(),
+            JackError::IntegerOverflow { value, .. } => {
+                format!("integer constant `{}` is larger than 32767", value)
+            }
+            JackError::UnexpectedToken {
+                expected, found, ..
+            } => format!("expected {}, found `{}`", expected, found),
+            JackError::UnexpectedEof { expected, .. } => {
+                format!("expected {}, found end of file", expected)
+            }
+            JackError::UndefinedVariable { name, .. } => {
+                format!("cannot find variable `{}` in this scope", name)
+            }
+            JackError::MethodCallInFunction { name, .. } => {
+                format!("method `{}` called without an object from a function", name)
+            }
+            JackError::Io { file, error } => format!("{}: {}", file, error),
+        }
+    }
+
+    /// Length of the text the error points at, used for the `^^^` marker.
+    fn width(&self) -> usize {
+        match self {
+            JackError::UnterminatedComment { .. } => 2,
+            JackError::IntegerOverflow { value, .. } => value.len(),
+            JackError::UnexpectedToken { found, .. } => found.chars().count(),
+            JackError::UndefinedVariable { name, .. }
+            | JackError::MethodCallInFunction { name, .. } => name.len(),
+            _ => 1,
+        }
+    }
+}
+
+/// Formats the error like a compiler diagnostic, in the same layout as
+/// [`AsmError`](crate::assembler::error::AsmError).
+impl fmt::Display for JackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_diagnostic(f, "error", &self.message(), self.location(), self.width())
+    }
+}
+
+impl std::error::Error for JackError {}
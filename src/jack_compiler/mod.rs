@@ -0,0 +1,10 @@
+pub mod ast;
+pub mod code_generator;
+pub mod compilation_engine;
+pub mod error;
+pub mod symbol_table;
+pub mod tokenizer;
+pub mod vm_writer;
+pub mod xml;
+#[cfg(test)]
+mod tests;
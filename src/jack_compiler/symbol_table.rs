@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::jack_compiler::ast::Type;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Static,
+    Field,
+    Arg,
+    Var,
+}
+
+impl Kind {
+    /// VM segment holding variables of this kind.
+    pub fn segment(&self) -> &'static str {
+        match self {
+            Kind::Static => "static",
+            Kind::Field => "this",
+            Kind::Arg => "argument",
+            Kind::Var => "local",
+        }
+    }
+}
+
+/// Class-level (`static`, `field`) and subroutine-level (`arg`, `var`) scopes.
+pub struct SymbolTable {
+    class_scope: HashMap<String, (Type, Kind, usize)>,
+    subroutine_scope: HashMap<String, (Type, Kind, usize)>,
+    counts: HashMap<Kind, usize>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self {
+            class_scope: HashMap::new(),
+            subroutine_scope: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Clears the subroutine scope.
+    pub fn start_subroutine(&mut self) {
+        self.subroutine_scope.clear();
+        self.counts.remove(&Kind::Arg);
+        self.counts.remove(&Kind::Var);
+    }
+
+    pub fn define(&mut self, name: &str, var_type: Type, kind: Kind) {
+        let count = self.counts.entry(kind).or_insert(0);
+        let scope = match kind {
+            Kind::Static | Kind::Field => &mut self.class_scope,
+            Kind::Arg | Kind::Var => &mut self.subroutine_scope,
+        };
+        scope.insert(name.to_string(), (var_type, kind, *count));
+        *count += 1;
+    }
+
+    pub fn var_count(&self, kind: Kind) -> usize {
+        *self.counts.get(&kind).unwrap_or(&0)
+    }
+
+    pub fn kind_of(&self, name: &str) -> Option<Kind> {
+        self.lookup(name).map(|(_, kind, _)| *kind)
+    }
+
+    pub fn type_of(&self, name: &str) -> Option<&Type> {
+        self.lookup(name).map(|(var_type, _, _)| var_type)
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.lookup(name).map(|(_, _, index)| *index)
+    }
+
+    fn lookup(&self, name: &str) -> Option<&(Type, Kind, usize)> {
+        self.subroutine_scope
+            .get(name)
+            .or_else(|| self.class_scope.get(name))
+    }
+}
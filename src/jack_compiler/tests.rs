@@ -0,0 +1,762 @@
+//! The programs of project 10 parsed into the `xxx.xml` trees the book compares with, the
+//! errors reported on malformed ones, and small classes compiled and run on the VM emulator.
+
+use crate::jack_compiler::code_generator::CodeGenerator;
+use crate::jack_compiler::compilation_engine::CompilationEngine;
+use crate::jack_compiler::error::JackError;
+use crate::jack_compiler::xml;
+use crate::vm_emulator::vm::Vm;
+
+/// `projects/10/ExpressionLessSquare/Main.jack`, without its header.
+const EXPRESSION_LESS_SQUARE_MAIN: &str = r#"/** Expressionless version of projects/10/Square/Main.jack. */
+
+class Main {
+    static boolean test;    // Added for testing -- there is no static keyword
+                            // in the Square files.
+
+    function void main() {
+        var SquareGame game;
+        let game = game;
+        do game.run();
+        do game.dispose();
+        return;
+    }
+
+    function void more() {  // Added to test Jack syntax that is not used in
+        var boolean b;      // the Square files.
+        if (b) {
+        }
+        else {              // There is no else keyword in the Square files.
+        }
+        return;
+    }
+}
+"#;
+
+const EXPRESSION_LESS_SQUARE_MAIN_XML: &str = r#"<class>
+  <keyword> class </keyword>
+  <identifier> Main </identifier>
+  <symbol> { </symbol>
+  <classVarDec>
+    <keyword> static </keyword>
+    <keyword> boolean </keyword>
+    <identifier> test </identifier>
+    <symbol> ; </symbol>
+  </classVarDec>
+  <subroutineDec>
+    <keyword> function </keyword>
+    <keyword> void </keyword>
+    <identifier> main </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <varDec>
+        <keyword> var </keyword>
+        <identifier> SquareGame </identifier>
+        <identifier> game </identifier>
+        <symbol> ; </symbol>
+      </varDec>
+      <statements>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> game </identifier>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <identifier> game </identifier>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> game </identifier>
+          <symbol> . </symbol>
+          <identifier> run </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> game </identifier>
+          <symbol> . </symbol>
+          <identifier> dispose </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <returnStatement>
+          <keyword> return </keyword>
+          <symbol> ; </symbol>
+        </returnStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <subroutineDec>
+    <keyword> function </keyword>
+    <keyword> void </keyword>
+    <identifier> more </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <varDec>
+        <keyword> var </keyword>
+        <keyword> boolean </keyword>
+        <identifier> b </identifier>
+        <symbol> ; </symbol>
+      </varDec>
+      <statements>
+        <ifStatement>
+          <keyword> if </keyword>
+          <symbol> ( </symbol>
+          <expression>
+            <term>
+              <identifier> b </identifier>
+            </term>
+          </expression>
+          <symbol> ) </symbol>
+          <symbol> { </symbol>
+          <statements>
+          </statements>
+          <symbol> } </symbol>
+          <keyword> else </keyword>
+          <symbol> { </symbol>
+          <statements>
+          </statements>
+          <symbol> } </symbol>
+        </ifStatement>
+        <returnStatement>
+          <keyword> return </keyword>
+          <symbol> ; </symbol>
+        </returnStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <symbol> } </symbol>
+</class>
+"#;
+
+/// `projects/10/ArrayTest/Main.jack`, without its header.
+const ARRAY_TEST_MAIN: &str = r#"/** Computes the average of a sequence of integers. */
+class Main {
+    function void main() {
+        var Array a;
+        var int length;
+        var int i, sum;
+	
+	let length = Keyboard.readInt("HOW MANY NUMBERS? ");
+	let a = Array.new(length);
+	let i = 0;
+	
+	while (i < length) {
+	    let a[i] = Keyboard.readInt("ENTER THE NEXT NUMBER: ");
+	    let i = i + 1;
+	}
+	
+	let i = 0;
+	let sum = 0;
+	
+	while (i < length) {
+	    let sum = sum + a[i];
+	    let i = i + 1;
+	}
+	
+	do Output.printString("THE AVERAGE IS: ");
+	do Output.printInt(sum / length);
+	do Output.println();
+	
+	return;
+    }
+}
+"#;
+
+const ARRAY_TEST_MAIN_XML: &str = r#"<class>
+  <keyword> class </keyword>
+  <identifier> Main </identifier>
+  <symbol> { </symbol>
+  <subroutineDec>
+    <keyword> function </keyword>
+    <keyword> void </keyword>
+    <identifier> main </identifier>
+    <symbol> ( </symbol>
+    <parameterList>
+    </parameterList>
+    <symbol> ) </symbol>
+    <subroutineBody>
+      <symbol> { </symbol>
+      <varDec>
+        <keyword> var </keyword>
+        <identifier> Array </identifier>
+        <identifier> a </identifier>
+        <symbol> ; </symbol>
+      </varDec>
+      <varDec>
+        <keyword> var </keyword>
+        <keyword> int </keyword>
+        <identifier> length </identifier>
+        <symbol> ; </symbol>
+      </varDec>
+      <varDec>
+        <keyword> var </keyword>
+        <keyword> int </keyword>
+        <identifier> i </identifier>
+        <symbol> , </symbol>
+        <identifier> sum </identifier>
+        <symbol> ; </symbol>
+      </varDec>
+      <statements>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> length </identifier>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <identifier> Keyboard </identifier>
+              <symbol> . </symbol>
+              <identifier> readInt </identifier>
+              <symbol> ( </symbol>
+              <expressionList>
+                <expression>
+                  <term>
+                    <stringConstant> HOW MANY NUMBERS?  </stringConstant>
+                  </term>
+                </expression>
+              </expressionList>
+              <symbol> ) </symbol>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> a </identifier>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <identifier> Array </identifier>
+              <symbol> . </symbol>
+              <identifier> new </identifier>
+              <symbol> ( </symbol>
+              <expressionList>
+                <expression>
+                  <term>
+                    <identifier> length </identifier>
+                  </term>
+                </expression>
+              </expressionList>
+              <symbol> ) </symbol>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> i </identifier>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <integerConstant> 0 </integerConstant>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <whileStatement>
+          <keyword> while </keyword>
+          <symbol> ( </symbol>
+          <expression>
+            <term>
+              <identifier> i </identifier>
+            </term>
+            <symbol> &lt; </symbol>
+            <term>
+              <identifier> length </identifier>
+            </term>
+          </expression>
+          <symbol> ) </symbol>
+          <symbol> { </symbol>
+          <statements>
+            <letStatement>
+              <keyword> let </keyword>
+              <identifier> a </identifier>
+              <symbol> [ </symbol>
+              <expression>
+                <term>
+                  <identifier> i </identifier>
+                </term>
+              </expression>
+              <symbol> ] </symbol>
+              <symbol> = </symbol>
+              <expression>
+                <term>
+                  <identifier> Keyboard </identifier>
+                  <symbol> . </symbol>
+                  <identifier> readInt </identifier>
+                  <symbol> ( </symbol>
+                  <expressionList>
+                    <expression>
+                      <term>
+                        <stringConstant> ENTER THE NEXT NUMBER:  </stringConstant>
+                      </term>
+                    </expression>
+                  </expressionList>
+                  <symbol> ) </symbol>
+                </term>
+              </expression>
+              <symbol> ; </symbol>
+            </letStatement>
+            <letStatement>
+              <keyword> let </keyword>
+              <identifier> i </identifier>
+              <symbol> = </symbol>
+              <expression>
+                <term>
+                  <identifier> i </identifier>
+                </term>
+                <symbol> + </symbol>
+                <term>
+                  <integerConstant> 1 </integerConstant>
+                </term>
+              </expression>
+              <symbol> ; </symbol>
+            </letStatement>
+          </statements>
+          <symbol> } </symbol>
+        </whileStatement>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> i </identifier>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <integerConstant> 0 </integerConstant>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <letStatement>
+          <keyword> let </keyword>
+          <identifier> sum </identifier>
+          <symbol> = </symbol>
+          <expression>
+            <term>
+              <integerConstant> 0 </integerConstant>
+            </term>
+          </expression>
+          <symbol> ; </symbol>
+        </letStatement>
+        <whileStatement>
+          <keyword> while </keyword>
+          <symbol> ( </symbol>
+          <expression>
+            <term>
+              <identifier> i </identifier>
+            </term>
+            <symbol> &lt; </symbol>
+            <term>
+              <identifier> length </identifier>
+            </term>
+          </expression>
+          <symbol> ) </symbol>
+          <symbol> { </symbol>
+          <statements>
+            <letStatement>
+              <keyword> let </keyword>
+              <identifier> sum </identifier>
+              <symbol> = </symbol>
+              <expression>
+                <term>
+                  <identifier> sum </identifier>
+                </term>
+                <symbol> + </symbol>
+                <term>
+                  <identifier> a </identifier>
+                  <symbol> [ </symbol>
+                  <expression>
+                    <term>
+                      <identifier> i </identifier>
+                    </term>
+                  </expression>
+                  <symbol> ] </symbol>
+                </term>
+              </expression>
+              <symbol> ; </symbol>
+            </letStatement>
+            <letStatement>
+              <keyword> let </keyword>
+              <identifier> i </identifier>
+              <symbol> = </symbol>
+              <expression>
+                <term>
+                  <identifier> i </identifier>
+                </term>
+                <symbol> + </symbol>
+                <term>
+                  <integerConstant> 1 </integerConstant>
+                </term>
+              </expression>
+              <symbol> ; </symbol>
+            </letStatement>
+          </statements>
+          <symbol> } </symbol>
+        </whileStatement>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> Output </identifier>
+          <symbol> . </symbol>
+          <identifier> printString </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+            <expression>
+              <term>
+                <stringConstant> THE AVERAGE IS:  </stringConstant>
+              </term>
+            </expression>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> Output </identifier>
+          <symbol> . </symbol>
+          <identifier> printInt </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+            <expression>
+              <term>
+                <identifier> sum </identifier>
+              </term>
+              <symbol> / </symbol>
+              <term>
+                <identifier> length </identifier>
+              </term>
+            </expression>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <doStatement>
+          <keyword> do </keyword>
+          <identifier> Output </identifier>
+          <symbol> . </symbol>
+          <identifier> println </identifier>
+          <symbol> ( </symbol>
+          <expressionList>
+          </expressionList>
+          <symbol> ) </symbol>
+          <symbol> ; </symbol>
+        </doStatement>
+        <returnStatement>
+          <keyword> return </keyword>
+          <symbol> ; </symbol>
+        </returnStatement>
+      </statements>
+      <symbol> } </symbol>
+    </subroutineBody>
+  </subroutineDec>
+  <symbol> } </symbol>
+</class>
+"#;
+
+fn parse(source: &str) -> Result<String, JackError> {
+    let class = CompilationEngine::from_source("Main.jack", source)?.compile_class()?;
+    Ok(xml::to_xml(&class))
+}
+
+#[test]
+fn expression_less_square() {
+    assert_eq!(
+        parse(EXPRESSION_LESS_SQUARE_MAIN).unwrap(),
+        EXPRESSION_LESS_SQUARE_MAIN_XML
+    );
+}
+
+#[test]
+fn array_test() {
+    assert_eq!(parse(ARRAY_TEST_MAIN).unwrap(), ARRAY_TEST_MAIN_XML);
+}
+
+/// The token found where another was expected, and its line.
+fn unexpected_token(source: &str) -> (String, String, usize) {
+    match parse(source) {
+        Err(JackError::UnexpectedToken {
+            expected,
+            found,
+            location,
+        }) => (expected, found, location.line),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn missing_expression() {
+    let source = "class Main {\n  function void main() {\n    let x = ;\n  }\n}\n";
+    let (_, found, line) = unexpected_token(source);
+    assert_eq!((found.as_str(), line), (";", 3));
+}
+
+#[test]
+fn missing_semicolon() {
+    let source = "class Main {\n  function void main() {\n    do f()\n    return;\n  }\n}\n";
+    let (expected, found, line) = unexpected_token(source);
+    assert_eq!(
+        (expected.as_str(), found.as_str(), line),
+        ("`;`", "return", 4)
+    );
+}
+
+#[test]
+fn unexpected_eof() {
+    match parse("class Main {\n  field int x;\n") {
+        Err(JackError::UnexpectedEof { location, .. }) => assert_eq!(location.line, 2),
+        result => panic!("{:?}", result),
+    }
+}
+
+/// Arrays, `let a[i] = ...` with `a[i]` on both sides, and `while` and `if` with `else`.
+const ARRAYS_MAIN: &str = r#"class Main {
+    function void main() {
+        var Array a, r;
+        var int i, sum;
+        let a = Array.new(5);
+        let i = 0;
+        while (i < 5) {
+            let a[i] = i + i;
+            let i = i + 1;
+        }
+        let sum = 0;
+        let i = 0;
+        while (i < 5) {
+            if (a[i] > 4) {
+                let sum = sum + a[i];
+            } else {
+                let sum = sum - 1;
+            }
+            let i = i + 1;
+        }
+        let r = 8000;
+        let r[0] = sum;
+        let r[a[1]] = a[4];
+        return;
+    }
+}
+"#;
+
+/// A constructor, fields, a static, methods calling methods on `this`, and a function.
+const POINT: &str = r#"class Point {
+    field int x, y;
+    static int count;
+
+    constructor Point new(int ax, int ay) {
+        let x = ax;
+        let y = ay;
+        let count = count + 1;
+        return this;
+    }
+
+    method int sum() {
+        return x + y;
+    }
+
+    method void shift(int d) {
+        let x = x + d;
+        let y = y - d;
+        return;
+    }
+
+    method int twice() {
+        return sum() + sum();
+    }
+
+    function int count() {
+        return count;
+    }
+}
+"#;
+
+const POINT_VM: &str = "function Point.new 0
+push constant 2
+call Memory.alloc 1
+pop pointer 0
+push argument 0
+pop this 0
+push argument 1
+pop this 1
+push static 0
+push constant 1
+add
+pop static 0
+push pointer 0
+return
+function Point.sum 0
+push argument 0
+pop pointer 0
+push this 0
+push this 1
+add
+return
+function Point.shift 0
+push argument 0
+pop pointer 0
+push this 0
+push argument 1
+add
+pop this 0
+push this 1
+push argument 1
+sub
+pop this 1
+push constant 0
+return
+function Point.twice 0
+push argument 0
+pop pointer 0
+push pointer 0
+call Point.sum 1
+push pointer 0
+call Point.sum 1
+add
+return
+function Point.count 0
+push static 0
+return
+";
+
+const POINTS_MAIN: &str = r#"class Main {
+    function void main() {
+        var Point p, q;
+        var Array r;
+        let r = 8000;
+        let p = Point.new(3, 4);
+        let q = Point.new(10, -2);
+        do p.shift(1);
+        let r[0] = p.sum();
+        let r[1] = q.twice();
+        let r[2] = Point.count();
+        let r[3] = ~(p.sum() = 8) & true;
+        return;
+    }
+}
+"#;
+
+/// The parts of the OS the classes above use: `Memory.alloc` handing out the heap from 2048
+/// on, and `Array.new`. `Sys.init` runs `Main.main`.
+const OS: &str = "function Memory.alloc 0
+push static 0
+push constant 0
+eq
+if-goto INIT
+label ALLOC
+push static 0
+push static 0
+push argument 0
+add
+pop static 0
+return
+label INIT
+push constant 2048
+pop static 0
+goto ALLOC
+function Array.new 0
+push argument 0
+call Memory.alloc 1
+return
+function Sys.init 0
+call Main.main 0
+pop temp 0
+push constant 0
+return
+";
+
+fn compile(jack_path: &str, source: &str) -> String {
+    let class = CompilationEngine::from_source(jack_path, source)
+        .and_then(|mut engine| engine.compile_class())
+        .unwrap();
+    CodeGenerator::new(jack_path, source)
+        .generate(&class)
+        .unwrap()
+}
+
+/// Compiles `jack_sources` and runs them with `OS` from `Sys.init` to its return. Returns the
+/// RAM at that point.
+fn run(jack_sources: &[(&str, &str)]) -> Vec<u16> {
+    let vm: Vec<(String, String)> = jack_sources
+        .iter()
+        .map(|(jack_path, source)| {
+            (
+                jack_path.replace(".jack", ".vm"),
+                compile(jack_path, source),
+            )
+        })
+        .chain(std::iter::once(("OS.vm".to_string(), OS.to_string())))
+        .collect();
+    let vm: Vec<(&str, &str)> = vm
+        .iter()
+        .map(|(vm_path, vm)| (vm_path.as_str(), vm.as_str()))
+        .collect();
+    let mut vm = Vm::from_sources(&vm).unwrap();
+    vm.bootstrap().unwrap();
+    while !vm.is_finished() {
+        assert!(vm.steps() < 100_000, "program did not return");
+        vm.step().unwrap();
+    }
+    vm.ram().to_vec()
+}
+
+#[test]
+fn arrays_and_control_flow() {
+    let ram = run(&[("Main.jack", ARRAYS_MAIN)]);
+    assert_eq!(ram[2048..2053], [0, 2, 4, 6, 8]);
+    // -1, -2, -3, then +6 and +8.
+    assert_eq!(ram[8000], 11);
+    // r[a[1]] = a[4]
+    assert_eq!(ram[8002], 8);
+}
+
+#[test]
+fn while_and_if_labels() {
+    let vm = compile("Main.jack", ARRAYS_MAIN);
+    let labels: Vec<&str> = vm
+        .lines()
+        .filter(|line| line.starts_with("label") || line.contains("goto"))
+        .collect();
+    assert_eq!(
+        labels,
+        [
+            "label WHILE_EXP0",
+            "if-goto WHILE_END0",
+            "goto WHILE_EXP0",
+            "label WHILE_END0",
+            "label WHILE_EXP1",
+            "if-goto WHILE_END1",
+            "if-goto IF_ELSE2",
+            "goto IF_END2",
+            "label IF_ELSE2",
+            "label IF_END2",
+            "goto WHILE_EXP1",
+            "label WHILE_END1",
+        ]
+    );
+}
+
+#[test]
+fn constructors_and_methods() {
+    assert_eq!(compile("Point.jack", POINT), POINT_VM);
+    let ram = run(&[("Main.jack", POINTS_MAIN), ("Point.jack", POINT)]);
+    // p = (3, 4) shifted by 1, q = (10, -2).
+    assert_eq!(ram[2048..2052], [4, 3, 10, -2i16 as u16]);
+    assert_eq!(ram[8000..8004], [7, 16, 2, -1i16 as u16]);
+}
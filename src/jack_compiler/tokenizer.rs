@@ -0,0 +1,390 @@
+use std::fs;
+
+use crate::jack_compiler::error::{JackError, Location};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keyword {
+    Class,
+    Constructor,
+    Function,
+    Method,
+    Field,
+    Static,
+    Var,
+    Int,
+    Char,
+    Boolean,
+    Void,
+    True,
+    False,
+    Null,
+    This,
+    Let,
+    Do,
+    If,
+    Else,
+    While,
+    Return,
+}
+
+const KEYWORDS: [(&str, Keyword); 21] = [
+    ("class", Keyword::Class),
+    ("constructor", Keyword::Constructor),
+    ("function", Keyword::Function),
+    ("method", Keyword::Method),
+    ("field", Keyword::Field),
+    ("static", Keyword::Static),
+    ("var", Keyword::Var),
+    ("int", Keyword::Int),
+    ("char", Keyword::Char),
+    ("boolean", Keyword::Boolean),
+    ("void", Keyword::Void),
+    ("true", Keyword::True),
+    ("false", Keyword::False),
+    ("null", Keyword::Null),
+    ("this", Keyword::This),
+    ("let", Keyword::Let),
+    ("do", Keyword::Do),
+    ("if", Keyword::If),
+    ("else", Keyword::Else),
+    ("while", Keyword::While),
+    ("return", Keyword::Return),
+];
+
+const SYMBOLS: &str = "{}()[].,;+-*/&|<>=~";
+
+impl Keyword {
+    pub fn as_str(&self) -> &'static str {
+        KEYWORDS.iter().find(|(_, k)| k == self).unwrap().0
+    }
+
+    fn parse(word: &str) -> Option<Self> {
+        KEYWORDS.iter().find(|(w, _)| *w == word).map(|(_, k)| *k)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Symbol(char),
+    IntegerConstant(u16),
+    StringConstant(String),
+    Identifier(String),
+}
+
+impl TokenKind {
+    /// Tag name used in the XML output, e.g. `integerConstant`.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            TokenKind::Keyword(_) => "keyword",
+            TokenKind::Symbol(_) => "symbol",
+            TokenKind::IntegerConstant(_) => "integerConstant",
+            TokenKind::StringConstant(_) => "stringConstant",
+            TokenKind::Identifier(_) => "identifier",
+        }
+    }
+
+    /// The token as written in the XML output, without escaping.
+    pub fn text(&self) -> String {
+        match self {
+            TokenKind::Keyword(keyword) => keyword.as_str().to_string(),
+            TokenKind::Symbol(symbol) => symbol.to_string(),
+            TokenKind::IntegerConstant(value) => value.to_string(),
+            TokenKind::StringConstant(value) | TokenKind::Identifier(value) => value.clone(),
+        }
+    }
+}
+
+/// Where a token appears in its source file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    /// Byte offset of the first character.
+    pub start: usize,
+    /// Byte offset just past the last character.
+    pub end: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column number.
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+pub struct Tokenizer {
+    tokens: Vec<Token>,
+    current: usize,
+}
+
+impl Tokenizer {
+    pub fn new(jack_path: &str) -> Result<Self, JackError> {
+        let source = fs::read_to_string(jack_path).map_err(|error| JackError::Io {
+            file: jack_path.to_string(),
+            error,
+        })?;
+        Self::from_source(jack_path, &source)
+    }
+
+    /// Tokenizes `source`, reporting errors against the file name `jack_path`.
+    pub fn from_source(jack_path: &str, source: &str) -> Result<Self, JackError> {
+        Ok(Self {
+            tokens: tokenize(jack_path, source)?,
+            current: 0,
+        })
+    }
+
+    pub fn has_more_tokens(&self) -> bool {
+        self.current < self.tokens.len()
+    }
+
+    pub fn advance(&mut self) -> &Token {
+        assert!(self.has_more_tokens());
+        self.current += 1;
+        &self.tokens[self.current - 1]
+    }
+
+    /// The token `advance` will return next.
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.current)
+    }
+
+    /// The token after the one `advance` will return next.
+    pub fn peek_second(&self) -> Option<&Token> {
+        self.tokens.get(self.current + 1)
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Renders all tokens in the `xxxT.xml` format of project 10.
+    pub fn to_xml(&self) -> String {
+        let mut xml = "<tokens>\n".to_string();
+        for token in &self.tokens {
+            xml += &format!(
+                "<{tag}> {} </{tag}>\n",
+                escape_xml(&token.kind.text()),
+                tag = token.kind.tag()
+            );
+        }
+        xml += "</tokens>\n";
+        xml
+    }
+}
+
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn tokenize(jack_path: &str, source: &str) -> Result<Vec<Token>, JackError> {
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let location = |line, column| Location::new(jack_path, source, line, column);
+    let offset = |i: usize| chars.get(i).map_or(source.len(), |(offset, _)| *offset);
+
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut line_start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i].1;
+        let next = chars.get(i + 1).map(|(_, c)| *c);
+        let column = i - line_start + 1;
+        if c == '\n' {
+            line += 1;
+            i += 1;
+            line_start = i;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i].1 != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            // Covers both `/* */` and `/** */`.
+            let (start_line, start_column) = (line, column);
+            i += 2;
+            loop {
+                if i + 1 >= chars.len() {
+                    return Err(JackError::UnterminatedComment {
+                        location: location(start_line, start_column),
+                    });
+                }
+                if chars[i].1 == '*' && chars[i + 1].1 == '/' {
+                    i += 2;
+                    break;
+                }
+                if chars[i].1 == '\n' {
+                    line += 1;
+                    line_start = i + 1;
+                }
+                i += 1;
+            }
+        } else {
+            let start = i;
+            let kind = if SYMBOLS.contains(c) {
+                i += 1;
+                TokenKind::Symbol(c)
+            } else if c == '"' {
+                i += 1;
+                while i < chars.len() && chars[i].1 != '"' && chars[i].1 != '\n' {
+                    i += 1;
+                }
+                if i >= chars.len() || chars[i].1 != '"' {
+                    return Err(JackError::UnterminatedString {
+                        location: location(line, column),
+                    });
+                }
+                i += 1;
+                TokenKind::StringConstant(source[offset(start + 1)..offset(i - 1)].to_string())
+            } else if c.is_ascii_digit() {
+                while i < chars.len() && chars[i].1.is_ascii_digit() {
+                    i += 1;
+                }
+                let value = &source[offset(start)..offset(i)];
+                match value.parse::<u16>() {
+                    Ok(v) if v <= 32767 => TokenKind::IntegerConstant(v),
+                    _ => {
+                        return Err(JackError::IntegerOverflow {
+                            value: value.to_string(),
+                            location: location(line, column),
+                        })
+                    }
+                }
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '_') {
+                    i += 1;
+                }
+                let word = &source[offset(start)..offset(i)];
+                match Keyword::parse(word) {
+                    Some(keyword) => TokenKind::Keyword(keyword),
+                    None => TokenKind::Identifier(word.to_string()),
+                }
+            } else {
+                return Err(JackError::InvalidCharacter {
+                    character: c,
+                    location: location(line, column),
+                });
+            };
+            tokens.push(Token {
+                kind,
+                span: Span {
+                    start: offset(start),
+                    end: offset(i),
+                    line,
+                    column,
+                },
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<TokenKind> {
+        tokenize("Main.jack", source)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn comments() {
+        let source = "// line\nclass /* block\n */ Main /** doc\n * more */ {\n}\n";
+        let tokens = tokenize("Main.jack", source).unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Keyword(Keyword::Class),
+                TokenKind::Identifier("Main".to_string()),
+                TokenKind::Symbol('{'),
+                TokenKind::Symbol('}'),
+            ]
+        );
+        let lines: Vec<_> = tokens.iter().map(|token| token.span.line).collect();
+        assert_eq!(lines, [2, 3, 4, 5]);
+        assert_eq!(tokens[2].span.column, 12);
+    }
+
+    #[test]
+    fn unterminated_comment() {
+        match tokenize("Main.jack", "class\n  /* never closed\n") {
+            Err(JackError::UnterminatedComment { location }) => {
+                assert_eq!((location.line, location.column), (2, 3));
+            }
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn string_constants() {
+        assert_eq!(
+            kinds("\"HOW MANY NUMBERS? \" \"\""),
+            [
+                TokenKind::StringConstant("HOW MANY NUMBERS? ".to_string()),
+                TokenKind::StringConstant(String::new()),
+            ]
+        );
+        match tokenize("Main.jack", "let s = \"open\n;") {
+            Err(JackError::UnterminatedString { location }) => {
+                assert_eq!((location.line, location.column), (1, 9));
+            }
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        let tokenizer = Tokenizer::from_source("Main.jack", "a < b & c > d").unwrap();
+        assert_eq!(
+            tokenizer.to_xml(),
+            "<tokens>
+<identifier> a </identifier>
+<symbol> &lt; </symbol>
+<identifier> b </identifier>
+<symbol> &amp; </symbol>
+<identifier> c </identifier>
+<symbol> &gt; </symbol>
+<identifier> d </identifier>
+</tokens>
+"
+        );
+        assert_eq!(escape_xml("\"<&>\""), "&quot;&lt;&amp;&gt;&quot;");
+    }
+
+    /// A statement of `projects/10/ArrayTest/Main.jack`, as its `MainT.xml` has it.
+    #[test]
+    fn array_test_tokens() {
+        let source = "\tlet a[i] = Keyboard.readInt(\"ENTER THE NEXT NUMBER: \");\n";
+        let tokenizer = Tokenizer::from_source("Main.jack", source).unwrap();
+        assert_eq!(
+            tokenizer.to_xml(),
+            "<tokens>
+<keyword> let </keyword>
+<identifier> a </identifier>
+<symbol> [ </symbol>
+<identifier> i </identifier>
+<symbol> ] </symbol>
+<symbol> = </symbol>
+<identifier> Keyboard </identifier>
+<symbol> . </symbol>
+<identifier> readInt </identifier>
+<symbol> ( </symbol>
+<stringConstant> ENTER THE NEXT NUMBER:  </stringConstant>
+<symbol> ) </symbol>
+<symbol> ; </symbol>
+</tokens>
+"
+        );
+    }
+}
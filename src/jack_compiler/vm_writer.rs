@@ -0,0 +1,57 @@
+/// Emits VM commands as text readable by `vm_translator::parser::Parser`.
+pub struct VmWriter {
+    vm: String,
+}
+
+impl Default for VmWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VmWriter {
+    pub fn new() -> Self {
+        Self { vm: String::new() }
+    }
+
+    pub fn write_push(&mut self, segment: &str, index: usize) {
+        self.vm += &format!("push {} {}\n", segment, index);
+    }
+
+    pub fn write_pop(&mut self, segment: &str, index: usize) {
+        self.vm += &format!("pop {} {}\n", segment, index);
+    }
+
+    pub fn write_arithmetic(&mut self, command: &str) {
+        self.vm += &format!("{}\n", command);
+    }
+
+    pub fn write_label(&mut self, label: &str) {
+        self.vm += &format!("label {}\n", label);
+    }
+
+    pub fn write_goto(&mut self, label: &str) {
+        self.vm += &format!("goto {}\n", label);
+    }
+
+    pub fn write_if(&mut self, label: &str) {
+        self.vm += &format!("if-goto {}\n", label);
+    }
+
+    pub fn write_call(&mut self, name: &str, num_args: usize) {
+        self.vm += &format!("call {} {}\n", name, num_args);
+    }
+
+    pub fn write_function(&mut self, name: &str, num_locals: usize) {
+        self.vm += &format!("function {} {}\n", name, num_locals);
+    }
+
+    pub fn write_return(&mut self) {
+        self.vm += "return\n";
+    }
+
+    /// Returns the VM code written so far.
+    pub fn close(self) -> String {
+        self.vm
+    }
+}
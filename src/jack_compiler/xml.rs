@@ -0,0 +1,269 @@
+use crate::jack_compiler::ast::*;
+use crate::jack_compiler::tokenizer::escape_xml;
+
+/// Renders `class` in the `xxx.xml` parse-tree format of project 10.
+pub fn to_xml(class: &Class) -> String {
+    let mut xml = XmlWriter {
+        xml: String::new(),
+        depth: 0,
+    };
+    xml.class(class);
+    xml.xml
+}
+
+struct XmlWriter {
+    xml: String,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn open(&mut self, tag: &str) {
+        self.xml += &format!("{}<{}>\n", "  ".repeat(self.depth), tag);
+        self.depth += 1;
+    }
+
+    fn close(&mut self, tag: &str) {
+        self.depth -= 1;
+        self.xml += &format!("{}</{}>\n", "  ".repeat(self.depth), tag);
+    }
+
+    fn leaf(&mut self, tag: &str, text: &str) {
+        self.xml += &format!(
+            "{}<{tag}> {} </{tag}>\n",
+            "  ".repeat(self.depth),
+            escape_xml(text),
+            tag = tag
+        );
+    }
+
+    fn keyword(&mut self, keyword: &str) {
+        self.leaf("keyword", keyword);
+    }
+
+    fn symbol(&mut self, symbol: char) {
+        self.leaf("symbol", &symbol.to_string());
+    }
+
+    fn identifier(&mut self, name: &str) {
+        self.leaf("identifier", name);
+    }
+
+    fn class(&mut self, class: &Class) {
+        self.open("class");
+        self.keyword("class");
+        self.identifier(&class.name);
+        self.symbol('{');
+        for class_var_dec in &class.class_var_decs {
+            self.open("classVarDec");
+            self.keyword(match class_var_dec.kind {
+                ClassVarKind::Static => "static",
+                ClassVarKind::Field => "field",
+            });
+            self.var_type(&class_var_dec.var_type);
+            self.names(&class_var_dec.names);
+            self.close("classVarDec");
+        }
+        for subroutine_dec in &class.subroutine_decs {
+            self.subroutine_dec(subroutine_dec);
+        }
+        self.symbol('}');
+        self.close("class");
+    }
+
+    fn var_type(&mut self, var_type: &Type) {
+        match var_type {
+            Type::Int => self.keyword("int"),
+            Type::Char => self.keyword("char"),
+            Type::Boolean => self.keyword("boolean"),
+            Type::Class(name) => self.identifier(name),
+        }
+    }
+
+    /// varName (',' varName)* ';'
+    fn names(&mut self, names: &[String]) {
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.identifier(name);
+        }
+        self.symbol(';');
+    }
+
+    fn subroutine_dec(&mut self, subroutine_dec: &SubroutineDec) {
+        self.open("subroutineDec");
+        self.keyword(match subroutine_dec.kind {
+            SubroutineKind::Constructor => "constructor",
+            SubroutineKind::Function => "function",
+            SubroutineKind::Method => "method",
+        });
+        match &subroutine_dec.return_type {
+            Some(return_type) => self.var_type(return_type),
+            None => self.keyword("void"),
+        }
+        self.identifier(&subroutine_dec.name);
+        self.symbol('(');
+        self.open("parameterList");
+        for (i, (var_type, name)) in subroutine_dec.parameters.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.var_type(var_type);
+            self.identifier(name);
+        }
+        self.close("parameterList");
+        self.symbol(')');
+        self.open("subroutineBody");
+        self.symbol('{');
+        for var_dec in &subroutine_dec.body.var_decs {
+            self.open("varDec");
+            self.keyword("var");
+            self.var_type(&var_dec.var_type);
+            self.names(&var_dec.names);
+            self.close("varDec");
+        }
+        self.statements(&subroutine_dec.body.statements);
+        self.symbol('}');
+        self.close("subroutineBody");
+        self.close("subroutineDec");
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        self.open("statements");
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.close("statements");
+    }
+
+    /// '{' statements '}'
+    fn block(&mut self, statements: &[Statement]) {
+        self.symbol('{');
+        self.statements(statements);
+        self.symbol('}');
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let { name, index, value } => {
+                self.open("letStatement");
+                self.keyword("let");
+                self.identifier(&name.name);
+                if let Some(index) = index {
+                    self.symbol('[');
+                    self.expression(index);
+                    self.symbol(']');
+                }
+                self.symbol('=');
+                self.expression(value);
+                self.symbol(';');
+                self.close("letStatement");
+            }
+            Statement::If {
+                condition,
+                then_statements,
+                else_statements,
+            } => {
+                self.open("ifStatement");
+                self.keyword("if");
+                self.symbol('(');
+                self.expression(condition);
+                self.symbol(')');
+                self.block(then_statements);
+                if let Some(else_statements) = else_statements {
+                    self.keyword("else");
+                    self.block(else_statements);
+                }
+                self.close("ifStatement");
+            }
+            Statement::While {
+                condition,
+                statements,
+            } => {
+                self.open("whileStatement");
+                self.keyword("while");
+                self.symbol('(');
+                self.expression(condition);
+                self.symbol(')');
+                self.block(statements);
+                self.close("whileStatement");
+            }
+            Statement::Do(call) => {
+                self.open("doStatement");
+                self.keyword("do");
+                self.subroutine_call(call);
+                self.symbol(';');
+                self.close("doStatement");
+            }
+            Statement::Return(value) => {
+                self.open("returnStatement");
+                self.keyword("return");
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+                self.symbol(';');
+                self.close("returnStatement");
+            }
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        self.open("expression");
+        self.term(&expression.term);
+        for (op, term) in &expression.ops {
+            self.symbol(op.symbol());
+            self.term(term);
+        }
+        self.close("expression");
+    }
+
+    fn term(&mut self, term: &Term) {
+        self.open("term");
+        match term {
+            Term::IntegerConstant(value) => self.leaf("integerConstant", &value.to_string()),
+            Term::StringConstant(value) => self.leaf("stringConstant", value),
+            Term::KeywordConstant(keyword) => self.keyword(match keyword {
+                KeywordConstant::True => "true",
+                KeywordConstant::False => "false",
+                KeywordConstant::Null => "null",
+                KeywordConstant::This => "this",
+            }),
+            Term::Variable(name) => self.identifier(&name.name),
+            Term::Index(name, index) => {
+                self.identifier(&name.name);
+                self.symbol('[');
+                self.expression(index);
+                self.symbol(']');
+            }
+            Term::Call(call) => self.subroutine_call(call),
+            Term::Parenthesized(expression) => {
+                self.symbol('(');
+                self.expression(expression);
+                self.symbol(')');
+            }
+            Term::Unary(op, term) => {
+                self.symbol(op.symbol());
+                self.term(term);
+            }
+        }
+        self.close("term");
+    }
+
+    fn subroutine_call(&mut self, call: &SubroutineCall) {
+        if let Some(receiver) = &call.receiver {
+            self.identifier(&receiver.name);
+            self.symbol('.');
+        }
+        self.identifier(&call.name.name);
+        self.symbol('(');
+        self.open("expressionList");
+        for (i, argument) in call.arguments.iter().enumerate() {
+            if i > 0 {
+                self.symbol(',');
+            }
+            self.expression(argument);
+        }
+        self.close("expressionList");
+        self.symbol(')');
+    }
+}
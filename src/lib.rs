@@ -4,3 +4,11 @@
 /// Parser of assemble
 pub mod assembler;
 pub mod vm_translator;
+pub mod cpu_emulator;
+pub mod vm_emulator;
+pub mod test_script;
+pub mod jack_compiler;
+pub mod source_map;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod hdl;
@@ -1,18 +1,38 @@
 use std::ffi::OsStr;
+use std::fs;
 
 use nand2tetris::assembler;
+use nand2tetris::assembler::error::{AsmError, AsmWarning};
+use nand2tetris::assembler::format::Format;
+use nand2tetris::assembler::parser::Parser;
+use nand2tetris::assembler::writer::Assembly;
+use nand2tetris::cpu_emulator::machine::{parse_hack, Machine};
+use nand2tetris::cpu_emulator::screen;
+use nand2tetris::disassembler;
+use nand2tetris::hdl::builtin;
+use nand2tetris::hdl::chip::{self, Chip};
+use nand2tetris::hdl::differential as chip_differential;
+use nand2tetris::jack_compiler;
+use nand2tetris::source_map::{SourceMap, VmLocation};
+use nand2tetris::test_script;
+use nand2tetris::test_script::hdl::TracedChip;
+use nand2tetris::test_script::runner::{Runner, Target, TestError};
+use nand2tetris::vm_emulator::differential;
+use nand2tetris::vm_emulator::vm::Vm;
 use nand2tetris::vm_translator;
 
 extern crate clap;
 use clap::{App, Arg};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 
 fn main() {
+    let builtin_names = builtin::names();
     let app = App::new("nand2tetris")
         .arg(
             Arg::with_name("input")
-                .help("path to input dir")
-                .required(true),
+                .help("path to input dir, an .asm file, a .tst script to run, or a .hack file to disassemble or run")
+                .required_unless("fuzz"),
         )
         .arg(
             Arg::with_name("output")
@@ -26,45 +46,605 @@ fn main() {
                 .help("if true, writer outputs initializing code")
                 .short("i")
                 .long("init"),
+        )
+        .arg(
+            Arg::with_name("xml")
+                .help("writes xxxT.xml tokens and xxx.xml parse trees for the .jack files in input dir to the --out dir, input/xml by default")
+                .long("xml"),
+        )
+        .arg(
+            Arg::with_name("map")
+                .help("writes a .map file linking each ROM address to its .asm line and .vm command")
+                .short("m")
+                .long("map"),
+        )
+        .arg(
+            Arg::with_name("extended")
+                .help("assembles with macros, .define, .include and pseudo-instructions like PUSH D")
+                .short("x")
+                .long("extended"),
+        )
+        .arg(
+            Arg::with_name("lst")
+                .help("writes a .lst listing with ROM addresses, words, source lines and symbols")
+                .long("lst"),
+        )
+        .arg(
+            Arg::with_name("warnings")
+                .help("reports unread variables and unused labels in the assembled program")
+                .short("W")
+                .long("warnings"),
+        )
+        .arg(
+            Arg::with_name("opt")
+                .help("optimization level of the VM translator output (0, 1 or 2)")
+                .long("opt")
+                .takes_value(true)
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("shared")
+                .help("jumps into shared call/return/comparison routines instead of inlining them")
+                .short("s")
+                .long("shared"),
+        )
+        .arg(
+            Arg::with_name("diff")
+                .help("runs the .vm files in input dir on the VM emulator and, translated, on the CPU emulator, reporting where they diverge")
+                .long("diff"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("format of the assembled program")
+                .short("f")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&Format::NAMES)
+                .default_value("hack"),
+        )
+        .arg(
+            Arg::with_name("run")
+                .help("runs the .hack or .asm input on the CPU emulator for this many instructions")
+                .long("run")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("screen")
+                .help("with --run, writes the screen to this .png or .pbm file at the end")
+                .long("screen")
+                .takes_value(true)
+                .requires("run"),
+        )
+        .arg(
+            Arg::with_name("every")
+                .help("with --screen, writes the screen every this many frames instead, to numbered files")
+                .long("every")
+                .takes_value(true)
+                .requires("screen"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .help("with --run, holds down the key with this code")
+                .long("key")
+                .takes_value(true)
+                .requires("run"),
+        )
+        .arg(
+            Arg::with_name("computer")
+                .help("with --run, runs the program on this Computer.hdl, reporting where it diverges from the CPU emulator")
+                .long("computer")
+                .takes_value(true)
+                .requires("run")
+                .conflicts_with("screen"),
+        )
+        .arg(
+            Arg::with_name("labels")
+                .help("when disassembling, replaces the addresses of jump targets with labels")
+                .short("l")
+                .long("labels"),
+        )
+        .arg(
+            Arg::with_name("hdl-path")
+                .help("directory searched for the chips an .hdl file uses when its own directory lacks them")
+                .long("hdl-path")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("use-hdl")
+                .help("builds this part from its .hdl file rather than simulating the builtin chip")
+                .long("use-hdl")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&builtin_names),
+        )
+        .arg(
+            Arg::with_name("no-builtins")
+                .help("builds every part with an .hdl file from it, down to Nand and DFF")
+                .long("no-builtins"),
+        )
+        .arg(
+            Arg::with_name("vcd")
+                .help("with a .tst script loading an .hdl file, writes the pins of the chip over time to this .vcd file")
+                .long("vcd")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vcd-internal")
+                .help("with --vcd, also writes the internal pins of the chip and the pins of its parts")
+                .long("vcd-internal")
+                .requires("vcd"),
+        )
+        .arg(
+            Arg::with_name("fuzz")
+                .help("compares the VM emulator with translated code on this many random programs")
+                .long("fuzz")
+                .takes_value(true),
         );
     let matches = app.get_matches();
+    let opt_level = match matches.value_of("opt").unwrap().parse() {
+        Ok(opt_level) if opt_level <= 2 => opt_level,
+        _ => {
+            eprintln!("error: --opt must be 0, 1 or 2");
+            process::exit(1);
+        }
+    };
+    let format = Format::from_name(matches.value_of("format").unwrap()).unwrap();
+    let options = differential::Options {
+        init: matches.is_present("init"),
+        opt_level,
+        shared_routines: matches.is_present("shared"),
+        ..Default::default()
+    };
+    if let Some(n) = matches.value_of("fuzz") {
+        fuzz(n.parse().expect("--fuzz takes a number"), options);
+        return;
+    }
     let input = Path::new(matches.value_of("input").unwrap())
         .canonicalize()
         .unwrap();
-    let files = input.read_dir().unwrap().filter(|x| match x.as_ref().ok() {
-        Some(dir_entry) => dir_entry.path().extension() == Some(OsStr::new("vm")),
-        None => false,
-    });
-    let asm_path = input
-        .join(format!(
-            "{}.asm",
-            input.file_name().unwrap().to_str().unwrap()
-        ))
-        .to_string_lossy()
-        .to_string();
-    {
-        let mut writer = vm_translator::code_writer::Writer::new(asm_path.as_str());
-        if matches.is_present("init") {
-            writer.write_init()
-        }
-        println!("{:?}", asm_path);
-        for file in files {
-            let file = file.unwrap().path();
-            let vm_path = file.to_str().unwrap();
-            println!("{:?}", vm_path);
-            writer.write(vm_path);
+    let hdl_options = chip::Options {
+        dirs: matches
+            .values_of("hdl-path")
+            .map(|dirs| dirs.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+        builtins: !matches.is_present("no-builtins"),
+        hdl_chips: matches
+            .values_of("use-hdl")
+            .map(|chips| chips.map(|chip| chip.to_string()).collect())
+            .unwrap_or_default(),
+    };
+    if input.extension() == Some(OsStr::new("tst")) {
+        let vcd = matches
+            .value_of("vcd")
+            .map(|vcd_path| (Path::new(vcd_path), matches.is_present("vcd-internal")));
+        run_test_script(&input, &hdl_options, vcd);
+        return;
+    }
+    if matches.is_present("run") {
+        let number = |name: &str| {
+            matches.value_of(name).map(|value| {
+                value.parse().unwrap_or_else(|_| {
+                    eprintln!("error: --{} takes a number", name);
+                    process::exit(1);
+                })
+            })
+        };
+        if let Some(computer) = matches.value_of("computer") {
+            let options = chip_differential::Options {
+                cycles: number("run").unwrap(),
+                key: number("key").map_or(0, |key: u64| key as u16),
+            };
+            run_on_computer(
+                &input,
+                matches.is_present("extended"),
+                Path::new(computer),
+                &hdl_options,
+                &options,
+            );
+            return;
         }
+        run_program(
+            &input,
+            matches.is_present("extended"),
+            number("run").unwrap(),
+            matches.value_of("screen").map(Path::new),
+            number("every"),
+            number("key").map(|key: u64| key as u16),
+        );
+        return;
+    }
+    if input.extension() == Some(OsStr::new("hack")) {
+        disassemble(
+            &input,
+            matches.value_of("output"),
+            matches.is_present("labels"),
+        );
+        return;
     }
+    if matches.is_present("xml") {
+        let xml_dir = matches
+            .value_of("output")
+            .map_or_else(|| input.join("xml"), PathBuf::from);
+        write_jack_xml(&input, &xml_dir);
+        return;
+    }
+    let (asm_path, vm_locations) = if input.extension() == Some(OsStr::new("asm")) {
+        (input.to_string_lossy().to_string(), vec![])
+    } else {
+        compile_jack(&input);
+        if matches.is_present("diff") {
+            diff(&input, &options);
+            return;
+        }
+        let files = input.read_dir().unwrap().filter(|x| match x.as_ref().ok() {
+            Some(dir_entry) => dir_entry.path().extension() == Some(OsStr::new("vm")),
+            None => false,
+        });
+        let asm_path = input
+            .join(format!(
+                "{}.asm",
+                input.file_name().unwrap().to_str().unwrap()
+            ))
+            .to_string_lossy()
+            .to_string();
+        let vm_locations = {
+            let mut writer = vm_translator::code_writer::Writer::new(asm_path.as_str());
+            writer.set_opt_level(opt_level);
+            writer.set_shared_routines(matches.is_present("shared"));
+            if matches.is_present("init") {
+                writer.write_init()
+            }
+            println!("{:?}", asm_path);
+            for file in files {
+                let file = file.unwrap().path();
+                let vm_path = file.to_str().unwrap();
+                println!("{:?}", vm_path);
+                if let Err(error) = writer.write(vm_path) {
+                    eprintln!("error: {}", error);
+                    process::exit(1);
+                }
+            }
+            writer.flush();
+            writer.source_map().to_vec()
+        };
+        (asm_path, vm_locations)
+    };
 
     let input = Path::new(asm_path.as_str()).canonicalize().unwrap();
     let output;
     if let Some(o) = matches.value_of("output") {
         output = o.to_string();
     } else {
-        let out_name = input.with_extension("hack").clone();
+        let out_name = input.with_extension(format.extension()).clone();
         output = input.with_file_name(out_name).to_string_lossy().to_string();
     };
     println!("{}", asm_path);
     println!("{}", output);
-    assembler::writer::Writer::write(input.to_str().unwrap(), output.as_str());
+    let warnings = write_program(
+        input.to_str().unwrap(),
+        output.as_str(),
+        format,
+        matches.is_present("extended"),
+        matches.is_present("map").then_some(vm_locations.as_slice()),
+    );
+    if matches.is_present("warnings") {
+        for warning in &warnings {
+            eprintln!("{}\n", warning);
+        }
+    }
+    if matches.is_present("lst") {
+        write_listing(
+            input.to_str().unwrap(),
+            output.as_str(),
+            matches.is_present("extended"),
+        );
+    }
+}
+
+fn report_asm_errors(errors: &[AsmError]) -> ! {
+    for error in errors {
+        eprintln!("{}\n", error);
+    }
+    eprintln!("aborting due to {} previous error(s)", errors.len());
+    process::exit(1);
+}
+
+/// Assembles `asm_path`, in the extended syntax if `extended`, into `hack_path`, and writes
+/// the source map next to it as `xxx.map` if `vm_locations` are given. Returns the warnings
+/// about the program.
+fn write_program(
+    asm_path: &str,
+    hack_path: &str,
+    format: Format,
+    extended: bool,
+    vm_locations: Option<&[Option<VmLocation>]>,
+) -> Vec<AsmWarning> {
+    let assembly = assemble_file(asm_path, extended);
+    let f = fs::File::create(hack_path).unwrap();
+    format
+        .write(&assembly.words, std::io::BufWriter::new(f))
+        .unwrap();
+    if let Some(vm_locations) = vm_locations {
+        let map_path = Path::new(hack_path).with_extension("map");
+        println!("{:?}", map_path);
+        fs::write(
+            map_path,
+            SourceMap::new(asm_path, &assembly.lines, vm_locations).to_json(),
+        )
+        .unwrap();
+    }
+    assembly.warnings
+}
+
+/// Assembles `asm_path`, in the extended syntax if `extended`, exiting on errors.
+fn assemble_file(asm_path: &str, extended: bool) -> Assembly {
+    let parser = if extended {
+        Parser::new_extended(asm_path).unwrap_or_else(|errors| report_asm_errors(&errors))
+    } else {
+        Parser::new(asm_path).unwrap_or_else(|error| {
+            report_asm_errors(&[AsmError::Io {
+                file: asm_path.to_string(),
+                error,
+            }])
+        })
+    };
+    assembler::writer::Writer::assemble_program(parser)
+        .unwrap_or_else(|errors| report_asm_errors(&errors))
+}
+
+/// Writes the listing of `asm_path`, in the extended syntax if `extended`, next to
+/// `hack_path` as `xxx.lst`.
+fn write_listing(asm_path: &str, hack_path: &str, extended: bool) {
+    let asm = fs::read_to_string(asm_path).unwrap();
+    let lst = if extended {
+        assembler::listing::listing_extended(asm_path, &asm)
+    } else {
+        assembler::listing::listing(asm_path, &asm)
+    };
+    let lst = lst.unwrap_or_else(|errors| report_asm_errors(&errors));
+    let lst_path = Path::new(hack_path).with_extension("lst");
+    println!("{:?}", lst_path);
+    fs::write(lst_path, lst).unwrap();
+}
+
+/// Runs the `.tst` script at `tst_path`, building the chips it loads as `hdl_options` say
+/// and, with `vcd`, dumping their pins, internal ones too if asked, to a `.vcd` file.
+fn run_test_script(tst_path: &Path, hdl_options: &chip::Options, vcd: Option<(&Path, bool)>) {
+    let commands =
+        test_script::parser::parse_file(&tst_path.to_string_lossy()).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(1);
+        });
+    let target = test_script::runner::target(&commands);
+    if vcd.is_some() && target != Target::Hardware {
+        eprintln!("error: --vcd needs a script loading an .hdl file");
+        process::exit(1);
+    }
+    let dir = tst_path.parent().unwrap_or_else(|| Path::new("."));
+    let tst_path = tst_path.to_string_lossy();
+    let result = match (target, vcd) {
+        (Target::Vm, _) => {
+            test_script::runner::run_file(&tst_path, Vm::from_sources(&[]).unwrap()).map(|_| ())
+        }
+        (Target::Hardware, Some((vcd_path, internal))) => {
+            let chip = TracedChip::new(Chip::new(hdl_options), vcd_path, internal);
+            let mut runner = Runner::new(chip, dir);
+            let result = runner.run(&commands);
+            // The dump up to a failure is what shows how it came about.
+            let finished = runner.simulator_mut().finish();
+            let result = result.and(finished.map_err(TestError::from));
+            if result.is_ok() {
+                println!("{:?}", vcd_path);
+            }
+            result
+        }
+        (Target::Hardware, None) => {
+            test_script::runner::run_file(&tst_path, Chip::new(hdl_options)).map(|_| ())
+        }
+        (Target::Cpu, _) => {
+            test_script::runner::run_file(&tst_path, Machine::new(vec![]).unwrap()).map(|_| ())
+        }
+    };
+    match result {
+        Ok(_) => println!("End of script - Comparison ended successfully"),
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs a `.hack` or `.asm` program for `cycles` instructions with `key` held down, writing
+/// the screen to `screen` at the end or, with `every`, every `every` frames.
+fn run_program(
+    path: &Path,
+    extended: bool,
+    cycles: u64,
+    screen: Option<&Path>,
+    every: Option<u64>,
+    key: Option<u16>,
+) {
+    let words = read_program(path, extended);
+    let result = Machine::new(words).and_then(|mut machine| {
+        machine.ram_mut()[screen::KBD] = key.unwrap_or(0);
+        match (screen, every) {
+            (Some(screen), Some(every)) => {
+                let paths = screen::Recorder::new(screen, every).run(&mut machine, cycles)?;
+                println!("{} frame(s)", paths.len());
+            }
+            (Some(screen), None) => {
+                machine.run(cycles as usize)?;
+                screen::write_image(machine.ram(), screen)?;
+            }
+            (None, _) => machine.run(cycles as usize)?,
+        }
+        Ok(())
+    });
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+/// Runs a `.hack` or `.asm` program on the `Computer.hdl` at `computer`, built as
+/// `hdl_options` say, next to the CPU emulator, exiting at the first divergence.
+fn run_on_computer(
+    path: &Path,
+    extended: bool,
+    computer: &Path,
+    hdl_options: &chip::Options,
+    options: &chip_differential::Options,
+) {
+    let words = read_program(path, extended);
+    let mut computer = Chip::load(computer, hdl_options).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+    match chip_differential::compare(&mut computer, &words, options) {
+        Ok(cycles) => println!("No divergence in {} cycles", cycles),
+        Err(divergence) => {
+            eprintln!("{}", divergence);
+            process::exit(1);
+        }
+    }
+}
+
+/// The words of a `.hack` program, or of an `.asm` one, assembled in the extended syntax if
+/// `extended`, exiting on errors.
+fn read_program(path: &Path, extended: bool) -> Vec<u16> {
+    if path.extension() == Some(OsStr::new("asm")) {
+        assemble_file(path.to_str().unwrap(), extended).words
+    } else {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|hack| parse_hack(&hack))
+            .unwrap_or_else(|error| {
+                eprintln!("{}: {}", path.display(), error);
+                process::exit(1);
+            })
+    }
+}
+
+/// Disassembles `hack_path` into `output`, or to stdout.
+fn disassemble(hack_path: &Path, output: Option<&str>, labels: bool) {
+    let words = fs::read_to_string(hack_path)
+        .map_err(|e| e.to_string())
+        .and_then(|hack| parse_hack(&hack))
+        .unwrap_or_else(|error| {
+            eprintln!("{}: {}", hack_path.display(), error);
+            process::exit(1);
+        });
+    let (asm, invalid) = disassembler::disassemble(&words, labels);
+    for invalid_word in &invalid {
+        eprintln!("warning: {}", invalid_word);
+    }
+    match output {
+        Some(output) => fs::write(output, asm).unwrap(),
+        None => print!("{}", asm),
+    }
+}
+
+/// Compares the VM emulator with translated code on the `.vm` files in `dir`.
+fn diff(dir: &Path, options: &differential::Options) {
+    let mut vm_paths: Vec<PathBuf> = dir
+        .read_dir()
+        .unwrap()
+        .filter_map(|x| x.ok().map(|dir_entry| dir_entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("vm")))
+        .collect();
+    vm_paths.sort();
+    let vm_sources: Vec<(String, String)> = vm_paths
+        .iter()
+        .map(|path| {
+            let vm_path = path.to_string_lossy().to_string();
+            let vm = fs::read_to_string(path).unwrap();
+            (vm_path, vm)
+        })
+        .collect();
+    let vm_sources: Vec<(&str, &str)> = vm_sources
+        .iter()
+        .map(|(vm_path, vm)| (vm_path.as_str(), vm.as_str()))
+        .collect();
+    match differential::compare(&vm_sources, options) {
+        Ok(steps) => println!("No divergence in {} VM commands", steps),
+        Err(divergence) => {
+            eprintln!("{}", divergence);
+            process::exit(1);
+        }
+    }
+}
+
+/// Compares the VM emulator with translated code on `n` random straight-line programs.
+fn fuzz(n: u64, options: differential::Options) {
+    let options = differential::Options {
+        init: false,
+        ..options
+    };
+    for seed in 1..=n {
+        let vm = differential::random_program(&mut differential::Rng::new(seed), 40);
+        if let Err(divergence) = differential::compare(&[("Random.vm", &vm)], &options) {
+            eprintln!("seed {}:\n{}\n{}", seed, vm, divergence);
+            process::exit(1);
+        }
+    }
+    println!("No divergence in {} random programs", n);
+}
+
+fn jack_files(dir: &Path) -> Vec<PathBuf> {
+    dir.read_dir()
+        .unwrap()
+        .filter_map(|x| match x.ok() {
+            Some(dir_entry) if dir_entry.path().extension() == Some(OsStr::new("jack")) => {
+                Some(dir_entry.path())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compiles every `.jack` file in `dir` into a `.vm` file next to it.
+fn compile_jack(dir: &Path) {
+    for file in jack_files(dir) {
+        let jack_path = file.to_string_lossy().to_string();
+        let vm_path = file.with_extension("vm").to_string_lossy().to_string();
+        println!("{:?}", vm_path);
+        if let Err(error) = jack_compiler::code_generator::compile_file(&jack_path, &vm_path) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+}
+
+/// Writes the tokens and the parse tree of each `.jack` file in `dir` to `xml_dir`, away from
+/// the `xxxT.xml` and `xxx.xml` the book gives to compare them with.
+fn write_jack_xml(dir: &Path, xml_dir: &Path) {
+    fs::create_dir_all(xml_dir).unwrap();
+    for file in jack_files(dir) {
+        let jack_path = file.to_string_lossy().to_string();
+        let mut engine = match jack_compiler::compilation_engine::CompilationEngine::new(&jack_path)
+        {
+            Ok(engine) => engine,
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        };
+        let stem = file.file_stem().unwrap().to_string_lossy();
+        let xml_path = xml_dir.join(format!("{}T.xml", stem));
+        println!("{:?}", xml_path);
+        fs::write(&xml_path, engine.tokenizer().to_xml()).unwrap();
+        let class = match engine.compile_class() {
+            Ok(class) => class,
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(1);
+            }
+        };
+        let xml_path = xml_dir.join(format!("{}.xml", stem));
+        println!("{:?}", xml_path);
+        fs::write(&xml_path, jack_compiler::xml::to_xml(&class)).unwrap();
+    }
 }
@@ -0,0 +1,148 @@
+//! Links ROM addresses of a `.hack` program back to `.asm` lines and `.vm` commands.
+
+/// A command in a `.vm` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmLocation {
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// The command as written, without comments.
+    pub command: String,
+}
+
+/// Where the instruction at `rom` came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub rom: usize,
+    /// 1-based line number in the `.asm` file.
+    pub asm_line: usize,
+    /// `None` for code that does not come from a VM command, such as the bootstrap code.
+    pub vm: Option<VmLocation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceMap {
+    pub asm_file: String,
+    /// One entry per ROM address, in address order.
+    pub entries: Vec<Entry>,
+}
+
+impl SourceMap {
+    /// Combines `asm_lines`, the `.asm` line of each ROM address as reported by the assembler,
+    /// with `vm_locations`, the VM command of each `.asm` line as recorded by
+    /// `vm_translator::code_writer::Writer`. `vm_locations` may be empty for hand-written assembly.
+    pub fn new(asm_file: &str, asm_lines: &[usize], vm_locations: &[Option<VmLocation>]) -> Self {
+        let entries = asm_lines
+            .iter()
+            .enumerate()
+            .map(|(rom, &asm_line)| Entry {
+                rom,
+                asm_line,
+                vm: vm_locations.get(asm_line - 1).cloned().flatten(),
+            })
+            .collect();
+        Self {
+            asm_file: asm_file.to_string(),
+            entries,
+        }
+    }
+
+    /// The VM command the instruction at `rom` was translated from.
+    pub fn lookup(&self, rom: usize) -> Option<&VmLocation> {
+        self.entries.get(rom).and_then(|entry| entry.vm.as_ref())
+    }
+
+    /// Renders the map as JSON, one entry per line.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\n  \"asm_file\": {},\n  \"entries\": [\n",
+            json_string(&self.asm_file)
+        );
+        for (i, entry) in self.entries.iter().enumerate() {
+            json += &format!(
+                "    {{\"rom\": {}, \"asm_line\": {}",
+                entry.rom, entry.asm_line
+            );
+            if let Some(vm) = &entry.vm {
+                json += &format!(
+                    ", \"vm_file\": {}, \"vm_line\": {}, \"vm_command\": {}",
+                    json_string(&vm.file),
+                    vm.line,
+                    json_string(&vm.command)
+                );
+            }
+            json += if i + 1 < self.entries.len() {
+                "},\n"
+            } else {
+                "}\n"
+            };
+        }
+        json += "  ]\n}\n";
+        json
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => json += "\\\"",
+            '\\' => json += "\\\\",
+            c if (c as u32) < 0x20 => json += &format!("\\u{:04x}", c as u32),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::parser::Parser;
+    use crate::assembler::writer::Writer as AsmWriter;
+    use crate::vm_translator::code_writer::Writer;
+
+    #[test]
+    fn maps_rom_back_to_vm_lines() {
+        let vm = "// Pushes two constants.\npush constant 7\n\npush constant 8 // the second\n";
+        for opt_level in 0..=2 {
+            let mut writer = Writer::from_writer(vec![]);
+            writer.set_opt_level(opt_level);
+            writer.write_parser("Two.vm", vm.parse().unwrap()).unwrap();
+            writer.flush();
+            let vm_locations = writer.source_map().to_vec();
+            let asm = String::from_utf8(writer.into_inner()).unwrap();
+            let assembly = AsmWriter::assemble_program(Parser::from_str("Two.asm", &asm)).unwrap();
+            let map = SourceMap::new("Two.asm", &assembly.lines, &vm_locations);
+
+            assert_eq!(map.entries.len(), assembly.words.len());
+            let first = VmLocation {
+                file: "Two.vm".to_string(),
+                line: 2,
+                command: "push constant 7".to_string(),
+            };
+            let second = VmLocation {
+                file: "Two.vm".to_string(),
+                line: 4,
+                command: "push constant 8".to_string(),
+            };
+            // Every instruction comes from one of the commands, the first ones first.
+            let split = (0..map.entries.len())
+                .find(|&rom| map.lookup(rom) == Some(&second))
+                .unwrap();
+            assert!(split > 0, "opt {}", opt_level);
+            for rom in 0..map.entries.len() {
+                let expected = if rom < split { &first } else { &second };
+                assert_eq!(
+                    map.lookup(rom),
+                    Some(expected),
+                    "opt {}, rom {}",
+                    opt_level,
+                    rom
+                );
+            }
+            assert_eq!(map.lookup(map.entries.len()), None);
+        }
+    }
+}
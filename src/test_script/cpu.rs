@@ -0,0 +1,70 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::assembler::parser::Parser;
+use crate::assembler::writer::Writer;
+use crate::cpu_emulator::machine::{parse_hack, Machine};
+use crate::test_script::parser::Step;
+use crate::test_script::runner::{parse_indexed, Simulator};
+
+/// Runs scripts written for the CPU emulator: `load` takes a `.hack` or `.asm` file
+/// and `ticktock` executes one instruction.
+impl Simulator for Machine {
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let program = if path.extension() == Some(OsStr::new("asm")) {
+            Writer::assemble(Parser::from_str(&path.to_string_lossy(), &text)).map_err(
+                |errors| {
+                    errors
+                        .iter()
+                        .map(|e| e.to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                },
+            )?
+        } else {
+            parse_hack(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        };
+        *self = Machine::new(program)?;
+        Ok(())
+    }
+
+    fn get(&self, variable: &str) -> Result<i32, String> {
+        let value = match variable {
+            "A" => self.a(),
+            "D" => self.d(),
+            "PC" => self.pc(),
+            _ => match parse_indexed(variable) {
+                Some(("RAM", i)) if i < self.ram().len() => self.ram()[i],
+                Some(("ROM", i)) | Some(("ROM32K", i)) if i < self.rom().len() => self.rom()[i],
+                _ => return Err(format!("unknown variable `{}`.", variable)),
+            },
+        };
+        Ok(value as i16 as i32)
+    }
+
+    fn set(&mut self, variable: &str, value: i32) -> Result<(), String> {
+        let value = value as u16;
+        match variable {
+            "A" => self.set_a(value),
+            "D" => self.set_d(value),
+            "PC" => self.set_pc(value),
+            _ => match parse_indexed(variable) {
+                Some(("RAM", i)) if i < self.ram().len() => self.ram_mut()[i] = value,
+                _ => return Err(format!("unknown variable `{}`.", variable)),
+            },
+        }
+        Ok(())
+    }
+
+    fn step(&mut self, step: Step) -> Result<(), String> {
+        match step {
+            Step::TickTock => Machine::step(self),
+            _ => Err(format!(
+                "`{}` is not supported by the CPU emulator.",
+                format!("{:?}", step).to_lowercase()
+            )),
+        }
+    }
+}
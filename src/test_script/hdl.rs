@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::hdl::chip::Chip;
+use crate::hdl::vcd::Recorder;
+use crate::test_script::parser::Step;
+use crate::test_script::runner::Simulator;
+
+/// Runs scripts written for the hardware simulator: `load` takes an `.hdl` file, `eval`
+/// propagates the inputs set to the outputs, and `tick` and `tock` are the rising and falling
+/// edges of the clock.
+impl Simulator for Chip {
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        *self = Chip::load(path, self.options())?;
+        Ok(())
+    }
+
+    fn get(&self, variable: &str) -> Result<i32, String> {
+        let value = Chip::get(self, variable)?;
+        // Buses as wide as a Hack word hold two's complement numbers.
+        match self.width(variable) {
+            Some(16) => Ok(value as i16 as i32),
+            _ => Ok(value as i32),
+        }
+    }
+
+    fn set(&mut self, variable: &str, value: i32) -> Result<(), String> {
+        Chip::set(self, variable, value as u16)
+    }
+
+    fn step(&mut self, step: Step) -> Result<(), String> {
+        match step {
+            Step::Eval => {
+                self.eval();
+                Ok(())
+            }
+            Step::Tick => {
+                self.tick();
+                Ok(())
+            }
+            Step::Tock => {
+                self.tock();
+                Ok(())
+            }
+            Step::TickTock => {
+                self.tick();
+                self.tock();
+                Ok(())
+            }
+            Step::VmStep => Err("`vmstep` is not supported by the hardware simulator.".to_string()),
+        }
+    }
+}
+
+/// A chip driven by a `.tst` script that also dumps its pins to a `.vcd` file, each step of
+/// the script taking one unit of time.
+pub struct TracedChip {
+    chip: Chip,
+    vcd_path: PathBuf,
+    /// Whether to dump internal pins too.
+    internal: bool,
+    recorder: Option<Recorder<BufWriter<File>>>,
+    time: u64,
+}
+
+impl TracedChip {
+    pub fn new(chip: Chip, vcd_path: &Path, internal: bool) -> Self {
+        Self {
+            chip,
+            vcd_path: vcd_path.to_path_buf(),
+            internal,
+            recorder: None,
+            time: 0,
+        }
+    }
+
+    /// Flushes the dump of the last chip loaded.
+    pub fn finish(&mut self) -> Result<(), String> {
+        match self.recorder.take() {
+            Some(recorder) => recorder
+                .into_inner()
+                .map(|_| ())
+                .map_err(|e| format!("{}: {}", self.vcd_path.display(), e)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Simulator for TracedChip {
+    /// Loads the chip and starts the dump anew.
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        Simulator::load(&mut self.chip, path)?;
+        let vcd_path = &self.vcd_path;
+        let error = |e: std::io::Error| format!("{}: {}", vcd_path.display(), e);
+        let out = BufWriter::new(File::create(vcd_path).map_err(error)?);
+        let mut recorder = Recorder::new(out, &self.chip, self.internal).map_err(error)?;
+        recorder.record(0, &self.chip).map_err(error)?;
+        self.recorder = Some(recorder);
+        self.time = 0;
+        Ok(())
+    }
+
+    fn get(&self, variable: &str) -> Result<i32, String> {
+        Simulator::get(&self.chip, variable)
+    }
+
+    fn set(&mut self, variable: &str, value: i32) -> Result<(), String> {
+        Simulator::set(&mut self.chip, variable, value)
+    }
+
+    fn step(&mut self, step: Step) -> Result<(), String> {
+        if step == Step::TickTock {
+            self.step(Step::Tick)?;
+            return self.step(Step::Tock);
+        }
+        Simulator::step(&mut self.chip, step)?;
+        self.time += 1;
+        if let Some(recorder) = &mut self.recorder {
+            recorder
+                .record(self.time, &self.chip)
+                .map_err(|e| format!("{}: {}", self.vcd_path.display(), e))?;
+        }
+        Ok(())
+    }
+}
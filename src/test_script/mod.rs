@@ -0,0 +1,5 @@
+pub mod cpu;
+pub mod hdl;
+pub mod parser;
+pub mod runner;
+pub mod vm;
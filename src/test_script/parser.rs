@@ -0,0 +1,417 @@
+use std::fs;
+
+/// Simulation steps a target can perform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Eval,
+    Tick,
+    Tock,
+    TickTock,
+    VmStep,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Binary,
+    Decimal,
+    Hex,
+    String,
+}
+
+/// A column of `output-list`, e.g. `RAM[256]%D2.6.2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputColumn {
+    pub variable: String,
+    pub format: Format,
+    pub pad_left: usize,
+    pub len: usize,
+    pub pad_right: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Condition of `while`, e.g. `RAM[0] <> 0`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub variable: String,
+    pub comparison: Comparison,
+    pub value: i32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<OutputColumn>),
+    Set(String, i32),
+    Step(Step),
+    Output,
+    Echo(String),
+    ClearEcho,
+    Repeat(usize, Vec<Command>),
+    While(Condition, Vec<Command>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    /// `,`, `;` or `!`
+    End,
+    Open,
+    Close,
+}
+
+/// Parses the `.tst` file at `tst_path`.
+pub fn parse_file(tst_path: &str) -> Result<Vec<Command>, String> {
+    let tst = fs::read_to_string(tst_path).map_err(|e| format!("{}: {}", tst_path, e))?;
+    parse(&tst).map_err(|e| format!("{}: {}", tst_path, e))
+}
+
+/// Parses the text of a `.tst` script.
+pub fn parse(tst: &str) -> Result<Vec<Command>, String> {
+    let tokens = tokenize(tst)?;
+    let mut current = 0;
+    let commands = parse_block(&tokens, &mut current)?;
+    if current < tokens.len() {
+        return Err("unexpected `}`.".to_string());
+    }
+    Ok(commands)
+}
+
+fn tokenize(tst: &str) -> Result<Vec<(usize, Token)>, String> {
+    let chars: Vec<char> = tst.chars().collect();
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            let start = line;
+            i += 2;
+            loop {
+                if i + 1 >= chars.len() {
+                    return Err(format!("line {}: unterminated comment.", start));
+                }
+                if chars[i] == '*' && chars[i + 1] == '/' {
+                    i += 2;
+                    break;
+                }
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+        } else if c == '"' {
+            let end = match chars[i + 1..].iter().position(|&c| c == '"') {
+                Some(end) => i + 1 + end,
+                None => return Err(format!("line {}: unterminated string.", line)),
+            };
+            tokens.push((line, Token::Text(chars[i + 1..end].iter().collect())));
+            i = end + 1;
+        } else if ",;!".contains(c) {
+            tokens.push((line, Token::End));
+            i += 1;
+        } else if c == '{' {
+            tokens.push((line, Token::Open));
+            i += 1;
+        } else if c == '}' {
+            tokens.push((line, Token::Close));
+            i += 1;
+        } else {
+            let start = i;
+            while i < chars.len() && !chars[i].is_whitespace() && !",;!{}\"".contains(chars[i]) {
+                i += 1;
+            }
+            tokens.push((line, Token::Word(chars[start..i].iter().collect())));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses commands until `}` or the end of the script.
+fn parse_block(tokens: &[(usize, Token)], current: &mut usize) -> Result<Vec<Command>, String> {
+    let mut commands = vec![];
+    while *current < tokens.len() {
+        let (line, token) = &tokens[*current];
+        let name = match token {
+            Token::Close => break,
+            Token::End => {
+                *current += 1;
+                continue;
+            }
+            Token::Word(name) => name.clone(),
+            _ => return Err(format!("line {}: expected a command.", line)),
+        };
+        *current += 1;
+        let line = *line;
+        let command = match name.as_str() {
+            "repeat" | "while" => {
+                let mut args = vec![];
+                while let Some((_, Token::Word(arg))) = tokens.get(*current) {
+                    args.push(arg.clone());
+                    *current += 1;
+                }
+                match tokens.get(*current) {
+                    Some((_, Token::Open)) => *current += 1,
+                    _ => return Err(format!("line {}: expected `{{` after `{}`.", line, name)),
+                }
+                let body = parse_block(tokens, current)?;
+                match tokens.get(*current) {
+                    Some((_, Token::Close)) => *current += 1,
+                    _ => return Err(format!("line {}: `{}` is not closed.", line, name)),
+                }
+                if name == "repeat" {
+                    let n = match args.as_slice() {
+                        [n] => n.parse().ok(),
+                        _ => None,
+                    };
+                    match n {
+                        Some(n) => Command::Repeat(n, body),
+                        None => return Err(format!("line {}: `repeat` needs a count.", line)),
+                    }
+                } else {
+                    let condition = parse_condition(&args.concat())
+                        .map_err(|e| format!("line {}: {}", line, e))?;
+                    Command::While(condition, body)
+                }
+            }
+            _ => {
+                let mut args = vec![];
+                loop {
+                    match tokens.get(*current) {
+                        Some((_, Token::Word(arg))) | Some((_, Token::Text(arg))) => {
+                            args.push(arg.clone())
+                        }
+                        Some((_, Token::End)) => {
+                            *current += 1;
+                            break;
+                        }
+                        _ => return Err(format!("line {}: `{}` is not terminated.", line, name)),
+                    }
+                    *current += 1;
+                }
+                parse_command(&name, args).map_err(|e| format!("line {}: {}", line, e))?
+            }
+        };
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+fn parse_command(name: &str, args: Vec<String>) -> Result<Command, String> {
+    let single = |args: Vec<String>| {
+        if args.len() == 1 {
+            Ok(args[0].clone())
+        } else {
+            Err(format!("`{}` takes exactly one argument.", name))
+        }
+    };
+    let none = |command: Command, args: Vec<String>| {
+        if args.is_empty() {
+            Ok(command)
+        } else {
+            Err(format!("`{}` takes no arguments.", name))
+        }
+    };
+    match name {
+        "load" => match args.len() {
+            0 => Ok(Command::Load(None)),
+            _ => Ok(Command::Load(Some(single(args)?))),
+        },
+        "output-file" => Ok(Command::OutputFile(single(args)?)),
+        "compare-to" => Ok(Command::CompareTo(single(args)?)),
+        "output-list" => Ok(Command::OutputList(
+            args.iter()
+                .map(|a| parse_column(a))
+                .collect::<Result<_, _>>()?,
+        )),
+        "set" => match args.as_slice() {
+            [variable, value] => Ok(Command::Set(variable.clone(), parse_value(value)?)),
+            _ => Err("`set` takes a variable and a value.".to_string()),
+        },
+        "eval" => none(Command::Step(Step::Eval), args),
+        "tick" => none(Command::Step(Step::Tick), args),
+        "tock" => none(Command::Step(Step::Tock), args),
+        "ticktock" => none(Command::Step(Step::TickTock), args),
+        "vmstep" => none(Command::Step(Step::VmStep), args),
+        "output" => none(Command::Output, args),
+        "echo" => Ok(Command::Echo(args.join(" "))),
+        "clear-echo" => none(Command::ClearEcho, args),
+        _ => Err(format!("unknown command `{}`.", name)),
+    }
+}
+
+/// Parses `variable%F<left>.<len>.<right>`.
+fn parse_column(column: &str) -> Result<OutputColumn, String> {
+    let (variable, spec) = match column.find('%') {
+        Some(index) => (&column[..index], &column[index + 1..]),
+        None => (column, "D1.6.1"),
+    };
+    let format = match spec.chars().next() {
+        Some('B') => Format::Binary,
+        Some('D') => Format::Decimal,
+        Some('X') => Format::Hex,
+        Some('S') => Format::String,
+        _ => return Err(format!("invalid output format `{}`.", column)),
+    };
+    let widths: Vec<usize> = spec[1..]
+        .split('.')
+        .map(|w| w.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid output format `{}`.", column))?;
+    match widths.as_slice() {
+        [pad_left, len, pad_right] => Ok(OutputColumn {
+            variable: variable.to_string(),
+            format,
+            pad_left: *pad_left,
+            len: *len,
+            pad_right: *pad_right,
+        }),
+        _ => Err(format!("invalid output format `{}`.", column)),
+    }
+}
+
+/// Parses a value of `set`: decimal, or `%B`, `%X` or `%D` prefixed.
+pub fn parse_value(value: &str) -> Result<i32, String> {
+    let (radix, digits) = match value.get(..2) {
+        Some("%B") => (2, &value[2..]),
+        Some("%X") => (16, &value[2..]),
+        Some("%D") => (10, &value[2..]),
+        _ => (10, value),
+    };
+    let parsed = if radix == 10 {
+        digits.parse::<i32>().ok()
+    } else {
+        // Binary and hex values are 16-bit patterns, e.g. `%B1111111111111111` is -1.
+        u16::from_str_radix(digits, radix)
+            .ok()
+            .map(|v| v as i16 as i32)
+    };
+    parsed.ok_or_else(|| format!("invalid value `{}`.", value))
+}
+
+fn parse_condition(condition: &str) -> Result<Condition, String> {
+    let operators = [
+        ("<>", Comparison::Ne),
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("=", Comparison::Eq),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+    for (operator, comparison) in operators.iter() {
+        if let Some(index) = condition.find(operator) {
+            return Ok(Condition {
+                variable: condition[..index].to_string(),
+                comparison: *comparison,
+                value: parse_value(&condition[index + operator.len()..])?,
+            });
+        }
+    }
+    Err(format!("invalid condition `{}`.", condition))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(variable: &str, format: Format, widths: (usize, usize, usize)) -> OutputColumn {
+        OutputColumn {
+            variable: variable.to_string(),
+            format,
+            pad_left: widths.0,
+            len: widths.1,
+            pad_right: widths.2,
+        }
+    }
+
+    #[test]
+    fn output_list_formats() {
+        let commands =
+            parse("output-list RAM[0]%D1.6.1 in%B2.1.2 out%S1.4.1 time%X0.4.0 PC;").unwrap();
+        let columns = vec![
+            column("RAM[0]", Format::Decimal, (1, 6, 1)),
+            column("in", Format::Binary, (2, 1, 2)),
+            column("out", Format::String, (1, 4, 1)),
+            column("time", Format::Hex, (0, 4, 0)),
+            column("PC", Format::Decimal, (1, 6, 1)),
+        ];
+        assert_eq!(commands, vec![Command::OutputList(columns)]);
+        for column in &["a%Q1.2.3", "a%D1.2", "a%D1.x.3"] {
+            let message = format!("line 1: invalid output format `{}`.", column);
+            assert_eq!(parse(&format!("output-list {};", column)), Err(message));
+        }
+    }
+
+    #[test]
+    fn repeat() {
+        let tst = "repeat 3 {
+    ticktock;
+    output;
+}
+set RAM[0] %B101,
+";
+        let body = vec![Command::Step(Step::TickTock), Command::Output];
+        let commands = vec![
+            Command::Repeat(3, body),
+            Command::Set("RAM[0]".to_string(), 5),
+        ];
+        assert_eq!(parse(tst), Ok(commands));
+        assert_eq!(
+            parse("repeat { tick; }"),
+            Err("line 1: `repeat` needs a count.".to_string())
+        );
+        assert_eq!(
+            parse("repeat 2 {\ntick;\n"),
+            Err("line 1: `repeat` is not closed.".to_string())
+        );
+    }
+
+    #[test]
+    fn while_loops() {
+        let tst = "while RAM[0] <> %X10 {
+    repeat 2 { vmstep; }
+}
+while PC<=-3 { eval; }
+";
+        let inner = Command::Repeat(2, vec![Command::Step(Step::VmStep)]);
+        let ne = Condition {
+            variable: "RAM[0]".to_string(),
+            comparison: Comparison::Ne,
+            value: 16,
+        };
+        let le = Condition {
+            variable: "PC".to_string(),
+            comparison: Comparison::Le,
+            value: -3,
+        };
+        let commands = vec![
+            Command::While(ne, vec![inner]),
+            Command::While(le, vec![Command::Step(Step::Eval)]),
+        ];
+        assert_eq!(parse(tst), Ok(commands));
+        assert_eq!(
+            parse("while RAM[0] { eval; }"),
+            Err("line 1: invalid condition `RAM[0]`.".to_string())
+        );
+    }
+}
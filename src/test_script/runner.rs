@@ -0,0 +1,362 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::test_script::parser::{parse_file, Command, Comparison, Format, OutputColumn, Step};
+
+/// Something a `.tst` script can drive: the CPU emulator, the VM emulator or a chip.
+pub trait Simulator {
+    /// Loads the program or chip named by `load`; a bare `load` passes the script's directory.
+    fn load(&mut self, path: &Path) -> Result<(), String>;
+    fn get(&self, variable: &str) -> Result<i32, String>;
+    fn set(&mut self, variable: &str, value: i32) -> Result<(), String>;
+    fn step(&mut self, step: Step) -> Result<(), String>;
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TestError {
+    Script(String),
+    /// The produced output differs from the compare file. `line` and `column` are 1-based;
+    /// columns are counted between `|` separators.
+    Mismatch {
+        line: usize,
+        column: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestError::Script(message) => write!(f, "{}", message),
+            TestError::Mismatch {
+                line,
+                column,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Comparison failure at line {}, column {}\nexpected: {}\n  actual: {}",
+                line, column, expected, actual
+            ),
+        }
+    }
+}
+
+impl From<String> for TestError {
+    fn from(message: String) -> Self {
+        TestError::Script(message)
+    }
+}
+
+pub struct Runner<S: Simulator> {
+    simulator: S,
+    dir: PathBuf,
+    columns: Vec<OutputColumn>,
+    output_path: Option<PathBuf>,
+    output: Vec<String>,
+    compare: Option<Vec<String>>,
+    time: usize,
+    ticked: bool,
+    echo: Option<String>,
+}
+
+impl<S: Simulator> Runner<S> {
+    /// Creates a runner resolving file names in scripts against `dir`.
+    pub fn new(simulator: S, dir: &Path) -> Self {
+        Self {
+            simulator,
+            dir: dir.to_path_buf(),
+            columns: vec![],
+            output_path: None,
+            output: vec![],
+            compare: None,
+            time: 0,
+            ticked: false,
+            echo: None,
+        }
+    }
+
+    /// Runs `commands`, then writes what was output so far to the output file, even on failure.
+    pub fn run(&mut self, commands: &[Command]) -> Result<(), TestError> {
+        let result = self.execute(commands);
+        if let Some(path) = &self.output_path {
+            let mut out = self.output.join("\n");
+            out.push('\n');
+            fs::write(path, out).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+        result
+    }
+
+    pub fn simulator(&self) -> &S {
+        &self.simulator
+    }
+
+    pub fn simulator_mut(&mut self) -> &mut S {
+        &mut self.simulator
+    }
+
+    /// Lines output so far, including the `output-list` header.
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    /// The last message given to `echo`.
+    pub fn echo(&self) -> Option<&str> {
+        self.echo.as_deref()
+    }
+
+    fn execute(&mut self, commands: &[Command]) -> Result<(), TestError> {
+        for command in commands {
+            match command {
+                Command::Load(name) => {
+                    let path = match name {
+                        Some(name) => self.dir.join(name),
+                        None => self.dir.clone(),
+                    };
+                    self.simulator.load(&path)?;
+                }
+                Command::OutputFile(name) => self.output_path = Some(self.dir.join(name)),
+                Command::CompareTo(name) => {
+                    let path = self.dir.join(name);
+                    let cmp = fs::read_to_string(&path)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                    self.compare = Some(cmp.lines().map(|l| l.trim_end().to_string()).collect());
+                }
+                Command::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    let header = self.columns.iter().map(header).collect::<String>() + "|";
+                    self.emit(header)?;
+                }
+                Command::Set(variable, value) => self.simulator.set(variable, *value)?,
+                Command::Step(step) => {
+                    self.simulator.step(*step)?;
+                    match step {
+                        Step::Tick => self.ticked = true,
+                        Step::Tock | Step::TickTock => {
+                            self.ticked = false;
+                            self.time += 1;
+                        }
+                        Step::Eval | Step::VmStep => {}
+                    }
+                }
+                Command::Output => {
+                    let line = self
+                        .columns
+                        .iter()
+                        .map(|column| self.cell(column))
+                        .collect::<Result<String, String>>()?
+                        + "|";
+                    self.emit(line)?;
+                }
+                Command::Echo(message) => self.echo = Some(message.clone()),
+                Command::ClearEcho => self.echo = None,
+                Command::Repeat(n, body) => {
+                    for _ in 0..*n {
+                        self.execute(body)?;
+                    }
+                }
+                Command::While(condition, body) => loop {
+                    let value = self.simulator.get(&condition.variable)?;
+                    let holds = match condition.comparison {
+                        Comparison::Eq => value == condition.value,
+                        Comparison::Ne => value != condition.value,
+                        Comparison::Lt => value < condition.value,
+                        Comparison::Le => value <= condition.value,
+                        Comparison::Gt => value > condition.value,
+                        Comparison::Ge => value >= condition.value,
+                    };
+                    if !holds {
+                        break;
+                    }
+                    self.execute(body)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `line` to the output and checks it against the compare file.
+    fn emit(&mut self, line: String) -> Result<(), TestError> {
+        self.output.push(line);
+        let index = self.output.len() - 1;
+        let actual = &self.output[index];
+        let expected = match &self.compare {
+            Some(compare) => compare.get(index).map(|s| s.as_str()).unwrap_or(""),
+            None => return Ok(()),
+        };
+        if actual == expected {
+            return Ok(());
+        }
+        let column = actual
+            .split('|')
+            .zip(expected.split('|'))
+            .position(|(a, e)| a != e)
+            .unwrap_or_else(|| actual.split('|').count().min(expected.split('|').count()));
+        Err(TestError::Mismatch {
+            line: index + 1,
+            column: column.max(1),
+            expected: expected.to_string(),
+            actual: actual.clone(),
+        })
+    }
+
+    fn cell(&self, column: &OutputColumn) -> Result<String, String> {
+        let text = if column.variable == "time" {
+            format!("{}{}", self.time, if self.ticked { "+" } else { "" })
+        } else {
+            let value = self.simulator.get(&column.variable)?;
+            format_value(value, column.format, column.len)
+        };
+        let text = match column.format {
+            Format::String => format!("{:<w$}", text, w = column.len),
+            _ => format!("{:>w$}", text, w = column.len),
+        };
+        Ok(format!(
+            "|{}{}{}",
+            " ".repeat(column.pad_left),
+            text,
+            " ".repeat(column.pad_right)
+        ))
+    }
+}
+
+/// The simulator a script is written for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Cpu,
+    Vm,
+    Hardware,
+}
+
+/// The simulator `commands` are written for, from the first file they `load`: `.hdl` files
+/// are for the hardware simulator, `.vm` files and directories for the VM emulator, and
+/// programs for the CPU emulator. Scripts loading nothing are for the VM emulator if they
+/// `vmstep`.
+pub fn target(commands: &[Command]) -> Target {
+    fn first_load(commands: &[Command]) -> Option<Option<&str>> {
+        commands.iter().find_map(|command| match command {
+            Command::Load(name) => Some(name.as_deref()),
+            Command::Repeat(_, body) | Command::While(_, body) => first_load(body),
+            _ => None,
+        })
+    }
+    fn steps_vm(commands: &[Command]) -> bool {
+        commands.iter().any(|command| match command {
+            Command::Step(step) => *step == Step::VmStep,
+            Command::Repeat(_, body) | Command::While(_, body) => steps_vm(body),
+            _ => false,
+        })
+    }
+    match first_load(commands) {
+        Some(Some(name)) => match Path::new(name).extension().and_then(|e| e.to_str()) {
+            Some("hdl") => Target::Hardware,
+            Some("hack") | Some("asm") => Target::Cpu,
+            _ => Target::Vm,
+        },
+        Some(None) => Target::Vm,
+        None if steps_vm(commands) => Target::Vm,
+        None => Target::Cpu,
+    }
+}
+
+/// Runs the `.tst` file at `tst_path` on `simulator`.
+pub fn run_file<S: Simulator>(tst_path: &str, simulator: S) -> Result<Runner<S>, TestError> {
+    let commands = parse_file(tst_path)?;
+    let dir = Path::new(tst_path)
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+    let mut runner = Runner::new(simulator, dir);
+    runner.run(&commands)?;
+    Ok(runner)
+}
+
+/// Splits a variable like `RAM[256]` into `("RAM", 256)`.
+pub fn parse_indexed(variable: &str) -> Option<(&str, usize)> {
+    let open = variable.find('[')?;
+    if !variable.ends_with(']') {
+        return None;
+    }
+    let index = variable[open + 1..variable.len() - 1].parse().ok()?;
+    Some((&variable[..open], index))
+}
+
+fn format_value(value: i32, format: Format, len: usize) -> String {
+    let mask = |bits: usize| {
+        if bits >= 32 {
+            u32::MAX
+        } else {
+            (1 << bits) - 1
+        }
+    };
+    match format {
+        Format::Binary => format!("{:0w$b}", value as u32 & mask(len), w = len),
+        Format::Hex => format!("{:0w$X}", value as u32 & mask(4 * len), w = len),
+        Format::Decimal | Format::String => value.to_string(),
+    }
+}
+
+fn header(column: &OutputColumn) -> String {
+    let space = column.pad_left + column.len + column.pad_right;
+    let name: String = column.variable.chars().take(space).collect();
+    let left = (space - name.len()) / 2;
+    format!(
+        "|{}{}{}",
+        " ".repeat(left),
+        name,
+        " ".repeat(space - left - name.len())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu_emulator::machine::Machine;
+
+    #[test]
+    fn first_mismatch() {
+        let dir = std::env::temp_dir().join(format!("nand2tetris-tst-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let tst = "load Add.hack,
+output-file Add.out,
+compare-to Add.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2;
+set RAM[1] 5,
+output;
+repeat 6 {
+    ticktock;
+}
+output;
+output;
+";
+        // The third line expects RAM[1] to be 4; the fourth is wrong too, but never reached.
+        let cmp = "|  RAM[0]  |  RAM[1]  |
+|       0  |       5  |
+|       5  |       4  |
+|       6  |       4  |
+";
+        fs::write(dir.join("Add.tst"), tst).unwrap();
+        fs::write(dir.join("Add.cmp"), cmp).unwrap();
+        fs::write(
+            dir.join("Add.hack"),
+            include_str!("../../projects/06/add/Add.hack"),
+        )
+        .unwrap();
+        let tst_path = dir.join("Add.tst").to_string_lossy().to_string();
+        let error = run_file(&tst_path, Machine::new(vec![]).unwrap()).err();
+        let mismatch = TestError::Mismatch {
+            line: 3,
+            column: 2,
+            expected: "|       5  |       4  |".to_string(),
+            actual: "|       5  |       5  |".to_string(),
+        };
+        assert_eq!(error, Some(mismatch));
+        // The output stops at the first mismatch, which it includes.
+        let out = fs::read_to_string(dir.join("Add.out")).unwrap();
+        assert_eq!(
+            out,
+            "|  RAM[0]  |  RAM[1]  |\n|       0  |       5  |\n|       5  |       5  |\n"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use crate::test_script::parser::Step;
+use crate::test_script::runner::{parse_indexed, Simulator};
+use crate::vm_emulator::vm::{Vm, ARG, LCL, SP, THAT, THIS};
+
+/// Runs scripts written for the VM emulator: `load` takes a `.vm` file or, bare, the
+/// script's directory, and `vmstep` executes one command.
+impl Simulator for Vm {
+    fn load(&mut self, path: &Path) -> Result<(), String> {
+        *self = Vm::load(path)?;
+        Ok(())
+    }
+
+    fn get(&self, variable: &str) -> Result<i32, String> {
+        let address = variable_address(self, variable)?;
+        Ok(self.ram()[address] as i16 as i32)
+    }
+
+    fn set(&mut self, variable: &str, value: i32) -> Result<(), String> {
+        let address = variable_address(self, variable)?;
+        self.ram_mut()[address] = value as u16;
+        Ok(())
+    }
+
+    fn step(&mut self, step: Step) -> Result<(), String> {
+        match step {
+            Step::VmStep => Vm::step(self),
+            _ => Err(format!(
+                "`{}` is not supported by the VM emulator.",
+                format!("{:?}", step).to_lowercase()
+            )),
+        }
+    }
+}
+
+/// RAM address of a script variable: `sp`, `local`, `argument`, `this`, `that`,
+/// `RAM[i]`, `temp[i]` or `local[i]` and the like.
+fn variable_address(vm: &Vm, variable: &str) -> Result<usize, String> {
+    let address = match variable {
+        "sp" => Some(SP),
+        "local" => Some(LCL),
+        "argument" => Some(ARG),
+        "this" => Some(THIS),
+        "that" => Some(THAT),
+        _ => match parse_indexed(variable) {
+            Some(("RAM", i)) => Some(i),
+            Some(("temp", i)) if i < 8 => Some(5 + i),
+            Some(("pointer", i)) if i < 2 => Some(THIS + i),
+            Some(("local", i)) => Some(vm.ram()[LCL] as usize + i),
+            Some(("argument", i)) => Some(vm.ram()[ARG] as usize + i),
+            Some(("this", i)) => Some(vm.ram()[THIS] as usize + i),
+            Some(("that", i)) => Some(vm.ram()[THAT] as usize + i),
+            _ => None,
+        },
+    };
+    match address {
+        Some(address) if address < vm.ram().len() => Ok(address),
+        _ => Err(format!("unknown variable `{}`.", variable)),
+    }
+}
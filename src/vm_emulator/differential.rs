@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::assembler::parser::Parser;
+use crate::assembler::writer::Writer as Assembler;
+use crate::cpu_emulator::machine::Machine;
+use crate::source_map::VmLocation;
+use crate::vm_emulator::program::{Instruction, Program};
+use crate::vm_emulator::vm::{Vm, LCL, SP};
+use crate::vm_translator::code_writer::Writer;
+
+/// Hack instructions a single VM command may take before the run is considered stuck.
+const MAX_CYCLES_PER_COMMAND: u64 = 1 << 20;
+
+/// RAM set up by `Options::default`, matching the `.tst` scripts of project 07.
+pub const DEFAULT_RAM: [(usize, u16); 5] = [(0, 256), (1, 300), (2, 400), (3, 3000), (4, 3010)];
+
+/// How the program is translated and run.
+pub struct Options {
+    /// Translate with the bootstrap code and start the VM with `Vm::bootstrap`.
+    pub init: bool,
+    pub opt_level: u8,
+    pub shared_routines: bool,
+    /// RAM contents before the first command, for both machines.
+    pub ram: Vec<(usize, u16)>,
+    /// VM commands to run before giving up on reaching the end of the program.
+    pub max_steps: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            init: false,
+            opt_level: 0,
+            shared_routines: false,
+            ram: DEFAULT_RAM.to_vec(),
+            max_steps: 1_000_000,
+        }
+    }
+}
+
+/// Where the VM emulator and the translated code stopped agreeing.
+#[derive(Debug)]
+pub struct Divergence {
+    /// The last VM command executed before the machines were compared, `None` if the program
+    /// could not be run at all.
+    pub command: Option<VmLocation>,
+    /// The VM command after which they last agreed.
+    pub last_agreed: Option<VmLocation>,
+    pub message: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.command {
+            Some(command) => write!(
+                f,
+                "diverged at `{}` ({}:{}): {}",
+                command.command, command.file, command.line, self.message
+            )?,
+            None => write!(f, "{}", self.message)?,
+        }
+        if let Some(last_agreed) = &self.last_agreed {
+            write!(
+                f,
+                "\nlast agreed after `{}` ({}:{})",
+                last_agreed.command, last_agreed.file, last_agreed.line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `vm_sources`, pairs of a file name and the text of a `.vm` file, both on the VM
+/// emulator and, translated and assembled, on the CPU emulator, and compares the RAM of the two.
+///
+/// The machines are compared after every VM command, or with `opt_level` 2, where the peephole
+/// optimizer moves work across commands, only after each `return` and at the end. Returns the
+/// number of VM commands run.
+pub fn compare(vm_sources: &[(&str, &str)], options: &Options) -> Result<u64, Box<Divergence>> {
+    let setup_error = |message: String| {
+        Box::new(Divergence {
+            command: None,
+            last_agreed: None,
+            message,
+        })
+    };
+    let program = Program::new(vm_sources).map_err(setup_error)?;
+
+    let mut writer = Writer::from_writer(vec![]);
+    writer.set_opt_level(options.opt_level);
+    writer.set_shared_routines(options.shared_routines);
+    if options.init {
+        writer.write_init();
+    }
+    for (vm_path, vm) in vm_sources {
+        writer
+            .write_parser(vm_path, vm.parse().unwrap())
+            .map_err(setup_error)?;
+    }
+    writer.flush();
+    let vm_locations = writer.source_map().to_vec();
+    let asm = String::from_utf8(writer.into_inner()).unwrap();
+    let (words, lines) = Assembler::assemble_with_lines(Parser::from_str("<translated>", &asm))
+        .map_err(|errors| {
+            setup_error(
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            )
+        })?;
+    let start_rom = start_addresses(&program, &words, &lines, &vm_locations);
+    let end = words.len();
+
+    let mut vm = Vm::new(program);
+    let mut machine = Machine::new(words).map_err(setup_error)?;
+    vm.set_pc(0);
+    for &(address, value) in &options.ram {
+        vm.ram_mut()[address] = value;
+        machine.ram_mut()[address] = value;
+    }
+    let every_command = options.opt_level < 2;
+    let mut last_agreed = None;
+    let mut last_step = 0;
+    if options.init {
+        vm.bootstrap().map_err(setup_error)?;
+        run_to(
+            &mut machine,
+            start_rom[vm.pc()],
+            end,
+            true,
+            MAX_CYCLES_PER_COMMAND,
+        )
+        .map_err(setup_error)?;
+    }
+
+    while !vm.is_finished() && vm.steps() < options.max_steps {
+        let pc = vm.pc();
+        let command = vm.current().cloned();
+        let divergence = |message| {
+            Box::new(Divergence {
+                command: command.clone(),
+                last_agreed: last_agreed.clone(),
+                message,
+            })
+        };
+        let instruction = vm.program().instructions()[pc].clone();
+        vm.step()
+            .map_err(|e| divergence(format!("VM error: {}", e)))?;
+        let is_return = instruction == Instruction::Return;
+        if !every_command && !is_return && !vm.is_finished() {
+            continue;
+        }
+        // Commands that emit no code, such as labels, leave the CPU where it is.
+        let has_code = start_rom[pc] != start_rom[pc + 1] || is_return;
+        let max_cycles = MAX_CYCLES_PER_COMMAND * (vm.steps() - last_step);
+        // A `return` to an address past the program ends it on both machines.
+        let target = start_rom.get(vm.pc()).copied().unwrap_or(end);
+        run_to(&mut machine, target, end, has_code, max_cycles)
+            .map_err(|e| divergence(format!("Hack: {}", e)))?;
+        compare_ram(&vm, &machine, is_return || vm.is_finished()).map_err(divergence)?;
+        last_agreed = command;
+        last_step = vm.steps();
+    }
+    Ok(vm.steps())
+}
+
+/// ROM address where the code of each VM command starts, plus the end of the program.
+fn start_addresses(
+    program: &Program,
+    words: &[u16],
+    lines: &[usize],
+    vm_locations: &[Option<VmLocation>],
+) -> Vec<usize> {
+    let n = program.instructions().len();
+    let index: HashMap<(&str, usize), usize> = (0..n)
+        .map(|i| program.source(i).unwrap())
+        .enumerate()
+        .map(|(i, source)| ((source.file.as_str(), source.line), i))
+        .collect();
+    let mut start_rom = vec![words.len(); n + 1];
+    let mut next = 0;
+    for (rom, &line) in lines.iter().enumerate() {
+        let owner = vm_locations
+            .get(line - 1)
+            .and_then(|location| location.as_ref())
+            .and_then(|location| index.get(&(location.file.as_str(), location.line)));
+        if let Some(&owner) = owner {
+            while next <= owner {
+                start_rom[next] = rom;
+                next += 1;
+            }
+        }
+    }
+    start_rom
+}
+
+/// Steps the CPU until PC is `target`, at least once if `must_move`. With `target` at `end`,
+/// the end of the program, any address past it will do.
+fn run_to(
+    machine: &mut Machine,
+    target: usize,
+    end: usize,
+    must_move: bool,
+    max_cycles: u64,
+) -> Result<(), String> {
+    let start = machine.cycles();
+    let reached = |pc: usize| pc == target || (target == end && pc > end);
+    if must_move || !reached(machine.pc() as usize) {
+        loop {
+            machine.step()?;
+            if reached(machine.pc() as usize) {
+                break;
+            }
+            if machine.cycles() - start > max_cycles {
+                return Err(format!("PC did not reach ROM address {}.", target));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compares the pointers, `temp`, `static` and the stack below SP, skipping the return addresses
+/// saved in call frames, which are command indices for the VM and ROM addresses for the CPU.
+/// With `heap`, also compares RAM from 2048 up to the keyboard.
+fn compare_ram(vm: &Vm, machine: &Machine, heap: bool) -> Result<(), String> {
+    let (expected, actual) = (vm.ram(), machine.ram());
+    let sp = expected[SP] as usize;
+    let mut return_slots = vec![];
+    let mut lcl = expected[LCL] as usize;
+    while lcl >= 256 + 5 && lcl <= sp {
+        return_slots.push(lcl - 5);
+        let saved = expected[lcl - 4] as usize;
+        if saved >= lcl {
+            break;
+        }
+        lcl = saved;
+    }
+    let mut addresses: Vec<usize> = (0..13).chain(16..256).collect();
+    if sp > 256 && sp <= 2048 {
+        addresses.extend((256..sp).filter(|a| !return_slots.contains(a)));
+    }
+    if heap {
+        addresses.extend(2048..24576);
+    }
+    for address in addresses {
+        if expected[address] != actual[address] {
+            return Err(format!(
+                "RAM[{}] is {} on the VM emulator but {} on the CPU.",
+                address, expected[address] as i16, actual[address] as i16
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Xorshift pseudo-random numbers, so that generated programs are reproducible from a seed.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Generates a random straight-line program of `len` commands over all segments, meant to be
+/// run with `DEFAULT_RAM`. The stack never underflows and `pointer` is only ever set to
+/// addresses in 3000..3100.
+pub fn random_program(rng: &mut Rng, len: usize) -> String {
+    const SEGMENTS: [(&str, usize); 7] = [
+        ("local", 8),
+        ("argument", 8),
+        ("this", 8),
+        ("that", 8),
+        ("static", 8),
+        ("temp", 8),
+        ("constant", 1 << 15),
+    ];
+    const ARITHMETIC: [&str; 9] = ["add", "sub", "neg", "eq", "gt", "lt", "and", "or", "not"];
+    let mut commands: Vec<String> = vec![];
+    let mut depth = 0;
+    while commands.len() < len {
+        let command = match rng.below(10) {
+            0..=3 => {
+                let (segment, size) = SEGMENTS[rng.below(SEGMENTS.len())];
+                let index = match rng.below(4) {
+                    // Small constants are the common case.
+                    0 if segment == "constant" => rng.below(3),
+                    _ => rng.below(size),
+                };
+                depth += 1;
+                format!("push {} {}", segment, index)
+            }
+            4 | 5 if depth > 0 => {
+                let (segment, size) = SEGMENTS[rng.below(SEGMENTS.len() - 1)];
+                depth -= 1;
+                format!("pop {} {}", segment, rng.below(size))
+            }
+            6 => {
+                commands.push(format!("push constant {}", 3000 + rng.below(100)));
+                format!("pop pointer {}", rng.below(2))
+            }
+            _ => {
+                let op = ARITHMETIC[rng.below(ARITHMETIC.len())];
+                let arity = if op == "neg" || op == "not" { 1 } else { 2 };
+                if depth < arity {
+                    continue;
+                }
+                depth -= arity - 1;
+                op.to_string()
+            }
+        };
+        commands.push(command);
+    }
+    commands.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compares `vm` at every optimization level, with and without shared routines.
+    fn compare_in_every_mode(vm: &str, what: &str) {
+        for opt_level in 0..=2 {
+            for &shared_routines in &[false, true] {
+                let options = Options {
+                    opt_level,
+                    shared_routines,
+                    ..Default::default()
+                };
+                if let Err(divergence) = compare(&[("Random.vm", vm)], &options) {
+                    panic!(
+                        "{}, --opt {}, shared: {}:\n{}\n{}",
+                        what, opt_level, shared_routines, vm, divergence
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_programs_agree_in_every_mode() {
+        for seed in 1..=50 {
+            let vm = random_program(&mut Rng::new(seed), 40);
+            compare_in_every_mode(&vm, &format!("seed {}", seed));
+        }
+    }
+
+    /// x - y overflows for each of these pairs: comparing through it alone gets them wrong.
+    #[test]
+    fn comparisons_do_not_overflow() {
+        let pairs = [
+            (-32767, 2),
+            (2, -32767),
+            (32767, -1),
+            (-1, 32767),
+            (-32768, 1),
+            (1, -32768),
+            (-32768, 32767),
+            (32767, -32768),
+            (0, -32768),
+            (-32768, 0),
+        ];
+        let mut vm = String::new();
+        for &(x, y) in &pairs {
+            for op in &["gt", "lt", "eq"] {
+                for &value in &[x, y] {
+                    // `push constant` takes 0..32767 only.
+                    match value {
+                        -32768 => vm += "push constant 32767\nnot\n",
+                        _ if value < 0 => vm += &format!("push constant {}\nneg\n", -value),
+                        _ => vm += &format!("push constant {}\n", value),
+                    }
+                }
+                vm += &format!("{}\n", op);
+            }
+        }
+        compare_in_every_mode(&vm, "overflowing comparisons");
+    }
+}
@@ -0,0 +1,3 @@
+pub mod differential;
+pub mod program;
+pub mod vm;
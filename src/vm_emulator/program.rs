@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::source_map::VmLocation;
+use crate::vm_translator::parser::{Command, Parser};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment {
+    Argument,
+    Local,
+    Static,
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+impl Segment {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "argument" => Some(Segment::Argument),
+            "local" => Some(Segment::Local),
+            "static" => Some(Segment::Static),
+            "constant" => Some(Segment::Constant),
+            "this" => Some(Segment::This),
+            "that" => Some(Segment::That),
+            "pointer" => Some(Segment::Pointer),
+            "temp" => Some(Segment::Temp),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticOp {
+    Add,
+    Sub,
+    Neg,
+    Eq,
+    Gt,
+    Lt,
+    And,
+    Or,
+    Not,
+}
+
+impl ArithmeticOp {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "add" => Some(ArithmeticOp::Add),
+            "sub" => Some(ArithmeticOp::Sub),
+            "neg" => Some(ArithmeticOp::Neg),
+            "eq" => Some(ArithmeticOp::Eq),
+            "gt" => Some(ArithmeticOp::Gt),
+            "lt" => Some(ArithmeticOp::Lt),
+            "and" => Some(ArithmeticOp::And),
+            "or" => Some(ArithmeticOp::Or),
+            "not" => Some(ArithmeticOp::Not),
+            _ => None,
+        }
+    }
+}
+
+/// A VM command with labels and function names resolved to instruction indices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    /// `static` indices are already RAM addresses.
+    Push(Segment, u16),
+    Pop(Segment, u16),
+    Arithmetic(ArithmeticOp),
+    /// Kept so that instruction indices match the commands of the source.
+    Label,
+    Goto(usize),
+    IfGoto(usize),
+    Function(usize),
+    Call(usize, usize),
+    Return,
+}
+
+/// A set of `.vm` files, ready to be executed by [`Vm`](crate::vm_emulator::vm::Vm).
+#[derive(Debug, Clone)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    sources: Vec<VmLocation>,
+    functions: HashMap<String, usize>,
+}
+
+impl Program {
+    /// Parses `vm_sources`, pairs of a file name and the text of a `.vm` file.
+    ///
+    /// Static variables get RAM addresses from 16 in order of first use, which is how the
+    /// assembler allocates the `Xxx.i` symbols of translated code.
+    pub fn new(vm_sources: &[(&str, &str)]) -> Result<Self, String> {
+        let mut commands = vec![];
+        let mut labels = HashMap::new();
+        let mut functions = HashMap::new();
+        for (vm_path, vm) in vm_sources {
+            let mut parser: Parser = vm.parse().unwrap();
+            while parser.has_more_commands() {
+                parser.advance();
+                let location = VmLocation {
+                    file: vm_path.to_string(),
+                    line: parser.line(),
+                    command: parser.code().to_string(),
+                };
+                let index = commands.len();
+                let duplicate = match parser.command_type() {
+                    Command::Label => {
+                        let label = format!(
+                            "{}${}",
+                            parser.current_function(),
+                            parser.arg1().map_err(|e| error(&location, &e))?
+                        );
+                        labels.insert(label, index).is_some()
+                    }
+                    Command::Function => {
+                        functions.insert(parser.current_function(), index).is_some()
+                    }
+                    _ => false,
+                };
+                if duplicate {
+                    return Err(error(
+                        &location,
+                        &format!("duplicate {}.", parser.command()),
+                    ));
+                }
+                commands.push((location, parser.command_type(), parser.current_function()));
+            }
+        }
+
+        let mut statics = HashMap::new();
+        let mut instructions = vec![];
+        let mut sources = vec![];
+        for (location, command_type, current_function) in commands {
+            let words: Vec<&str> = location.command.split_whitespace().collect();
+            let arg1 = || {
+                words
+                    .get(1)
+                    .copied()
+                    .ok_or_else(|| error(&location, "missing argument."))
+            };
+            let arg2 = || match words.get(2).map(|w| w.parse::<u16>()) {
+                Some(Ok(n)) => Ok(n),
+                Some(Err(_)) => Err(error(&location, "expected a number.")),
+                None => Err(error(&location, "missing argument.")),
+            };
+            let label = |label: &str| match labels.get(&format!("{}${}", current_function, label)) {
+                Some(&index) => Ok(index),
+                None => Err(error(&location, &format!("unknown label `{}`.", label))),
+            };
+            let instruction = match command_type {
+                Command::Push | Command::Pop => {
+                    let segment = Segment::from_name(arg1()?).ok_or_else(|| {
+                        error(&location, &format!("unknown segment `{}`.", words[1]))
+                    })?;
+                    let mut index = arg2()?;
+                    let in_range = match segment {
+                        Segment::Pointer => index < 2,
+                        Segment::Temp => index < 8,
+                        Segment::Constant => index < 1 << 15,
+                        _ => true,
+                    };
+                    if !in_range {
+                        return Err(error(&location, "index is out of range."));
+                    }
+                    if segment == Segment::Static {
+                        let name = format!("{}.{}", file_stem(&location.file), index);
+                        let n_statics = statics.len() as u16;
+                        index = *statics.entry(name).or_insert(16 + n_statics);
+                    }
+                    match command_type {
+                        Command::Push => Instruction::Push(segment, index),
+                        _ if segment == Segment::Constant => {
+                            return Err(error(&location, "cannot pop to `constant`."));
+                        }
+                        _ => Instruction::Pop(segment, index),
+                    }
+                }
+                Command::Arithmetic => match ArithmeticOp::from_name(words[0]) {
+                    Some(op) => Instruction::Arithmetic(op),
+                    None => {
+                        return Err(error(
+                            &location,
+                            &format!("unknown command `{}`.", words[0]),
+                        ))
+                    }
+                },
+                Command::Label => Instruction::Label,
+                Command::Goto => Instruction::Goto(label(arg1()?)?),
+                Command::If => Instruction::IfGoto(label(arg1()?)?),
+                Command::Function => Instruction::Function(arg2()? as usize),
+                Command::Call => match functions.get(arg1()?) {
+                    Some(&index) => Instruction::Call(index, arg2()? as usize),
+                    None => {
+                        return Err(error(
+                            &location,
+                            &format!("unknown function `{}`.", words[1]),
+                        ))
+                    }
+                },
+                Command::Return => Instruction::Return,
+            };
+            instructions.push(instruction);
+            sources.push(location);
+        }
+        Ok(Self {
+            instructions,
+            sources,
+            functions,
+        })
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Where the instruction at `index` came from.
+    pub fn source(&self, index: usize) -> Option<&VmLocation> {
+        self.sources.get(index)
+    }
+
+    /// Index of the `function` command of `name`.
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.get(name).copied()
+    }
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .to_string()
+}
+
+fn error(location: &VmLocation, message: &str) -> String {
+    format!(
+        "{}:{}: `{}`: {}",
+        location.file, location.line, location.command, message
+    )
+}
@@ -0,0 +1,257 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::cpu_emulator::machine::RAM_SIZE;
+use crate::source_map::VmLocation;
+use crate::vm_emulator::program::{ArithmeticOp, Instruction, Program, Segment};
+
+pub const SP: usize = 0;
+pub const LCL: usize = 1;
+pub const ARG: usize = 2;
+pub const THIS: usize = 3;
+pub const THAT: usize = 4;
+
+/// Executes VM commands on the RAM layout of the Hack platform, as translated code would.
+pub struct Vm {
+    program: Program,
+    ram: Vec<u16>,
+    pc: usize,
+    steps: u64,
+}
+
+impl Vm {
+    /// Creates a VM starting at `Sys.init` if the program has one, at its first command otherwise.
+    /// Like the VM emulator of the book, starting at `Sys.init` sets up no frame; see `bootstrap`.
+    pub fn new(program: Program) -> Self {
+        let pc = program.function("Sys.init").unwrap_or(0);
+        Self {
+            program,
+            ram: vec![0; RAM_SIZE],
+            pc,
+            steps: 0,
+        }
+    }
+
+    /// Creates a VM from `vm_sources`, pairs of a file name and the text of a `.vm` file.
+    pub fn from_sources(vm_sources: &[(&str, &str)]) -> Result<Self, String> {
+        Ok(Self::new(Program::new(vm_sources)?))
+    }
+
+    /// Creates a VM from a `.vm` file, or from all `.vm` files in a directory.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let paths = if path.is_dir() {
+            let mut paths: Vec<_> = path
+                .read_dir()
+                .map_err(|e| format!("{}: {}", path.display(), e))?
+                .filter_map(|x| x.ok().map(|dir_entry| dir_entry.path()))
+                .filter(|path| path.extension() == Some(OsStr::new("vm")))
+                .collect();
+            paths.sort();
+            paths
+        } else {
+            vec![path.to_path_buf()]
+        };
+        let mut vm_sources = vec![];
+        for path in paths {
+            let vm = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            vm_sources.push((path.to_string_lossy().to_string(), vm));
+        }
+        let vm_sources: Vec<(&str, &str)> = vm_sources
+            .iter()
+            .map(|(vm_path, vm)| (vm_path.as_str(), vm.as_str()))
+            .collect();
+        Self::from_sources(&vm_sources)
+    }
+
+    /// Does what the bootstrap code of `code_writer::Writer::write_init` does: SP = 256, then
+    /// `call Sys.init 0`. Returning from `Sys.init` ends the program.
+    pub fn bootstrap(&mut self) -> Result<(), String> {
+        let sys_init = self
+            .program
+            .function("Sys.init")
+            .ok_or("the program has no `Sys.init`.")?;
+        self.ram[SP] = 256;
+        self.pc = self.program.instructions().len();
+        self.call(sys_init, 0)
+    }
+
+    /// Whether the program counter is past the last command.
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.program.instructions().len()
+    }
+
+    /// Executes the command at PC.
+    pub fn step(&mut self) -> Result<(), String> {
+        let instruction = match self.program.instructions().get(self.pc) {
+            Some(instruction) => instruction.clone(),
+            None => return Err("the program has ended.".to_string()),
+        };
+        self.steps += 1;
+        self.pc += 1;
+        let result = match instruction {
+            Instruction::Push(segment, index) => match self.address(segment, index) {
+                Some(address) => self.read(address).and_then(|value| self.push(value)),
+                None => self.push(index),
+            },
+            Instruction::Pop(segment, index) => {
+                let address = self.address(segment, index).unwrap();
+                self.pop().and_then(|value| self.write(address, value))
+            }
+            Instruction::Arithmetic(op) => self.arithmetic(op),
+            Instruction::Label => Ok(()),
+            Instruction::Goto(target) => {
+                self.pc = target;
+                Ok(())
+            }
+            Instruction::IfGoto(target) => self.pop().map(|value| {
+                if value != 0 {
+                    self.pc = target;
+                }
+            }),
+            Instruction::Function(n_locals) => (0..n_locals).try_for_each(|_| self.push(0)),
+            Instruction::Call(function, n_args) => self.call(function, n_args),
+            Instruction::Return => self.ret(),
+        };
+        result.map_err(|e| match self.program.source(self.pc - 1) {
+            Some(source) => format!(
+                "{}:{}: `{}`: {}",
+                source.file, source.line, source.command, e
+            ),
+            None => e,
+        })
+    }
+
+    /// Executes `n` commands, stopping at the first error.
+    pub fn run(&mut self, n: usize) -> Result<(), String> {
+        for _ in 0..n {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    /// Index of the next command to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// The next command to execute.
+    pub fn current(&self) -> Option<&VmLocation> {
+        self.program.source(self.pc)
+    }
+
+    /// Number of commands executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// RAM address of `segment`[`index`], or `None` for `constant`.
+    fn address(&self, segment: Segment, index: u16) -> Option<usize> {
+        let base = match segment {
+            Segment::Constant => return None,
+            Segment::Static => return Some(index as usize),
+            Segment::Pointer => return Some(THIS + index as usize),
+            Segment::Temp => return Some(5 + index as usize),
+            Segment::Local => self.ram[LCL],
+            Segment::Argument => self.ram[ARG],
+            Segment::This => self.ram[THIS],
+            Segment::That => self.ram[THAT],
+        };
+        Some(base.wrapping_add(index) as usize)
+    }
+
+    fn read(&self, address: usize) -> Result<u16, String> {
+        match self.ram.get(address) {
+            Some(value) => Ok(*value),
+            None => Err(format!("RAM address {} is out of range.", address)),
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u16) -> Result<(), String> {
+        match self.ram.get_mut(address) {
+            Some(m) => {
+                *m = value;
+                Ok(())
+            }
+            None => Err(format!("RAM address {} is out of range.", address)),
+        }
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), String> {
+        let sp = self.ram[SP];
+        self.write(sp as usize, value)?;
+        self.ram[SP] = sp.wrapping_add(1);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u16, String> {
+        let sp = self.ram[SP].wrapping_sub(1);
+        self.ram[SP] = sp;
+        self.read(sp as usize)
+    }
+
+    fn arithmetic(&mut self, op: ArithmeticOp) -> Result<(), String> {
+        let y = self.pop()?;
+        let value = match op {
+            ArithmeticOp::Neg => y.wrapping_neg(),
+            ArithmeticOp::Not => !y,
+            _ => {
+                let x = self.pop()?;
+                let bool = |b: bool| if b { 0xffff } else { 0 };
+                match op {
+                    ArithmeticOp::Add => x.wrapping_add(y),
+                    ArithmeticOp::Sub => x.wrapping_sub(y),
+                    ArithmeticOp::Eq => bool(x == y),
+                    ArithmeticOp::Gt => bool((x as i16) > (y as i16)),
+                    ArithmeticOp::Lt => bool((x as i16) < (y as i16)),
+                    ArithmeticOp::And => x & y,
+                    ArithmeticOp::Or => x | y,
+                    ArithmeticOp::Neg | ArithmeticOp::Not => unreachable!(),
+                }
+            }
+        };
+        self.push(value)
+    }
+
+    /// Saves the frame of the caller, whose next command is at PC, and jumps to `function`.
+    fn call(&mut self, function: usize, n_args: usize) -> Result<(), String> {
+        self.push(self.pc as u16)?;
+        for pointer in &[LCL, ARG, THIS, THAT] {
+            self.push(self.ram[*pointer])?;
+        }
+        let sp = self.ram[SP];
+        self.ram[ARG] = sp.wrapping_sub(n_args as u16 + 5);
+        self.ram[LCL] = sp;
+        self.pc = function;
+        Ok(())
+    }
+
+    fn ret(&mut self) -> Result<(), String> {
+        let frame = self.ram[LCL];
+        let return_address = self.read(frame.wrapping_sub(5) as usize)?;
+        let value = self.pop()?;
+        self.write(self.ram[ARG] as usize, value)?;
+        self.ram[SP] = self.ram[ARG].wrapping_add(1);
+        for (i, pointer) in [THAT, THIS, ARG, LCL].iter().enumerate() {
+            self.ram[*pointer] = self.read(frame.wrapping_sub(i as u16 + 1) as usize)?;
+        }
+        self.pc = return_address as usize;
+        Ok(())
+    }
+}
@@ -4,29 +4,187 @@ use std::io;
 use std::io::prelude::*;
 use std::path::Path;
 
+use crate::source_map::VmLocation;
+use crate::vm_translator::optimizer::Peephole;
 use crate::vm_translator::parser::{Command, Parser};
 
-pub struct Writer {
+/// Beyond this index, `pop local i` and the like compute the address through R13 instead of
+/// stepping A up to it.
+const MAX_POP_STEPS: usize = 6;
+
+// R13 is used for temporal variable `FRAME`.
+// R14 is used for return address `RET`
+const RETURN: &str = concat!(
+    "@LCL\nD=M\n@R13\nM=D\n",                  // FRAME = LCL
+    "@5\nA=D-A\nD=M\n@R14\nM=D\n",             // RET = *(FRAME-5)
+    "@SP\nAM=M-1\nD=M\n@ARG\nA=M\nM=D\n",      // *ARG = pop()
+    "@ARG\nD=M+1\n@SP\nM=D\n",                 // SP = ARG+1
+    "@R13\nA=M-1\nD=M\n@THAT\nM=D\n",          // THAT = *(FRAME-1)
+    "@R13\nD=M\n@2\nA=D-A\nD=M\n@THIS\nM=D\n", // THIS = *(FRAME-2)
+    "@R13\nD=M\n@3\nA=D-A\nD=M\n@ARG\nM=D\n",  // ARG = *(FRAME-3)
+    "@R13\nD=M\n@4\nA=D-A\nD=M\n@LCL\nM=D\n",  // LCL = *(FRAME-4)
+    "@R14\nA=M\n0;JMP\n"                       // goto RET
+);
+
+/// Pops y and leaves in D a number with the sign of x - y, x being the new top of the stack:
+/// x - y itself when both have the same sign, as it cannot overflow then, and -1 or 1
+/// otherwise. A is left pointing at x. y is kept in R13 rather than read again from the
+/// stack, where the peephole optimizer may never have written it. Labels start with `prefix`.
+fn difference(prefix: &str) -> String {
+    format!(
+        concat!(
+            "@SP\nAM=M-1\nD=M\n@R13\nM=D\n@{p}.YNEG\nD;JLT\n", // R13 = y; y < 0?
+            "@SP\nA=M-1\nD=M\n@{p}.SUB\nD;JGE\nD=-1\n@{p}.END\n0;JMP\n", // x < 0 <= y
+            "({p}.YNEG)\n@SP\nA=M-1\nD=M\n@{p}.SUB\nD;JLT\nD=1\n@{p}.END\n0;JMP\n", // y < 0 <= x
+            "({p}.SUB)\n@R13\nD=M\n@SP\nA=M-1\nD=M-D\n",       // x - y
+            "({p}.END)\n@SP\nA=M-1\n"
+        ),
+        p = prefix
+    )
+}
+
+pub struct Writer<W: Write = io::BufWriter<File>> {
     vm_path: String,
-    writer: io::BufWriter<File>,
+    writer: W,
     n_eq: usize,
     n_gt: usize,
     n_lt: usize,
     n_call_func: HashMap<String, usize>,
+    /// VM command being translated, `None` outside of `write_parser`.
+    source: Option<VmLocation>,
+    /// VM command of each emitted asm line.
+    source_map: Vec<Option<VmLocation>>,
+    opt_level: u8,
+    peephole: Peephole,
+    shared_routines: bool,
+    routines_written: bool,
 }
 
 impl Writer {
     pub fn new(asm_path: &str) -> Self {
         let f = File::create(asm_path).unwrap();
         //let f = OpenOptions::new().write(true).append(true).truncate(false).create(true).open(asm_path).unwrap();
-        let writer = io::BufWriter::new(f);
+        Self::from_writer(io::BufWriter::new(f))
+    }
+}
+
+impl<W: Write> Writer<W> {
+    /// Creates a writer emitting assembly into `writer`.
+    pub fn from_writer(writer: W) -> Self {
         Self {
             vm_path: "init.vm".to_string(),
-            writer: writer,
+            writer,
             n_eq: 0,
             n_gt: 0,
             n_lt: 0,
             n_call_func: HashMap::new(),
+            source: None,
+            source_map: vec![],
+            opt_level: 0,
+            peephole: Peephole::new(),
+            shared_routines: false,
+            routines_written: false,
+        }
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> W {
+        self.flush();
+        self.writer
+    }
+
+    /// Sets how hard the output is optimized:
+    ///
+    /// - 0: every command is translated with the same generic template.
+    /// - 1: push/pop are specialized per segment and the stack is accessed without reloading SP.
+    /// - 2: additionally, the peephole optimizer fuses push/pop pairs and drops redundant
+    ///   `@SP` reloads across commands. The output is held back until `flush`.
+    pub fn set_opt_level(&mut self, opt_level: u8) {
+        self.opt_level = opt_level;
+    }
+
+    /// With `shared`, `call`, `return`, `eq`, `gt` and `lt` jump into routines emitted once
+    /// per program (`$$CALL`, `$$RETURN`, `$$EQ`, `$$GT` and `$$LT`) instead of being inlined,
+    /// trading a few cycles per command for far less ROM.
+    pub fn set_shared_routines(&mut self, shared: bool) {
+        self.shared_routines = shared;
+    }
+
+    /// Emits the shared routines the first time one is needed. They are jumped over, so this
+    /// works wherever in the program it happens.
+    fn write_routines(&mut self) {
+        if !self.shared_routines || self.routines_written {
+            return;
+        }
+        self.routines_written = true;
+        let source = self.source.take();
+        let push = self.push_d();
+        self.emit("// shared routines\n@$$ROUTINES.END\n0;JMP\n");
+        // D = return address, R13 = function address, R14 = number of arguments.
+        self.emit(&format!(
+            "{}{}{}{}{}{}{}{}",
+            format_args!("($$CALL)\n{}", push), // push return-address
+            format_args!("@LCL\nD=M\n{}", push), // push LCL
+            format_args!("@ARG\nD=M\n{}", push), // push ARG
+            format_args!("@THIS\nD=M\n{}", push), // push THIS
+            format_args!("@THAT\nD=M\n{}", push), // push THAT
+            "@SP\nD=M\n@R14\nD=D-M\n@5\nD=D-A\n@ARG\nM=D\n", // ARG = SP-n-5
+            "@SP\nD=M\n@LCL\nM=D\n",            // LCL = SP
+            "@R13\nA=M\n0;JMP\n"                // goto f
+        ));
+        self.emit("($$RETURN)\n");
+        self.emit(RETURN);
+        // D = return address, kept in R15.
+        for (name, jump) in [("EQ", "JEQ"), ("GT", "JGT"), ("LT", "JLT")].iter() {
+            let difference = match *name {
+                "EQ" => "@SP\nAM=M-1\nD=M\nA=A-1\nD=M-D\n".to_string(),
+                _ => difference(&format!("$${}", name)),
+            };
+            self.emit(&format!(
+                "($${name})\n@R15\nM=D\n{difference}M=-1\n@$${name}.TRUE\nD;{jump}\n@SP\nA=M-1\nM=0\n($${name}.TRUE)\n@R15\nA=M\n0;JMP\n",
+                name = name,
+                difference = difference,
+                jump = jump
+            ));
+        }
+        self.emit("($$ROUTINES.END)\n");
+        self.source = source;
+    }
+
+    /// Writes out everything emitted so far.
+    pub fn flush(&mut self) {
+        for (line, source) in self.peephole.drain() {
+            self.writer.write_all(line.as_bytes()).unwrap();
+            self.writer.write_all(b"\n").unwrap();
+            self.source_map.push(source);
+        }
+        self.writer.flush().unwrap();
+    }
+
+    /// The VM command each asm line written so far came from, indexed by 0-based line number.
+    /// Lines written outside of `write`/`write_parser`, such as the bootstrap code, map to `None`.
+    pub fn source_map(&self) -> &[Option<VmLocation>] {
+        &self.source_map
+    }
+
+    fn emit(&mut self, code: &str) {
+        if self.opt_level >= 2 {
+            for line in code.lines() {
+                self.peephole.push(line, self.source.clone());
+            }
+            return;
+        }
+        self.writer.write_all(code.as_bytes()).unwrap();
+        let n_lines = self.source_map.len() + code.matches('\n').count();
+        self.source_map.resize(n_lines, self.source.clone());
+    }
+
+    /// Pushes D onto the stack.
+    fn push_d(&self) -> &'static str {
+        if self.opt_level == 0 {
+            "@SP\nA=M\nM=D\n@SP\nM=M+1\n"
+        } else {
+            "@SP\nM=M+1\nA=M-1\nM=D\n"
         }
     }
 
@@ -36,52 +194,85 @@ impl Writer {
     }
 
     pub fn write_init(&mut self) {
-        self.writer
-            .write("@256\nD=A\n@SP\nM=D\n".as_bytes())
-            .unwrap();
+        self.emit("@256\nD=A\n@SP\nM=D\n");
         self.write_call("Sys.init".to_string(), 0);
     }
 
     pub fn write_arithmetic(&mut self, command: String) -> Result<(), String> {
-        let code = match command.as_str() {
-            "add" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=D+M\n@SP\nM=M+1\n".to_string()),
-            "sub" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=M-D\n@SP\nM=M+1\n".to_string()),
-            "neg" => Some("@SP\nAM=M-1\nM=-M\n@SP\nM=M+1\n".to_string()),
-            "eq" => {
-                self.n_eq += 1;
-                Some(format!("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nD=M-D\n@EQ.IF.{}\nD;JEQ\nD=0\n@EQ.ENDIF.{}\n0;JMP\n(EQ.IF.{})\nD=-1\n(EQ.ENDIF.{})\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", self.n_eq, self.n_eq, self.n_eq, self.n_eq))
-            }
-            "gt" => {
-                self.n_gt += 1;
-                Some(format!("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nD=M-D\n@GT.IF.{}\nD;JGT\nD=0\n@GT.ENDIF.{}\n0;JMP\n(GT.IF.{})\nD=-1\n(GT.ENDIF.{})\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", self.n_gt, self.n_gt, self.n_gt, self.n_gt))
-            }
-            "lt" => {
-                self.n_lt += 1;
-                Some(format!("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nD=M-D\n@LT.IF.{}\nD;JLT\nD=0\n@LT.ENDIF.{}\n0;JMP\n(LT.IF.{})\nD=-1\n(LT.ENDIF.{})\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", self.n_lt, self.n_lt, self.n_lt, self.n_lt))
+        if self.shared_routines && ["eq", "gt", "lt"].contains(&command.as_str()) {
+            self.write_routines();
+            let n = match command.as_str() {
+                "eq" => &mut self.n_eq,
+                "gt" => &mut self.n_gt,
+                _ => &mut self.n_lt,
+            };
+            *n += 1;
+            let return_address = format!("{}.RETURN.{}", command.to_uppercase(), n);
+            self.emit(&format!("// {}\n", command));
+            self.emit(&format!(
+                "@{}\nD=A\n@$${}\n0;JMP\n({})\n",
+                return_address,
+                command.to_uppercase(),
+                return_address
+            ));
+            return Ok(());
+        }
+        let code = if self.opt_level > 0 {
+            self.specialized_arithmetic(&command)
+        } else {
+            match command.as_str() {
+                "add" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=D+M\n@SP\nM=M+1\n".to_string()),
+                "sub" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=M-D\n@SP\nM=M+1\n".to_string()),
+                "neg" => Some("@SP\nAM=M-1\nM=-M\n@SP\nM=M+1\n".to_string()),
+                "eq" => {
+                    self.n_eq += 1;
+                    Some(format!("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nD=M-D\n@EQ.IF.{}\nD;JEQ\nD=0\n@EQ.ENDIF.{}\n0;JMP\n(EQ.IF.{})\nD=-1\n(EQ.ENDIF.{})\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", self.n_eq, self.n_eq, self.n_eq, self.n_eq))
+                }
+                "gt" => {
+                    self.n_gt += 1;
+                    Some(format!("{}@SP\nM=M-1\n@GT.IF.{}\nD;JGT\nD=0\n@GT.ENDIF.{}\n0;JMP\n(GT.IF.{})\nD=-1\n(GT.ENDIF.{})\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", difference(&format!("GT.{}", self.n_gt)), self.n_gt, self.n_gt, self.n_gt, self.n_gt))
+                }
+                "lt" => {
+                    self.n_lt += 1;
+                    Some(format!("{}@SP\nM=M-1\n@LT.IF.{}\nD;JLT\nD=0\n@LT.ENDIF.{}\n0;JMP\n(LT.IF.{})\nD=-1\n(LT.ENDIF.{})\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", difference(&format!("LT.{}", self.n_lt)), self.n_lt, self.n_lt, self.n_lt, self.n_lt))
+                }
+                "and" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=D&M\n@SP\nM=M+1\n".to_string()),
+                "or" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=D|M\n@SP\nM=M+1\n".to_string()),
+                "not" => Some("@SP\nAM=M-1\nM=!M\n@SP\nM=M+1\n".to_string()),
+                _ => None,
             }
-            "and" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=D&M\n@SP\nM=M+1\n".to_string()),
-            "or" => Some("@SP\nAM=M-1\nD=M\n@SP\nAM=M-1\nM=D|M\n@SP\nM=M+1\n".to_string()),
-            "not" => Some("@SP\nAM=M-1\nM=!M\n@SP\nM=M+1\n".to_string()),
-            _ => None,
         };
-        self.writer
-            .write(format!("// {}\n", command).as_bytes())
-            .unwrap();
+        self.emit(&format!("// {}\n", command));
         match code {
             Some(code) => {
-                self.writer.write(code.as_bytes()).unwrap();
+                self.emit(&code);
                 Ok(())
             }
             None => Err(format!("Undefined command `{}`.", command)),
         }
     }
 
+    #[allow(clippy::manual_map)]
     pub fn write_push_pop(
         &mut self,
         command: String,
         segment: String,
         index: usize,
     ) -> Result<(), String> {
+        if self.opt_level > 0 {
+            let code = self.specialized_push_pop(&command, &segment, index);
+            self.emit(&format!("// {} {} {}\n", command, segment, index));
+            return match code {
+                Some(code) => {
+                    self.emit(&code);
+                    Ok(())
+                }
+                None => Err(format!(
+                    "Undefined command `{} {} {}`.",
+                    command, segment, index
+                )),
+            };
+        }
         // R13 is used for temporal register containing address to be referenced.
         // R14 is used for `static` or `const` variable.
         let address = match segment.as_str() {
@@ -113,12 +304,10 @@ impl Writer {
             },
             _ => None,
         };
-        self.writer
-            .write(format!("// {} {} {}\n", command, segment, index).as_bytes())
-            .unwrap();
+        self.emit(&format!("// {} {} {}\n", command, segment, index));
         match code {
             Some(code) => {
-                self.writer.write(code.as_bytes()).unwrap();
+                self.emit(&code);
                 Ok(())
             }
             None => Err(format!(
@@ -128,169 +317,223 @@ impl Writer {
         }
     }
 
+    /// `write_arithmetic` for `opt_level` >= 1: binary operations pop one operand and
+    /// overwrite the other in place.
+    fn specialized_arithmetic(&mut self, command: &str) -> Option<String> {
+        let binary = |comp| format!("@SP\nAM=M-1\nD=M\nA=A-1\nM={}\n", comp);
+        let compare = |difference: String, label: String, jump| {
+            format!(
+                "{}M=-1\n@{}\n{}\n@SP\nA=M-1\nM=0\n({})\n",
+                difference, label, jump, label
+            )
+        };
+        match command {
+            "add" => Some(binary("D+M")),
+            "sub" => Some(binary("M-D")),
+            "and" => Some(binary("D&M")),
+            "or" => Some(binary("D|M")),
+            "neg" => Some("@SP\nA=M-1\nM=-M\n".to_string()),
+            "not" => Some("@SP\nA=M-1\nM=!M\n".to_string()),
+            "eq" => {
+                self.n_eq += 1;
+                let difference = "@SP\nAM=M-1\nD=M\nA=A-1\nD=M-D\n".to_string();
+                Some(compare(difference, format!("EQ.IF.{}", self.n_eq), "D;JEQ"))
+            }
+            "gt" => {
+                self.n_gt += 1;
+                let difference = difference(&format!("GT.{}", self.n_gt));
+                Some(compare(difference, format!("GT.IF.{}", self.n_gt), "D;JGT"))
+            }
+            "lt" => {
+                self.n_lt += 1;
+                let difference = difference(&format!("LT.{}", self.n_lt));
+                Some(compare(difference, format!("LT.IF.{}", self.n_lt), "D;JLT"))
+            }
+            _ => None,
+        }
+    }
+
+    /// `write_push_pop` for `opt_level` >= 1: values go through D alone wherever the address
+    /// is known without arithmetic.
+    fn specialized_push_pop(&self, command: &str, segment: &str, index: usize) -> Option<String> {
+        let base = match segment {
+            "local" => Some("LCL"),
+            "argument" => Some("ARG"),
+            "this" => Some("THIS"),
+            "that" => Some("THAT"),
+            _ => None,
+        };
+        let address = match segment {
+            "static" => Some(format!("{}.{}", self.set_file_name(), index)),
+            "pointer" => Some((3 + index).to_string()),
+            "temp" => Some((5 + index).to_string()),
+            _ => None,
+        };
+        match (command, base, address) {
+            ("push", _, _) if segment == "constant" => {
+                let load = match index {
+                    0 | 1 => format!("D={}\n", index),
+                    _ => format!("@{}\nD=A\n", index),
+                };
+                Some(format!("{}{}", load, self.push_d()))
+            }
+            ("push", Some(base), _) => {
+                let load = match index {
+                    0 => format!("@{}\nA=M\nD=M\n", base),
+                    1 => format!("@{}\nA=M+1\nD=M\n", base),
+                    _ => format!("@{}\nD=A\n@{}\nA=D+M\nD=M\n", index, base),
+                };
+                Some(format!("{}{}", load, self.push_d()))
+            }
+            ("push", None, Some(address)) => Some(format!("@{}\nD=M\n{}", address, self.push_d())),
+            ("pop", Some(base), _) if index > MAX_POP_STEPS => Some(format!(
+                "@{}\nD=A\n@{}\nD=D+M\n@R13\nM=D\n@SP\nAM=M-1\nD=M\n@R13\nA=M\nM=D\n",
+                index, base
+            )),
+            ("pop", Some(base), _) => {
+                let address = match index {
+                    0 => format!("@{}\nA=M\n", base),
+                    _ => format!("@{}\nA=M+1\n{}", base, "A=A+1\n".repeat(index - 1)),
+                };
+                Some(format!("@SP\nAM=M-1\nD=M\n{}M=D\n", address))
+            }
+            ("pop", None, Some(address)) => Some(format!("@SP\nAM=M-1\nD=M\n@{}\nM=D\n", address)),
+            _ => None,
+        }
+    }
+
     pub fn write_label(&mut self, label: String) {
         let label = format!("({})\n", label);
-        self.writer
-            .write(format!("// label {}", label).as_bytes())
-            .unwrap();
-        self.writer.write(label.as_bytes()).unwrap();
+        self.emit(&format!("// label {}", label));
+        self.emit(&label);
     }
 
     pub fn write_goto(&mut self, label: String) {
-        self.writer
-            .write(format!("// goto {}\n", label).as_bytes())
-            .unwrap();
+        self.emit(&format!("// goto {}\n", label));
 
-        self.writer
-            .write(format!("@{}\n0;JMP\n", label).as_bytes())
-            .unwrap();
+        self.emit(&format!("@{}\n0;JMP\n", label));
     }
 
     pub fn write_if(&mut self, label: String) {
-        self.writer
-            .write(format!("// if-goto {}\n", label).as_bytes())
-            .unwrap();
+        self.emit(&format!("// if-goto {}\n", label));
 
-        self.writer
-            .write(format!("@SP\nAM=M-1\nD=M\n@{}\nD;JNE\n", label).as_bytes())
-            .unwrap();
+        self.emit(&format!("@SP\nAM=M-1\nD=M\n@{}\nD;JNE\n", label));
     }
 
     pub fn write_call(&mut self, function_name: String, num_args: usize) {
-        self.writer
-            .write(format!("// call {} {}\n", function_name, num_args).as_bytes())
-            .unwrap();
+        self.write_routines();
+        self.emit(&format!("// call {} {}\n", function_name, num_args));
 
         // let file_name = self.set_file_name();
         let n_call = self.n_call_func.entry(function_name.clone()).or_insert(0);
         *n_call += 1;
         // let return_address = format!("RETURN.{}.{}_at_{}", function_name, *n_call, file_name);
         let return_address = format!("RETURN.{}.{}", function_name, *n_call);
-        self.writer
-            .write(
-                format!(
-                    "{}{}{}{}{}{}{}{}{}",
-                    format!("@{}\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n", return_address), // push return-address
-                    "@LCL\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n",                         // push LCL
-                    "@ARG\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n",                         // push ARG
-                    "@THIS\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n",                        // push THIS
-                    "@THAT\nD=M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n",                        // push THAT
-                    format!("@SP\nD=M\n@{}\nD=D-A\n@5\nD=D-A\n@ARG\nM=D\n", num_args), // ARG = SP-n-5
-                    "@SP\nD=M\n@LCL\nM=D\n",                                           // LCL = SP
-                    format!("@{}\n0;JMP\n", function_name),                            // goto f
-                    format!("({})\n", return_address) // (return-address)
-                )
-                .as_bytes(),
-            )
-            .unwrap();
+        if self.shared_routines {
+            self.emit(&format!(
+                "@{}\nD=A\n@R14\nM=D\n@{}\nD=A\n@R13\nM=D\n@{}\nD=A\n@$$CALL\n0;JMP\n({})\n",
+                num_args, function_name, return_address, return_address
+            ));
+            return;
+        }
+        let push = self.push_d();
+        self.emit(&format!(
+            "{}{}{}{}{}{}{}{}{}",
+            format_args!("@{}\nD=A\n{}", return_address, push), // push return-address
+            format_args!("@LCL\nD=M\n{}", push),                // push LCL
+            format_args!("@ARG\nD=M\n{}", push),                // push ARG
+            format_args!("@THIS\nD=M\n{}", push),               // push THIS
+            format_args!("@THAT\nD=M\n{}", push),               // push THAT
+            format_args!("@SP\nD=M\n@{}\nD=D-A\n@5\nD=D-A\n@ARG\nM=D\n", num_args), // ARG = SP-n-5
+            "@SP\nD=M\n@LCL\nM=D\n",                            // LCL = SP
+            format_args!("@{}\n0;JMP\n", function_name),        // goto f
+            format_args!("({})\n", return_address)              // (return-address)
+        ));
     }
 
     pub fn write_return(&mut self) {
-        self.writer.write("// return\n".as_bytes()).unwrap();
-
-        // R13 is used for temporal variable `FRAME`.
-        // R14 is used for return address `RET`
-        self.writer
-            .write(
-                format!(
-                    "{}{}{}{}{}{}{}{}{}",
-                    "@LCL\nD=M\n@R13\nM=D\n",                  // FRAME = LCL
-                    "@5\nA=D-A\nD=M\n@R14\nM=D\n",             // RET = *(FRAME-5)
-                    "@SP\nAM=M-1\nD=M\n@ARG\nA=M\nM=D\n",      // *ARG = pop()
-                    "@ARG\nD=M+1\n@SP\nM=D\n",                 // SP = ARG+1
-                    "@R13\nA=M-1\nD=M\n@THAT\nM=D\n",          // THAT = *(FRAME-1)
-                    "@R13\nD=M\n@2\nA=D-A\nD=M\n@THIS\nM=D\n", // THIS = *(FRAME-2)
-                    "@R13\nD=M\n@3\nA=D-A\nD=M\n@ARG\nM=D\n",  // ARG = *(FRAME-3)
-                    "@R13\nD=M\n@4\nA=D-A\nD=M\n@LCL\nM=D\n",  // LCL = *(FRAME-4)
-                    "@R14\nA=M\n0;JMP\n"                       // goto RET
-                )
-                .as_bytes(),
-            )
-            .unwrap();
+        self.write_routines();
+        self.emit("// return\n");
+        if self.shared_routines {
+            self.emit("@$$RETURN\n0;JMP\n");
+        } else {
+            self.emit(RETURN);
+        }
     }
 
     pub fn write_function(&mut self, function_name: String, num_locals: usize) {
-        self.writer
-            .write(format!("// function {} {}\n", function_name, num_locals).as_bytes())
-            .unwrap();
+        self.emit(&format!("// function {} {}\n", function_name, num_locals));
         let mut repeated_code = "".to_string();
-        (0..num_locals).for_each(|_| repeated_code += "@0\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n");
-        self.writer
-            .write(
-                format!(
-                    "{}{}",
-                    format!("({})\n", function_name), // (f)
-                    repeated_code                     // repeat k times: push 0
-                )
-                .as_bytes(),
-            )
-            .unwrap();
+        if self.opt_level > 0 && num_locals > 0 {
+            // Zero the locals in a row and bump SP once.
+            repeated_code = format!(
+                "@SP\nA=M\n{}D=A\n@SP\nM=D\n",
+                "M=0\nA=A+1\n".repeat(num_locals)
+            );
+        } else {
+            (0..num_locals).for_each(|_| repeated_code += "@0\nD=A\n@SP\nA=M\nM=D\n@SP\nM=M+1\n");
+        }
+        self.emit(&format!(
+            "{}{}",
+            format_args!("({})\n", function_name), // (f)
+            repeated_code                          // repeat k times: push 0
+        ));
+    }
+
+    /// Translates the `.vm` file at `vm_path`.
+    pub fn write(&mut self, vm_path: &str) -> Result<(), String> {
+        let parser = Parser::new(vm_path).map_err(|e| format!("{}: {}", vm_path, e))?;
+        self.write_parser(vm_path, parser)
     }
 
-    pub fn write(&mut self, vm_path: &str) {
+    /// Translates the commands of `parser`; `vm_path` names the file for `static` variables.
+    /// Stops at the first malformed command, reported with its file and line.
+    pub fn write_parser(&mut self, vm_path: &str, mut parser: Parser) -> Result<(), String> {
         self.vm_path = vm_path.to_string();
-        let mut parser = Parser::new(vm_path).unwrap();
-        loop {
-            if !parser.has_more_commands() {
-                break;
-            }
+        while parser.has_more_commands() {
             parser.advance();
-            match parser.command_type() {
-                Command::Push => {
-                    self.write_push_pop(
-                        "push".to_string(),
-                        parser.arg1().unwrap(),
-                        parser.arg2().unwrap().parse().unwrap(),
-                    )
-                    .unwrap();
-                }
-                Command::Pop => {
-                    self.write_push_pop(
-                        "pop".to_string(),
-                        parser.arg1().unwrap(),
-                        parser.arg2().unwrap().parse().unwrap(),
-                    )
-                    .unwrap();
-                }
-                Command::Arithmetic => {
-                    self.write_arithmetic(parser.command()).unwrap();
-                }
-                Command::Label => {
-                    self.write_label(format!(
-                        "{}${}",
-                        parser.current_function(),
-                        parser.arg1().unwrap()
-                    ));
-                }
-                Command::Goto => {
-                    self.write_goto(format!(
-                        "{}${}",
-                        parser.current_function(),
-                        parser.arg1().unwrap()
-                    ));
-                }
-                Command::If => {
-                    self.write_if(format!(
-                        "{}${}",
-                        parser.current_function(),
-                        parser.arg1().unwrap()
-                    ));
-                }
-                Command::Call => {
-                    self.write_call(
-                        parser.arg1().unwrap(),
-                        parser.arg2().unwrap().parse().unwrap(),
-                    );
-                }
-                Command::Return => {
-                    self.write_return();
-                }
-                Command::Function => {
-                    self.write_function(
-                        parser.arg1().unwrap(),
-                        parser.arg2().unwrap().parse().unwrap(),
-                    );
-                }
+            self.source = Some(VmLocation {
+                file: vm_path.to_string(),
+                line: parser.line(),
+                command: parser.code().to_string(),
+            });
+            self.write_command(&parser)
+                .map_err(|e| format!("{}:{}: {}", vm_path, parser.line(), e))?;
+        }
+        self.source = None;
+        Ok(())
+    }
+
+    fn write_command(&mut self, parser: &Parser) -> Result<(), String> {
+        match parser.command_type() {
+            Command::Push => {
+                self.write_push_pop("push".to_string(), parser.arg1()?, parser.index()?)
+            }
+            Command::Pop => self.write_push_pop("pop".to_string(), parser.arg1()?, parser.index()?),
+            Command::Arithmetic => self.write_arithmetic(parser.command()),
+            Command::Label => {
+                self.write_label(format!("{}${}", parser.current_function(), parser.arg1()?));
+                Ok(())
+            }
+            Command::Goto => {
+                self.write_goto(format!("{}${}", parser.current_function(), parser.arg1()?));
+                Ok(())
+            }
+            Command::If => {
+                self.write_if(format!("{}${}", parser.current_function(), parser.arg1()?));
+                Ok(())
+            }
+            Command::Call => {
+                self.write_call(parser.arg1()?, parser.index()?);
+                Ok(())
+            }
+            Command::Return => {
+                self.write_return();
+                Ok(())
+            }
+            Command::Function => {
+                self.write_function(parser.arg1()?, parser.index()?);
+                Ok(())
             }
         }
     }
@@ -1,2 +1,30 @@
-pub mod parser;
 pub mod code_writer;
+pub mod optimizer;
+pub mod parser;
+#[cfg(test)]
+mod tests;
+
+use crate::vm_translator::code_writer::Writer;
+
+/// Translates `vm_sources`, pairs of a file name and the text of a `.vm` file, into assembly.
+/// With `init`, the bootstrap code that sets SP and calls `Sys.init` comes first.
+/// See `Writer::set_opt_level` for `opt_level` and `Writer::set_shared_routines` for
+/// `shared_routines`. Fails on the first malformed command.
+pub fn translate(
+    vm_sources: &[(&str, &str)],
+    init: bool,
+    opt_level: u8,
+    shared_routines: bool,
+) -> Result<String, String> {
+    let mut writer = Writer::from_writer(vec![]);
+    writer.set_opt_level(opt_level);
+    writer.set_shared_routines(shared_routines);
+    if init {
+        writer.write_init();
+    }
+    for (vm_path, vm) in vm_sources {
+        writer.write_parser(vm_path, vm.parse().unwrap())?;
+    }
+    // The writer only ever writes ASCII assembly.
+    Ok(String::from_utf8(writer.into_inner()).unwrap())
+}
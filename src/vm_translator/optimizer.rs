@@ -0,0 +1,172 @@
+use crate::source_map::VmLocation;
+
+/// A line of a rewrite rule. Patterns ending in `*` match any line with that prefix.
+enum Repl {
+    Line(&'static str),
+    /// The matched line at this index.
+    Keep(usize),
+}
+
+/// Rewrite rules, each replacing a run of consecutive instructions with a shorter,
+/// equivalent one. Every rule leaves A, D, SP and the stack below SP as they were.
+const RULES: [(&[&str], &[Repl]); 6] = [
+    // push D, then pop into D: only A = SP remains.
+    (
+        &["@SP", "M=M+1", "A=M-1", "M=D", "@SP", "AM=M-1", "D=M"],
+        &[Repl::Line("@SP"), Repl::Line("A=M")],
+    ),
+    (
+        &["@SP", "A=M", "A=A-1"],
+        &[Repl::Keep(0), Repl::Line("A=M-1")],
+    ),
+    // A is already SP-1.
+    (
+        &["@SP", "AM=M-1", "D=M", "A=A-1", "M=*", "@SP", "A=M-1"],
+        &[
+            Repl::Keep(0),
+            Repl::Keep(1),
+            Repl::Keep(2),
+            Repl::Keep(3),
+            Repl::Keep(4),
+        ],
+    ),
+    (
+        &["@SP", "A=M-1", "M=*", "@SP", "A=M-1"],
+        &[Repl::Keep(0), Repl::Keep(1), Repl::Keep(2)],
+    ),
+    // A is overwritten before being used.
+    (&["A=M", "@*"], &[Repl::Keep(1)]),
+    (&["@*", "@*"], &[Repl::Keep(1)]),
+];
+
+/// Longest pattern in `RULES`.
+const WINDOW: usize = 7;
+
+/// Peephole optimizer over assembly lines, applying `RULES` as lines come in.
+///
+/// Comment lines are skipped over when matching; labels are not, so no rule applies
+/// across a jump target.
+#[derive(Default)]
+pub struct Peephole {
+    lines: Vec<(String, Option<VmLocation>)>,
+}
+
+impl Peephole {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `line`, which came from the VM command `source`.
+    pub fn push(&mut self, line: &str, source: Option<VmLocation>) {
+        self.lines.push((line.to_string(), source));
+        while self.rewrite() {}
+    }
+
+    /// Takes the optimized lines out.
+    pub fn drain(&mut self) -> Vec<(String, Option<VmLocation>)> {
+        std::mem::take(&mut self.lines)
+    }
+
+    /// Applies the first rule matching the last instructions, if any.
+    fn rewrite(&mut self) -> bool {
+        let mut tail: Vec<usize> = (0..self.lines.len())
+            .rev()
+            .filter(|&i| !self.lines[i].0.starts_with("//"))
+            .take(WINDOW)
+            .collect();
+        tail.reverse();
+        for (pattern, replacement) in RULES.iter() {
+            if pattern.len() > tail.len() {
+                continue;
+            }
+            let matched = &tail[tail.len() - pattern.len()..];
+            let is_match = pattern.iter().zip(matched).all(|(p, &i)| {
+                let line = &self.lines[i].0;
+                match p.strip_suffix('*') {
+                    Some(prefix) => line.starts_with(prefix),
+                    None => line == p,
+                }
+            });
+            if !is_match {
+                continue;
+            }
+            // Rewritten lines belong to the last command involved.
+            let source = self.lines[*matched.last().unwrap()].1.clone();
+            let new_lines: Vec<_> = replacement
+                .iter()
+                .map(|r| match r {
+                    Repl::Line(line) => (line.to_string(), source.clone()),
+                    Repl::Keep(k) => self.lines[matched[*k]].clone(),
+                })
+                .collect();
+            for &i in matched.iter().rev() {
+                self.lines.remove(i);
+            }
+            self.lines.extend(new_lines);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines left after pushing `lines` one by one through the optimizer.
+    fn optimize(lines: &[&str]) -> Vec<String> {
+        let mut peephole = Peephole::new();
+        for line in lines {
+            peephole.push(line, None);
+        }
+        peephole.drain().into_iter().map(|(line, _)| line).collect()
+    }
+
+    #[test]
+    fn fuses_push_d_and_pop_d() {
+        assert_eq!(
+            optimize(&["@SP", "M=M+1", "A=M-1", "M=D", "@SP", "AM=M-1", "D=M"]),
+            ["@SP", "A=M"]
+        );
+    }
+
+    #[test]
+    fn loads_sp_minus_one_directly() {
+        assert_eq!(optimize(&["@SP", "A=M", "A=A-1"]), ["@SP", "A=M-1"]);
+    }
+
+    #[test]
+    fn keeps_a_after_binary_operation() {
+        assert_eq!(
+            optimize(&["@SP", "AM=M-1", "D=M", "A=A-1", "M=D+M", "@SP", "A=M-1"]),
+            ["@SP", "AM=M-1", "D=M", "A=A-1", "M=D+M"]
+        );
+    }
+
+    #[test]
+    fn keeps_a_after_unary_operation() {
+        assert_eq!(
+            optimize(&["@SP", "A=M-1", "M=-M", "@SP", "A=M-1"]),
+            ["@SP", "A=M-1", "M=-M"]
+        );
+    }
+
+    #[test]
+    fn drops_a_load_overwritten_by_a_instruction() {
+        assert_eq!(optimize(&["D=M", "A=M", "@R13"]), ["D=M", "@R13"]);
+    }
+
+    #[test]
+    fn drops_a_instruction_overwritten_by_another() {
+        assert_eq!(optimize(&["D=M", "@5", "@R13"]), ["D=M", "@R13"]);
+    }
+
+    #[test]
+    fn matches_across_comments_but_not_labels() {
+        assert_eq!(
+            optimize(&["@5", "// pop temp 0", "@R13"]),
+            ["// pop temp 0", "@R13"]
+        );
+        assert_eq!(optimize(&["@5", "(L)", "@R13"]), ["@5", "(L)", "@R13"]);
+    }
+}
@@ -1,6 +1,8 @@
+use std::convert::Infallible;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub enum Command {
@@ -16,39 +18,56 @@ pub enum Command {
 }
 
 pub struct Parser {
-    vm: Vec<String>,
+    /// 1-based line number and text of each command.
+    vm: Vec<(usize, String)>,
     current: usize,
+    line: usize,
     code: String,
     current_function: String,
 }
 
-impl Parser {
-    pub fn new(vm_path: &str) -> io::Result<Parser> {
-        let f = File::open(vm_path)?;
-        let reader = io::BufReader::new(f);
-        let vm: Vec<String> = reader
+impl FromStr for Parser {
+    type Err = Infallible;
+
+    /// Parses the text of a `.vm` file.
+    fn from_str(vm: &str) -> Result<Self, Self::Err> {
+        let vm: Vec<(usize, String)> = vm
             .lines()
-            .filter_map(|l| {
-                let mut line = l.unwrap();
-                line = line.trim().to_string();
+            .enumerate()
+            .filter_map(|(i, l)| {
+                let mut line = l.trim().to_string();
                 line = match line.find("//") {
-                    Some(index) => (&line[..index]).trim().to_string(),
+                    Some(index) => line[..index].trim().to_string(),
                     None => line,
                 };
-                if line.len() > 0 {
-                    Some(line)
+                if !line.is_empty() {
+                    Some((i + 1, line))
                 } else {
                     None
                 }
             })
             .collect();
         Ok(Parser {
-            vm: vm,
+            vm,
             current: 0,
+            line: 0,
             code: "".to_string(),
             current_function: "".to_string(),
         })
     }
+}
+
+impl Parser {
+    pub fn new(vm_path: &str) -> io::Result<Parser> {
+        let f = File::open(vm_path)?;
+        Self::from_reader(f)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Parser> {
+        let mut vm = String::new();
+        reader.read_to_string(&mut vm)?;
+        Ok(vm.parse().unwrap())
+    }
 
     pub fn has_more_commands(&self) -> bool {
         self.current < self.vm.len()
@@ -56,13 +75,12 @@ impl Parser {
 
     pub fn advance(&mut self) {
         assert!(self.has_more_commands());
-        self.code = self.vm[self.current].clone();
+        let (line, code) = self.vm[self.current].clone();
+        self.line = line;
+        self.code = code;
         self.current += 1;
-        match self.command_type() {
-            Command::Function => {
-                self.current_function = self.arg1().unwrap();
-            },
-            _ => {},
+        if let (Command::Function, Ok(function)) = (self.command_type(), self.arg1()) {
+            self.current_function = function;
         }
     }
 
@@ -84,21 +102,55 @@ impl Parser {
     pub fn arg1(&self) -> Result<String, String> {
         match self.command_type() {
             Command::Return => Err("Command type should not be C_RETURN.".to_string()),
-            _ => Ok(self.code.split_whitespace().nth(1).unwrap().to_string()),
+            _ => self
+                .code
+                .split_whitespace()
+                .nth(1)
+                .map(|arg| arg.to_string())
+                .ok_or_else(|| format!("`{}` is missing its first argument.", self.code)),
         }
     }
 
     pub fn arg2(&self) -> Result<String, String> {
         match self.command_type() {
-            Command::Push | Command::Pop | Command::Function | Command::Call => Ok(self.code.split_whitespace().nth(2).unwrap().to_string()),
-            _ => Err(format!("command type {:?} doesn't have two arguments.", self.command_type())),
+            Command::Push | Command::Pop | Command::Function | Command::Call => self
+                .code
+                .split_whitespace()
+                .nth(2)
+                .map(|arg| arg.to_string())
+                .ok_or_else(|| format!("`{}` is missing its second argument.", self.code)),
+            _ => Err(format!(
+                "command type {:?} doesn't have two arguments.",
+                self.command_type()
+            )),
         }
     }
 
+    /// The second argument, a segment index or a count.
+    pub fn index(&self) -> Result<usize, String> {
+        let arg2 = self.arg2()?;
+        arg2.parse().map_err(|_| {
+            format!(
+                "`{}` is not a non-negative number in `{}`.",
+                arg2, self.code
+            )
+        })
+    }
+
     pub fn command(&self) -> String {
         self.code.split_whitespace().nth(0).unwrap().to_string()
     }
 
+    /// 1-based line number of the current command.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The current command without comments.
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
     pub fn current_function(&self) -> String {
         self.current_function.clone()
     }
@@ -0,0 +1,480 @@
+//! The programs of projects 07 and 08, translated at every optimization level, with and
+//! without shared routines, must behave like the VM emulator and leave the RAM as the
+//! unoptimized translation does.
+
+use crate::assembler::parser::Parser;
+use crate::assembler::writer::Writer as Assembler;
+use crate::cpu_emulator::machine::Machine;
+use crate::vm_emulator::differential::{self, Options, DEFAULT_RAM};
+use crate::vm_translator::code_writer::Writer;
+use crate::vm_translator::translate;
+
+const SIMPLE_ADD: &str = "push constant 7
+push constant 8
+add
+";
+
+const STACK_TEST: &str = "push constant 17
+push constant 17
+eq
+push constant 17
+push constant 16
+eq
+push constant 16
+push constant 17
+eq
+push constant 892
+push constant 891
+lt
+push constant 891
+push constant 892
+lt
+push constant 891
+push constant 891
+lt
+push constant 32767
+push constant 32766
+gt
+push constant 32766
+push constant 32767
+gt
+push constant 32766
+push constant 32766
+gt
+push constant 57
+push constant 31
+push constant 53
+add
+push constant 112
+sub
+neg
+and
+push constant 82
+or
+not
+";
+
+const BASIC_TEST: &str = "push constant 10
+pop local 0
+push constant 21
+push constant 22
+pop argument 2
+pop argument 1
+push constant 36
+pop this 6
+push constant 42
+push constant 45
+pop that 5
+pop that 2
+push constant 510
+pop temp 6
+push local 0
+push that 5
+add
+push argument 1
+sub
+push this 6
+push this 6
+add
+sub
+push temp 6
+add
+";
+
+const POINTER_TEST: &str = "push constant 3030
+pop pointer 0
+push constant 3040
+pop pointer 1
+push constant 32
+pop this 2
+push constant 46
+pop that 6
+push pointer 0
+push pointer 1
+add
+push this 2
+sub
+push that 6
+add
+";
+
+const STATIC_TEST: &str = "push constant 111
+push constant 333
+push constant 888
+pop static 8
+pop static 3
+pop static 1
+push static 3
+push static 1
+sub
+push static 8
+add
+";
+
+const BASIC_LOOP: &str = "push constant 0
+pop local 0
+label LOOP_START
+push argument 0
+push local 0
+add
+pop local 0
+push argument 0
+push constant 1
+sub
+pop argument 0
+push argument 0
+if-goto LOOP_START
+push local 0
+";
+
+const SIMPLE_FUNCTION: &str = "function SimpleFunction.test 2
+push local 0
+push local 1
+add
+not
+push argument 0
+add
+push argument 1
+sub
+return
+";
+
+const FIBONACCI_SERIES: &str = "push argument 1
+pop pointer 1
+push constant 0
+pop that 0
+push constant 1
+pop that 1
+push argument 0
+push constant 2
+sub
+pop argument 0
+label MAIN_LOOP_START
+push argument 0
+if-goto COMPUTE_ELEMENT
+goto END_PROGRAM
+label COMPUTE_ELEMENT
+push that 0
+push that 1
+add
+pop that 2
+push pointer 1
+push constant 1
+add
+pop pointer 1
+push argument 0
+push constant 1
+sub
+pop argument 0
+goto MAIN_LOOP_START
+label END_PROGRAM
+";
+
+const FIBONACCI_MAIN: &str = "function Main.fibonacci 0
+push argument 0
+push constant 2
+lt
+if-goto IF_TRUE
+goto IF_FALSE
+label IF_TRUE
+push argument 0
+return
+label IF_FALSE
+push argument 0
+push constant 2
+sub
+call Main.fibonacci 1
+push argument 0
+push constant 1
+sub
+call Main.fibonacci 1
+add
+return
+";
+
+const FIBONACCI_SYS: &str = "function Sys.init 0
+push constant 4
+call Main.fibonacci 1
+label WHILE
+goto WHILE
+";
+
+const STATICS_CLASS1: &str = "function Class1.set 0
+push argument 0
+pop static 0
+push argument 1
+pop static 1
+push constant 0
+return
+function Class1.get 0
+push static 0
+push static 1
+sub
+return
+";
+
+const STATICS_CLASS2: &str = "function Class2.set 0
+push argument 0
+pop static 0
+push argument 1
+pop static 1
+push constant 0
+return
+function Class2.get 0
+push static 0
+push static 1
+sub
+return
+";
+
+const STATICS_SYS: &str = "function Sys.init 0
+push constant 6
+push constant 8
+call Class1.set 2
+pop temp 0
+push constant 23
+push constant 15
+call Class2.set 2
+pop temp 0
+call Class1.get 0
+call Class2.get 0
+label WHILE
+goto WHILE
+";
+
+const NESTED_CALL_SYS: &str = "function Sys.init 0
+push constant 4000
+pop pointer 0
+push constant 5000
+pop pointer 1
+call Sys.main 0
+pop temp 1
+label LOOP
+goto LOOP
+function Sys.main 5
+push constant 4001
+pop pointer 0
+push constant 5001
+pop pointer 1
+push constant 200
+pop local 1
+push constant 40
+pop local 2
+push constant 6
+pop local 3
+push constant 123
+call Sys.add12 1
+pop temp 0
+push local 0
+push local 1
+push local 2
+push local 3
+push local 4
+add
+add
+add
+add
+return
+function Sys.add12 0
+push constant 4002
+pop pointer 0
+push constant 5002
+pop pointer 1
+push argument 0
+push constant 12
+add
+return
+";
+
+/// `0;JMP`, which with the `@` before it pointing at itself ends a program in a tight loop.
+const JMP: u16 = 0b1110_1010_1000_0111;
+
+/// Hack instructions run before a program is considered stuck.
+const MAX_CYCLES: u64 = 1_000_000;
+
+/// Translates `vm_sources` and runs them on the CPU emulator with `ram` set, until they run
+/// off the end of the program or into a tight loop. Returns the RAM at that point.
+fn run(
+    vm_sources: &[(&str, &str)],
+    init: bool,
+    opt_level: u8,
+    shared_routines: bool,
+    ram: &[(usize, u16)],
+) -> Vec<u16> {
+    let mut writer = Writer::from_writer(vec![]);
+    writer.set_opt_level(opt_level);
+    writer.set_shared_routines(shared_routines);
+    if init {
+        writer.write_init();
+    }
+    for (vm_path, vm) in vm_sources {
+        writer.write_parser(vm_path, vm.parse().unwrap()).unwrap();
+    }
+    let asm = String::from_utf8(writer.into_inner()).unwrap();
+    let words = Assembler::assemble(Parser::from_str("<translated>", &asm)).unwrap();
+    let mut machine = Machine::new(words.clone()).unwrap();
+    for &(address, value) in ram {
+        machine.ram_mut()[address] = value;
+    }
+    loop {
+        let pc = machine.pc() as usize;
+        if pc >= words.len() || (words[pc] as usize == pc && words.get(pc + 1) == Some(&JMP)) {
+            return machine.ram().to_vec();
+        }
+        assert!(machine.cycles() < MAX_CYCLES, "program did not halt");
+        machine.step().unwrap();
+    }
+}
+
+/// Addresses whose values do not depend on how the program was translated: the pointers,
+/// `temp`, `static`, the stack below SP except the return addresses of call frames, which
+/// are ROM addresses, and the heap.
+fn observable(ram: &[u16]) -> Vec<usize> {
+    let sp = ram[0] as usize;
+    let mut return_slots = vec![];
+    let mut lcl = ram[1] as usize;
+    while lcl >= 256 + 5 && lcl <= sp {
+        return_slots.push(lcl - 5);
+        let saved = ram[lcl - 4] as usize;
+        if saved >= lcl {
+            break;
+        }
+        lcl = saved;
+    }
+    (0..13)
+        .chain(16..sp.min(2048))
+        .filter(|address| !return_slots.contains(address))
+        .chain(2048..24576)
+        .collect()
+}
+
+/// Checks `vm_sources` against the VM emulator and the unoptimized translation in every mode.
+fn check(vm_sources: &[(&str, &str)], init: bool, ram: &[(usize, u16)]) {
+    let expected = run(vm_sources, init, 0, false, ram);
+    for opt_level in 0..=2 {
+        for &shared_routines in &[false, true] {
+            let options = Options {
+                init,
+                opt_level,
+                shared_routines,
+                ram: ram.to_vec(),
+                max_steps: 10_000,
+            };
+            if let Err(divergence) = differential::compare(vm_sources, &options) {
+                panic!(
+                    "--opt {}, shared: {}: {}",
+                    opt_level, shared_routines, divergence
+                );
+            }
+            let actual = run(vm_sources, init, opt_level, shared_routines, ram);
+            for address in observable(&expected) {
+                assert_eq!(
+                    actual[address], expected[address],
+                    "RAM[{}] with --opt {}, shared: {}",
+                    address, opt_level, shared_routines
+                );
+            }
+        }
+    }
+}
+
+fn with_ram(extra: &[(usize, u16)]) -> Vec<(usize, u16)> {
+    DEFAULT_RAM.iter().chain(extra).copied().collect()
+}
+
+#[test]
+fn simple_add() {
+    check(&[("SimpleAdd.vm", SIMPLE_ADD)], false, &DEFAULT_RAM);
+}
+
+#[test]
+fn stack_test() {
+    check(&[("StackTest.vm", STACK_TEST)], false, &DEFAULT_RAM);
+}
+
+#[test]
+fn basic_test() {
+    check(&[("BasicTest.vm", BASIC_TEST)], false, &DEFAULT_RAM);
+}
+
+#[test]
+fn pointer_test() {
+    check(&[("PointerTest.vm", POINTER_TEST)], false, &DEFAULT_RAM);
+}
+
+#[test]
+fn static_test() {
+    check(&[("StaticTest.vm", STATIC_TEST)], false, &DEFAULT_RAM);
+}
+
+#[test]
+fn basic_loop() {
+    check(
+        &[("BasicLoop.vm", BASIC_LOOP)],
+        false,
+        &with_ram(&[(400, 3)]),
+    );
+}
+
+#[test]
+fn simple_function() {
+    // The frame of a caller with two arguments, returning to ROM[1000], past the program.
+    let ram = [
+        (0, 317),
+        (1, 317),
+        (2, 310),
+        (3, 3000),
+        (4, 4000),
+        (310, 1234),
+        (311, 37),
+        (312, 1000),
+        (313, 305),
+        (314, 300),
+        (315, 3010),
+        (316, 4010),
+    ];
+    let vm_sources = [("SimpleFunction.vm", SIMPLE_FUNCTION)];
+    check(&vm_sources, false, &ram);
+    let ram = run(&vm_sources, false, 0, false, &ram);
+    assert_eq!(ram[..5], [311, 305, 300, 3010, 4010]);
+    assert_eq!(ram[310], 1196);
+}
+
+#[test]
+fn fibonacci_series() {
+    let ram = with_ram(&[(400, 6), (401, 3000)]);
+    check(&[("FibonacciSeries.vm", FIBONACCI_SERIES)], false, &ram);
+}
+
+#[test]
+fn fibonacci_element() {
+    let vm_sources = [("Main.vm", FIBONACCI_MAIN), ("Sys.vm", FIBONACCI_SYS)];
+    check(&vm_sources, true, &[]);
+}
+
+#[test]
+fn statics_test() {
+    let vm_sources = [
+        ("Class1.vm", STATICS_CLASS1),
+        ("Class2.vm", STATICS_CLASS2),
+        ("Sys.vm", STATICS_SYS),
+    ];
+    check(&vm_sources, true, &[]);
+}
+
+#[test]
+fn nested_call() {
+    check(&[("Sys.vm", NESTED_CALL_SYS)], true, &[]);
+}
+
+#[test]
+fn translate_with_shared_routines() {
+    let vm_sources = [("Sys.vm", NESTED_CALL_SYS)];
+    for opt_level in 0..=2 {
+        let inlined = translate(&vm_sources, true, opt_level, false).unwrap();
+        let shared = translate(&vm_sources, true, opt_level, true).unwrap();
+        assert!(!inlined.contains("($$CALL)"), "--opt {}", opt_level);
+        assert!(shared.contains("($$CALL)\n"), "--opt {}", opt_level);
+        assert!(shared.contains("($$RETURN)\n"), "--opt {}", opt_level);
+    }
+}